@@ -0,0 +1,381 @@
+use rand::{rngs::StdRng, seq::IteratorRandom, Rng, SeedableRng};
+
+use crate::game::{check_winner, Board, Mark, Outcome, WinCondition};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Difficulty {
+    /// Plays a random free cell.
+    Easy,
+    /// Scores the free cells with [`heuristic_move`].
+    Medium,
+    /// Searches the game tree with minimax: all of it on 3×3 boards, a few moves ahead
+    /// on bigger ones.
+    Hard,
+}
+
+/// Weights used by the medium heuristic.
+pub const WIN_WEIGHT: i32 = 100;
+pub const BLOCK_WEIGHT: i32 = 50;
+pub const OPEN_LINE_WEIGHT: i32 = 2;
+pub const CENTER_WEIGHT: i32 = 3;
+
+/// The random number generator for the AI's next move: seeded from `seed` and the
+/// number of moves played so far, so that a seeded game replays the same way, or from
+/// entropy without a seed.
+pub fn move_rng(seed: Option<u64>, moves: usize) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(moves as u64)),
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// Picks a move for `mark`. Only the hard AI takes misère rules into account.
+pub fn ai_move(
+    board: &Board,
+    mark: Mark,
+    difficulty: Difficulty,
+    condition: WinCondition,
+    rng: &mut impl Rng,
+) -> Option<usize> {
+    match difficulty {
+        Difficulty::Easy => random_move(board, rng),
+        Difficulty::Medium => heuristic_move(board, mark, rng),
+        Difficulty::Hard => best_move(board, mark, condition),
+    }
+}
+
+pub fn random_move(board: &Board, rng: &mut impl Rng) -> Option<usize> {
+    board.empty_cells().choose(rng)
+}
+
+/// Wins if it can, blocks if it must, and otherwise prefers the cells that sit on the
+/// most lines still open for `mark`. Ties are broken randomly.
+pub fn heuristic_move(board: &Board, mark: Mark, rng: &mut impl Rng) -> Option<usize> {
+    let lines = board.lines();
+    let center = (board.size() % 2 == 1).then(|| board.size() * board.size() / 2);
+
+    let score = |cell: usize| {
+        let mut score = 0;
+        for line in lines.iter().filter(|line| line.contains(&cell)) {
+            let count = |m: Mark| line.iter().filter(|&&i| board.cells()[i] == Some(m)).count();
+            let (mine, theirs) = (count(mark), count(mark.other()));
+            if mine == line.len() - 1 {
+                score += WIN_WEIGHT;
+            } else if theirs == line.len() - 1 {
+                score += BLOCK_WEIGHT;
+            } else if theirs == 0 {
+                score += OPEN_LINE_WEIGHT * (mine as i32 + 1);
+            }
+        }
+        if Some(cell) == center {
+            score += CENTER_WEIGHT;
+        }
+        score
+    };
+
+    let scored: Vec<_> = board.empty_cells().map(|cell| (cell, score(cell))).collect();
+    let best = scored.iter().map(|&(_, score)| score).max()?;
+    scored
+        .into_iter()
+        .filter(|&(_, score)| score == best)
+        .map(|(cell, _)| cell)
+        .choose(rng)
+}
+
+/// The move with the best minimax score for `mark`, preferring faster wins.
+pub fn best_move(board: &Board, mark: Mark, condition: WinCondition) -> Option<usize> {
+    search(board, mark, condition).cell
+}
+
+/// The outcome of a [`search`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Search {
+    pub cell: Option<usize>,
+    /// The positions evaluated, `board` included.
+    pub positions: u64,
+}
+
+/// Finds the [`best_move`] and counts the positions it took, which learn mode shows.
+/// The first of equally good moves is played.
+pub fn search(board: &Board, mark: Mark, condition: WinCondition) -> Search {
+    let depth = cutoff_depth(board).saturating_sub(1);
+    let mut positions = 1;
+    let mut best = None;
+    let mut alpha = -i32::MAX;
+    for cell in board.empty_cells() {
+        let mut next = board.clone();
+        next.place(cell, mark).ok();
+        let score = -minimax(&next, mark.other(), condition, depth, -i32::MAX, -alpha, &mut positions);
+        if best.is_none() || score > alpha {
+            best = Some(cell);
+            alpha = score;
+        }
+    }
+    Search { cell: best, positions }
+}
+
+/// The score of a won game, before the bonus for winning sooner. Far above anything
+/// [`evaluate`] gives, which grows with the number of lines.
+const WIN_SCORE: i32 = i32::MAX / 2;
+
+/// How many moves ahead the hard AI looks. 3×3 boards are searched to the end; on bigger
+/// ones the full tree is out of reach, and the positions the search stops at are scored
+/// by [`evaluate`]. Short lines make for many more lines to score and fewer cutoffs, so
+/// boards with them are searched less deep to keep a move well under a second.
+fn cutoff_depth(board: &Board) -> u32 {
+    let short_lines = board.win_length() < board.size();
+    match board.size() {
+        0..=MAX_ASSESS_SIZE => u32::MAX,
+        4 => 5,
+        5 if short_lines => 3,
+        5 => 4,
+        6 => 3,
+        _ if short_lines => 2,
+        _ => 3,
+    }
+}
+
+/// The largest board [`assess`] searches; the full game tree of anything bigger takes
+/// too long to build on every move.
+pub const MAX_ASSESS_SIZE: usize = 3;
+
+/// How the game ends if both sides play perfectly from here, or `None` if the board is
+/// too big to tell.
+pub fn assess(board: &Board, to_move: Mark, condition: WinCondition) -> Option<Outcome> {
+    if board.size() > MAX_ASSESS_SIZE {
+        return None;
+    }
+    let outcome = match minimax(board, to_move, condition, u32::MAX, -i32::MAX, i32::MAX, &mut 0) {
+        score if score > 0 => Outcome::Win(to_move),
+        score if score < 0 => Outcome::Win(to_move.other()),
+        _ => Outcome::Draw,
+    };
+    Some(outcome)
+}
+
+/// Scores `board` from the point of view of `to_move`, positive if it wins, looking
+/// `depth` moves ahead. With alpha-beta pruning: once a move is found to score at least
+/// `beta`, which the opponent can avoid, the other moves aren't searched, and scores at
+/// most `alpha` are only bounds. Every position evaluated is counted in `positions`.
+fn minimax(
+    board: &Board,
+    to_move: Mark,
+    condition: WinCondition,
+    depth: u32,
+    mut alpha: i32,
+    beta: i32,
+    positions: &mut u64,
+) -> i32 {
+    *positions += 1;
+    let empty = board.empty_cells().count() as i32;
+    match check_winner(board, condition) {
+        Some(Outcome::Win(mark)) if mark == to_move => return WIN_SCORE + empty,
+        Some(Outcome::Win(_)) => return -(WIN_SCORE + empty),
+        Some(Outcome::Draw) => return 0,
+        None => {}
+    }
+    if depth == 0 {
+        return evaluate(board, to_move, condition);
+    }
+
+    let mut best = -i32::MAX;
+    for cell in board.empty_cells() {
+        let mut next = board.clone();
+        next.place(cell, to_move).ok();
+        let score = -minimax(&next, to_move.other(), condition, depth - 1, -beta, -alpha, positions);
+        best = best.max(score);
+        alpha = alpha.max(score);
+        if alpha >= beta {
+            break;
+        }
+    }
+    best
+}
+
+/// Scores a position the search stops at, from the point of view of `to_move`: each
+/// line only one side has marks on counts for that side, the more so the fuller it is.
+/// Under misère rules, filling lines is what the players avoid.
+fn evaluate(board: &Board, to_move: Mark, condition: WinCondition) -> i32 {
+    let score: i32 = board
+        .lines()
+        .iter()
+        .map(|line| {
+            let count = |m: Mark| line.iter().filter(|&&i| board.cells()[i] == Some(m)).count() as i32;
+            match (count(to_move), count(to_move.other())) {
+                (mine, 0) => mine * mine,
+                (0, theirs) => -theirs * theirs,
+                _ => 0,
+            }
+        })
+        .sum();
+    match condition {
+        WinCondition::Normal => score,
+        WinCondition::Misere => -score,
+    }
+}
+
+/// The largest board [`simulate`] plays out. The hard AI's depth-limited search keeps
+/// each move fast, but a whole game of them on the biggest boards takes seconds.
+pub const MAX_SIMULATE_SIZE: usize = 6;
+
+/// A game the hard AI played against itself, see [`simulate`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Simulation {
+    /// The cells played, in order, starting with `to_move`.
+    pub moves: Vec<usize>,
+    pub outcome: Outcome,
+    /// Whether both sides played perfectly, which the AI only does on boards it searches
+    /// to the end. The outcome is then the position's theoretical result.
+    pub exact: bool,
+}
+
+/// Plays the hard AI against itself on a copy of `board`, `to_move` first, until the
+/// game ends. `None` if the game is already over or the board is bigger than
+/// [`MAX_SIMULATE_SIZE`].
+pub fn simulate(board: &Board, to_move: Mark, condition: WinCondition) -> Option<Simulation> {
+    if board.size() > MAX_SIMULATE_SIZE || check_winner(board, condition).is_some() {
+        return None;
+    }
+    let mut board = board.clone();
+    let mut mark = to_move;
+    let mut moves = Vec::new();
+    let outcome = loop {
+        if let Some(outcome) = check_winner(&board, condition) {
+            break outcome;
+        }
+        let cell = best_move(&board, mark, condition)?;
+        board.place(cell, mark).ok()?;
+        moves.push(cell);
+        mark = mark.other();
+    };
+    Some(Simulation { moves, outcome, exact: board.size() <= MAX_ASSESS_SIZE })
+}
+
+/// How a move compares with the best move of its position, for reviewing a game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Annotation {
+    /// Keeps the best outcome the position allows.
+    Best,
+    /// Gives away a win or a draw.
+    Blunder,
+}
+
+impl Annotation {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Annotation::Best => "!",
+            Annotation::Blunder => "?",
+        }
+    }
+}
+
+/// Annotates the `moves` that led to `board`, the mark on each of their cells being
+/// the one that played it. `None` for boards too big to [`assess`].
+pub fn annotate(board: &Board, moves: &[usize], condition: WinCondition) -> Option<Vec<Annotation>> {
+    if board.size() > MAX_ASSESS_SIZE {
+        return None;
+    }
+    let mut position = board.clone();
+    for &cell in moves {
+        position.clear(cell);
+    }
+    let mut annotations = Vec::with_capacity(moves.len());
+    for &cell in moves {
+        let mark = board.cells()[cell]?;
+        let best = assess(&position, mark, condition)?;
+        position.place(cell, mark).ok()?;
+        let played = assess(&position, mark.other(), condition)?;
+        annotations.push(if played == best { Annotation::Best } else { Annotation::Blunder });
+    }
+    Some(annotations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// [`minimax`] without the pruning, counting the positions it evaluates.
+    fn unpruned(board: &Board, to_move: Mark, condition: WinCondition, depth: u32, positions: &mut u64) -> i32 {
+        *positions += 1;
+        let empty = board.empty_cells().count() as i32;
+        match check_winner(board, condition) {
+            Some(Outcome::Win(mark)) if mark == to_move => return WIN_SCORE + empty,
+            Some(Outcome::Win(_)) => return -(WIN_SCORE + empty),
+            Some(Outcome::Draw) => return 0,
+            None => {}
+        }
+        if depth == 0 {
+            return evaluate(board, to_move, condition);
+        }
+        let mut best = -i32::MAX;
+        for cell in board.empty_cells() {
+            let mut next = board.clone();
+            next.place(cell, to_move).ok();
+            best = best.max(-unpruned(&next, to_move.other(), condition, depth - 1, positions));
+        }
+        best
+    }
+
+    /// Whether the hard AI playing `ai` never loses from `board`, whatever the other side
+    /// plays.
+    fn never_loses(board: &Board, to_move: Mark, ai: Mark) -> bool {
+        match check_winner(board, WinCondition::Normal) {
+            Some(Outcome::Win(mark)) => return mark == ai,
+            Some(Outcome::Draw) => return true,
+            None => {}
+        }
+        let cells: Vec<_> = if to_move == ai {
+            best_move(board, ai, WinCondition::Normal).into_iter().collect()
+        } else {
+            board.empty_cells().collect()
+        };
+        cells.into_iter().all(|cell| {
+            let mut next = board.clone();
+            next.place(cell, to_move).unwrap();
+            never_loses(&next, to_move.other(), ai)
+        })
+    }
+
+    #[test]
+    fn hard_ai_never_loses_on_3x3() {
+        assert!(never_loses(&Board::new(3), Mark::X, Mark::X));
+        assert!(never_loses(&Board::new(3), Mark::X, Mark::O));
+    }
+
+    #[test]
+    fn pruning_visits_fewer_positions_on_big_boards() {
+        let mut board = Board::with_win_length(5, 4);
+        board.place(12, Mark::X).unwrap();
+        let depth = cutoff_depth(&board);
+        let mut positions = 1;
+        for cell in board.empty_cells() {
+            let mut next = board.clone();
+            next.place(cell, Mark::O).unwrap();
+            unpruned(&next, Mark::X, WinCondition::Normal, depth - 1, &mut positions);
+        }
+        let search = search(&board, Mark::O, WinCondition::Normal);
+        assert!(search.positions < positions, "{} positions with pruning, {} without", search.positions, positions);
+    }
+
+    #[test]
+    fn big_boards_are_searched_in_few_positions() {
+        for n in MAX_ASSESS_SIZE + 1..=8 {
+            for k in 3..=n {
+                let mut board = Board::with_win_length(n, k);
+                board.place(n * n / 2, Mark::X).unwrap();
+                let positions = search(&board, Mark::O, WinCondition::Normal).positions;
+                assert!(positions < 50_000, "{0}×{0} with {1} in a row took {2} positions", n, k, positions);
+            }
+        }
+    }
+
+    #[test]
+    fn pruning_visits_fewer_positions() {
+        let board = Board::new(3);
+        let mut positions = 0;
+        let score = unpruned(&board, Mark::X, WinCondition::Normal, u32::MAX, &mut positions);
+        let search = search(&board, Mark::X, WinCondition::Normal);
+        assert_eq!(score, 0, "3×3 is a draw");
+        assert!(search.positions < positions, "{} positions with pruning, {} without", search.positions, positions);
+    }
+}
@@ -0,0 +1,86 @@
+/// Splits command arguments on whitespace, keeping double-quoted parts together:
+/// `one "two three"` gives `["one", "two three"]`. Inside quotes, `\"` is a literal quote.
+pub fn tokenize(input: &str) -> Result<Vec<String>, UnclosedQuote> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_token = false;
+    let mut quoted = false;
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted => match chars.next() {
+                Some('"') => current.push('"'),
+                Some(other) => {
+                    current.push('\\');
+                    current.push(other);
+                }
+                None => current.push('\\'),
+            },
+            '"' => {
+                quoted = !quoted;
+                in_token = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_token {
+                    tokens.push(std::mem::take(&mut current));
+                    in_token = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_token = true;
+            }
+        }
+    }
+
+    if quoted {
+        return Err(UnclosedQuote);
+    }
+    if in_token {
+        tokens.push(current);
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnclosedQuote;
+
+impl std::fmt::Display for UnclosedQuote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "A quote is never closed")
+    }
+}
+
+impl std::error::Error for UnclosedQuote {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_parts_stay_together() {
+        assert_eq!(tokenize(r#"one "two three" four"#).unwrap(), ["one", "two three", "four"]);
+        assert_eq!(tokenize(r#""say \"hi\"""#).unwrap(), [r#"say "hi""#]);
+        assert_eq!(tokenize(r#"a"b c"d"#).unwrap(), ["ab cd"]);
+    }
+
+    #[test]
+    fn unquoted_arguments_split_on_whitespace() {
+        assert_eq!(tokenize("  5\t4 \n misere ").unwrap(), ["5", "4", "misere"]);
+        assert_eq!(tokenize(r"back\slash").unwrap(), [r"back\slash"]);
+    }
+
+    #[test]
+    fn empty_arguments() {
+        assert!(tokenize("").unwrap().is_empty());
+        assert!(tokenize("   ").unwrap().is_empty());
+        assert_eq!(tokenize(r#""" x"#).unwrap(), ["", "x"]);
+    }
+
+    #[test]
+    fn unclosed_quote_is_an_error() {
+        assert_eq!(tokenize(r#"one "two"#), Err(UnclosedQuote));
+        assert_eq!(tokenize(r#""\""#), Err(UnclosedQuote));
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use crate::{
+    history::{History, HistoryBackup},
+    settings::{Settings, SettingsBackup},
+    snapshots::{Snapshots, SnapshotsBackup},
+    stats::{Stats, StatsBackup},
+};
+
+/// The format `/backup` writes. `/restore` only reads backups of this version.
+pub const BACKUP_VERSION: u32 = 1;
+/// The largest file a bot may download from Telegram.
+pub const MAX_BACKUP_SIZE: u32 = 20 * 1024 * 1024;
+
+/// The stores the bot keeps in memory, saved by `/backup` for an admin to `/restore`
+/// after a restart. The chats' dialogues aren't in it: the storage can't list them, and
+/// it keeps them itself.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Backup {
+    pub version: u32,
+    /// Unix time in seconds.
+    pub created_at: u64,
+    pub stats: StatsBackup,
+    pub settings: SettingsBackup,
+    pub snapshots: SnapshotsBackup,
+    #[serde(default)]
+    pub history: HistoryBackup,
+}
+
+impl Backup {
+    pub fn new(stats: &Stats, settings: &Settings, snapshots: &Snapshots, history: &History, now: u64) -> Self {
+        Self {
+            version: BACKUP_VERSION,
+            created_at: now,
+            stats: stats.backup(),
+            settings: settings.backup(),
+            snapshots: snapshots.backup(),
+            history: history.backup(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackupError {
+    NotABackup,
+    /// A backup of another version, which is given.
+    Version(u32),
+    /// A backup of this version whose contents don't hold up, for the given reason.
+    Invalid(&'static str),
+}
+
+impl std::fmt::Display for BackupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackupError::NotABackup => write!(f, "That isn't a backup made with /backup"),
+            BackupError::Version(version) => {
+                write!(f, "That backup has version {}, I can only restore version {}", version, BACKUP_VERSION)
+            }
+            BackupError::Invalid(reason) => write!(f, "That backup can't be restored: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+/// Reads a backup and checks everything in it, so that restoring it can't fail halfway.
+pub fn parse_backup(bytes: &[u8]) -> Result<Backup, BackupError> {
+    // The version is read on its own first, so that a backup of another version isn't
+    // taken for a broken one.
+    #[derive(serde::Deserialize)]
+    struct Versioned {
+        version: u32,
+    }
+    let Versioned { version } = serde_json::from_slice(bytes).map_err(|_| BackupError::NotABackup)?;
+    if version != BACKUP_VERSION {
+        return Err(BackupError::Version(version));
+    }
+    let backup: Backup =
+        serde_json::from_slice(bytes).map_err(|_| BackupError::Invalid("its contents don't fit this version"))?;
+    backup.stats.validate().map_err(BackupError::Invalid)?;
+    backup.settings.validate().map_err(BackupError::Invalid)?;
+    backup.snapshots.validate().map_err(BackupError::Invalid)?;
+    backup.history.validate().map_err(BackupError::Invalid)?;
+    Ok(backup)
+}
+
+/// Backups sent with `/restore` whose confirmation is pending, by the admin who sent
+/// them. A second `/restore` replaces the first.
+#[derive(Debug, Default)]
+pub struct PendingRestores {
+    by_admin: HashMap<i64, Backup>,
+}
+
+impl PendingRestores {
+    pub fn insert(&mut self, admin_id: i64, backup: Backup) {
+        self.by_admin.insert(admin_id, backup);
+    }
+
+    pub fn take(&mut self, admin_id: i64) -> Option<Backup> {
+        self.by_admin.remove(&admin_id)
+    }
+}
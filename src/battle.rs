@@ -0,0 +1,727 @@
+use std::collections::BTreeMap;
+
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::{
+    ai, i18n,
+    game::{Board, Game, GameStatus, Mark, Mode, Outcome, WinCondition, AI_PLAYER_ID},
+    settings::{self, Settings},
+    stats::{GameType, Record, Stats},
+    MyStats, State,
+};
+
+/// The games running in a chat. A chat can run several games at once; each board's
+/// buttons carry the id of its game.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Battle {
+    /// The state to go back to once every game is over.
+    previous: Box<State>,
+    next_id: u32,
+    pub games: BTreeMap<u32, BattleGame>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BattleGame {
+    pub message_id: i32,
+    pub game: Game,
+    /// Whether the board was pinned when the game started, so that it's unpinned when
+    /// the game ends. See `expiry::pin_board`.
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+impl Battle {
+    pub fn new(previous: State) -> Self {
+        Self { previous: Box::new(previous), next_id: 0, games: BTreeMap::new() }
+    }
+
+    /// Reserves the id of the next game, so that its board can be sent before the game
+    /// is inserted.
+    pub fn next_game_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+
+    /// The newest game `user_id` plays in.
+    pub fn game_of(&self, user_id: i64) -> Option<u32> {
+        self.games.iter().rev().find(|(_, g)| g.game.is_player(user_id)).map(|(&id, _)| id)
+    }
+
+    /// Whether a `/battle` of `user_id` may start a game. Starting over is `/newgame`'s
+    /// job, so a player's second `/battle` mustn't drop the game they're in.
+    pub fn may_start(&self, user_id: i64) -> bool {
+        self.game_of(user_id).is_none()
+    }
+
+    /// The state to store: the battle itself while games are running, the state the chat
+    /// was in before otherwise.
+    pub fn into_state(self) -> State {
+        if self.games.is_empty() {
+            *self.previous
+        } else {
+            State::Battle(self)
+        }
+    }
+}
+
+/// The `/games` list, with a button per game that sends its board again.
+pub fn games_list(battle: &Battle) -> (String, InlineKeyboardMarkup) {
+    let mut text = String::from("Active games:");
+    let mut keyboard = Vec::new();
+    for (id, entry) in &battle.games {
+        let game = &entry.game;
+        let status = match &game.status {
+            GameStatus::Paused { by } => format!("paused by {}", by),
+            GameStatus::Playing => format!("turn {}", game.turn.symbol()),
+        };
+        text.push_str(&format!(
+            "\n#{}: {} {} vs {} {}, {}",
+            id,
+            Mark::X.symbol(),
+            player_name(game, Mark::X),
+            Mark::O.symbol(),
+            player_name(game, Mark::O),
+            status,
+        ));
+        keyboard.push(vec![InlineKeyboardButton::callback(format!("Jump to #{}", id), format!("jump:{}", id))]);
+    }
+
+    (text, InlineKeyboardMarkup::new(keyboard))
+}
+
+pub fn cell_data(game_id: u32, cell: usize) -> String {
+    format!("g:{}:cell:{}", game_id, cell)
+}
+
+/// The button that switches which mark moves first.
+pub fn first_data(game_id: u32) -> String {
+    format!("g:{}:first", game_id)
+}
+
+/// Parses callback data made by [`first_data`] into the game id.
+pub fn parse_first_data(data: &str) -> Option<u32> {
+    data.strip_prefix("g:")?.strip_suffix(":first")?.parse().ok()
+}
+
+/// Parses callback data made by [`cell_data`] into the game id and the cell.
+fn parse_cell_data(data: &str) -> Option<(u32, usize)> {
+    let rest = data.strip_prefix("g:")?;
+    let (game_id, cell) = rest.split_once(":cell:")?;
+    Some((game_id.parse().ok()?, cell.parse().ok()?))
+}
+
+/// What the player left in a game whose opponent ran out of time can do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Takeover {
+    /// Finish the game against the AI.
+    Bot,
+    /// End the game as a win.
+    Claim,
+}
+
+pub fn takeover_data(game_id: u32, takeover: Takeover) -> String {
+    let answer = match takeover {
+        Takeover::Bot => "bot",
+        Takeover::Claim => "win",
+    };
+    format!("takeover:{}:{}", game_id, answer)
+}
+
+/// A button of a board message that is played through the game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoardButton {
+    Cell(usize),
+    Takeover(Takeover),
+}
+
+/// Parses callback data made by [`cell_data`] or [`takeover_data`] into the game id and
+/// the button.
+pub fn parse_board_data(data: &str) -> Option<(u32, BoardButton)> {
+    match parse_cell_data(data) {
+        Some((game_id, cell)) => Some((game_id, BoardButton::Cell(cell))),
+        None => parse_takeover_data(data).map(|(game_id, takeover)| (game_id, BoardButton::Takeover(takeover))),
+    }
+}
+
+/// Parses callback data made by [`takeover_data`].
+fn parse_takeover_data(data: &str) -> Option<(u32, Takeover)> {
+    let (game_id, answer) = data.strip_prefix("takeover:")?.split_once(':')?;
+    let takeover = match answer {
+        "bot" => Takeover::Bot,
+        "win" => Takeover::Claim,
+        _ => return None,
+    };
+    Some((game_id.parse().ok()?, takeover))
+}
+
+/// The board of a game whose player to move ran out of time, with the choices of the
+/// other player below it.
+pub fn takeover_keyboard(game_id: u32, game: &Game) -> InlineKeyboardMarkup {
+    board_keyboard(game_id, game).append_row(vec![
+        InlineKeyboardButton::callback("🤖 Let the bot take over".to_owned(), takeover_data(game_id, Takeover::Bot)),
+        InlineKeyboardButton::callback("🏆 Take the win".to_owned(), takeover_data(game_id, Takeover::Claim)),
+    ])
+}
+
+/// Every line that ends a game counts for `/winstats`, but only games between two
+/// different users count for the leaderboard and the scoreboard of `chat_id`. Returns
+/// whether the game counted for those.
+pub fn record_result(stats: &MyStats, chat_id: i64, game: &Game, outcome: Outcome) -> bool {
+    let mut stats = stats.lock().unwrap();
+    if let Some(line) = game.winning_line {
+        stats.record_line(game.board.size(), line);
+    }
+    if game.mode != Mode::TwoPlayer {
+        return false;
+    }
+    let (x, o) = match (game.player(Mark::X), game.player(Mark::O)) {
+        (Some(x), Some(o)) => (x, o),
+        _ => return false,
+    };
+    let (winner, loser) = match outcome {
+        Outcome::Win(Mark::O) => (o, x),
+        Outcome::Win(Mark::X) | Outcome::Draw => (x, o),
+    };
+    match outcome {
+        Outcome::Win(_) => stats.record_win(GameType::of_battle(game.condition), winner.id, loser.id),
+        Outcome::Draw => stats.record_draw(GameType::of_battle(game.condition), x.id, o.id),
+    }
+    stats.record_chat_result(chat_id, winner, loser, outcome == Outcome::Draw);
+    true
+}
+
+/// The most players a `/exportleaderboard` file lists.
+pub const MAX_CSV_ROWS: usize = 5000;
+
+/// The standings of `chat_id` as CSV, with a header row, and how many players it lists,
+/// [`MAX_CSV_ROWS`] at most. Players who turned `/anon` on are listed as
+/// [`settings::ANONYMOUS`].
+pub fn leaderboard_csv(stats: &Stats, settings: &Settings, chat_id: i64) -> (String, usize) {
+    let mut csv = String::from("rank,name,wins,losses,draws\n");
+    let standings = stats.chat_standings(chat_id);
+    let rows = standings.len().min(MAX_CSV_ROWS);
+    for (rank, (user_id, standing)) in standings.into_iter().take(rows).enumerate() {
+        let record = standing.record;
+        let name = if settings.is_anonymous(user_id) { settings::ANONYMOUS } else { standing.name.as_str() };
+        csv.push_str(&format!("{},{},{},{},{}\n", rank + 1, csv_field(name), record.wins, record.losses, record.draws));
+    }
+    (csv, rows)
+}
+
+/// Quotes `field` if it needs it. A leading `=`, `+`, `-` or `@` gets a `'` so that
+/// spreadsheets don't take a player's name for a formula.
+fn csv_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@']) { format!("'{}", field) } else { field.to_owned() };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+/// The most players a scoreboard shows.
+pub const SCOREBOARD_SIZE: usize = 10;
+
+/// The pinned scoreboard of `chat_id`: its best players with their results. Players who
+/// turned `/anon` on are listed without their name.
+pub fn scoreboard_text(stats: &Stats, settings: &Settings, chat_id: i64) -> String {
+    let mut text = String::from("🏆 Scoreboard");
+    for (rank, (user_id, standing)) in stats.chat_standings(chat_id).into_iter().take(SCOREBOARD_SIZE).enumerate() {
+        let record = standing.record;
+        let name = if settings.is_anonymous(user_id) { settings::ANONYMOUS } else { standing.name.as_str() };
+        text.push_str(&format!(
+            "\n{}. {}: {} won, {} lost, {} drawn",
+            rank + 1,
+            name,
+            record.wins,
+            record.losses,
+            record.draws,
+        ));
+    }
+    text
+}
+
+/// The biggest board that gets a button per cell. Telegram rejects inline keyboards with
+/// more than 100 buttons or rows of more than 8 buttons, so 8×8 is the largest square
+/// board that fits.
+pub const MAX_KEYBOARD_BOARD: usize = 8;
+
+pub fn fits_keyboard(board: &Board) -> bool {
+    board.size() <= MAX_KEYBOARD_BOARD
+}
+
+/// The smallest board, which is also the size of a `/battle` without one.
+pub const MIN_BOARD_SIZE: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SizeError {
+    NotNumeric,
+    TooSmall,
+    TooLarge,
+    /// The line length after the board size is below [`MIN_BOARD_SIZE`].
+    LineTooShort,
+    LineTooLong,
+    UnclosedQuote,
+}
+
+impl std::fmt::Display for SizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SizeError::NotNumeric => write!(
+                f,
+                "Use /battle with a board size, e.g. /battle 4, optionally followed by how many in a row win, \
+                 e.g. /battle 5 4, and any of: misere, learn, pass, ai, easy, medium, hard"
+            ),
+            SizeError::TooSmall => write!(f, "Boards are at least {0}×{0}", MIN_BOARD_SIZE),
+            SizeError::TooLarge => write!(f, "Boards are at most {0}×{0}", MAX_KEYBOARD_BOARD),
+            SizeError::LineTooShort => write!(f, "Lines are at least {} long", MIN_BOARD_SIZE),
+            SizeError::LineTooLong => write!(f, "A line can't be longer than the board"),
+            SizeError::UnclosedQuote => write!(f, "A quote in the options is never closed"),
+        }
+    }
+}
+
+impl std::error::Error for SizeError {}
+
+impl From<crate::args::UnclosedQuote> for SizeError {
+    fn from(_: crate::args::UnclosedQuote) -> Self {
+        SizeError::UnclosedQuote
+    }
+}
+
+/// Parses how many marks in a row win on a board of `size`, the number after the board
+/// size of a `/battle`.
+pub fn parse_win_length(arg: &str, size: usize) -> Result<usize, SizeError> {
+    let arg = arg.trim();
+    if arg.is_empty() || !arg.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(SizeError::NotNumeric);
+    }
+    match arg.parse::<usize>() {
+        Ok(length) if length < MIN_BOARD_SIZE => Err(SizeError::LineTooShort),
+        Ok(length) if length <= size => Ok(length),
+        _ => Err(SizeError::LineTooLong),
+    }
+}
+
+/// Parses the board size of a `/battle`, [`MIN_BOARD_SIZE`] if it's missing. Sizes
+/// too long for a `usize` are too large like any other size above the limit.
+pub fn parse_board_size(arg: &str) -> Result<usize, SizeError> {
+    let arg = arg.trim();
+    if arg.is_empty() {
+        return Ok(MIN_BOARD_SIZE);
+    }
+    let digits = arg.strip_prefix(['-', '+']).unwrap_or(arg);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(SizeError::NotNumeric);
+    }
+    if arg.starts_with('-') {
+        return Err(SizeError::TooSmall);
+    }
+    match digits.parse::<usize>() {
+        Ok(size) if size < MIN_BOARD_SIZE => Err(SizeError::TooSmall),
+        Ok(size) if size <= MAX_KEYBOARD_BOARD => Ok(size),
+        _ => Err(SizeError::TooLarge),
+    }
+}
+
+/// A button per cell, and one to switch the first mark until the game starts. The cell
+/// played last is in parentheses, so that players see what changed. Boards that don't
+/// [fit](fits_keyboard) get no buttons rather than a markup Telegram would reject.
+pub fn board_keyboard(game_id: u32, game: &Game) -> InlineKeyboardMarkup {
+    let board = &game.board;
+    if !fits_keyboard(board) {
+        return InlineKeyboardMarkup::new(Vec::<Vec<_>>::new());
+    }
+    let last_move = game.last_move();
+    let mut keyboard: Vec<Vec<_>> = board
+        .cells()
+        .chunks(board.size())
+        .enumerate()
+        .map(|(row, cells)| {
+            cells
+                .iter()
+                .enumerate()
+                .map(|(col, cell)| {
+                    let index = row * board.size() + col;
+                    let label = match cell {
+                        Some(mark) if last_move == Some(index) => format!("({})", mark.symbol()),
+                        Some(mark) => mark.symbol().to_owned(),
+                        None => "⬜".to_owned(),
+                    };
+                    InlineKeyboardButton::callback(label, cell_data(game_id, index))
+                })
+                .collect()
+        })
+        .collect();
+    if game.can_choose_first() {
+        let label = format!("{} moves first, tap to switch", game.turn.symbol());
+        keyboard.push(vec![InlineKeyboardButton::callback(label, first_data(game_id))]);
+    }
+
+    InlineKeyboardMarkup::new(keyboard)
+}
+
+fn player_name(game: &Game, mark: Mark) -> String {
+    game.player(mark).map_or_else(|| "waiting for a player".to_owned(), |player| player.name.clone())
+}
+
+pub fn battle_text(game: &Game) -> String {
+    if let GameStatus::Paused { by } = &game.status {
+        return format!("{}\n\nPaused by {} — /resume to continue", board_text(&game.board), by);
+    }
+    let title = match game.condition {
+        WinCondition::Normal => "Let's battle!",
+        WinCondition::Misere => "Let's battle! Misère rules: three in a row loses.",
+    };
+    let board = &game.board;
+    let title = match (board.win_length() < board.size(), game.condition) {
+        (false, _) => title.to_owned(),
+        (true, WinCondition::Normal) => format!("{} {} in a row wins.", title, board.win_length()),
+        (true, WinCondition::Misere) => format!("Let's battle! Misère rules: {} in a row loses.", board.win_length()),
+    };
+    let mut text = format!(
+        "{}\n{} {} vs {} {}\nTurn: {}",
+        title,
+        Mark::X.symbol(),
+        player_name(game, Mark::X),
+        Mark::O.symbol(),
+        player_name(game, Mark::O),
+        game.turn.symbol(),
+    );
+    if let Some(player) = &game.taken_over {
+        text.push_str(&format!("\nThe bot took over from {}", player.name));
+    }
+    match assessment_text(game) {
+        Some(assessment) => format!("{}\n{}", text, assessment),
+        None => text,
+    }
+}
+
+/// What the AI thinks of the position, in learn mode.
+fn assessment_text(game: &Game) -> Option<String> {
+    if !game.learn {
+        return None;
+    }
+    let text = match ai::assess(&game.board, game.turn, game.condition)? {
+        Outcome::Win(mark) => format!("{} likely wins", mark.symbol()),
+        Outcome::Draw => "likely draw".to_owned(),
+    };
+    Some(format!("🧠 With best play: {}", text))
+}
+
+/// How many cells are left on the board and whose turn it is.
+pub fn moves_text(game: &Game) -> String {
+    let left = game.board.empty_cells().count();
+    let cells = if left == 1 { "cell" } else { "cells" };
+    format!(
+        "{} empty {} left of {}. Turn: {} {}",
+        left,
+        cells,
+        game.board.cells().len(),
+        game.turn.symbol(),
+        player_name(game, game.turn),
+    )
+}
+
+/// What `/clock` says about `game` in a chat with a `move_timer` of that many seconds,
+/// given the seconds its player to move has left.
+pub fn clock_text(game: &Game, move_timer: Option<u64>, seconds_left: Option<u64>) -> String {
+    let left = match (move_timer, seconds_left) {
+        (Some(_), Some(left)) => left,
+        _ => return NO_TIMER.to_owned(),
+    };
+    let name = player_name(game, game.turn);
+    match &game.status {
+        GameStatus::Paused { by } => {
+            format!("Paused by {}, the clock is stopped: {} has {}s left once it's resumed", by, name, left)
+        }
+        _ if game.is_waiting_for_players() => "The clock starts once both players are seated".to_owned(),
+        _ if game.player(game.turn).is_some_and(|p| p.id == AI_PLAYER_ID) => NO_TIMER.to_owned(),
+        _ if left == 0 => format!("⏰ Time's up for {} {}", game.turn.symbol(), name),
+        _ => format!("⏱ {} {} has {}s left to move", game.turn.symbol(), name, left),
+    }
+}
+
+pub const NO_TIMER: &str = "No timer running";
+
+pub fn board_text(board: &Board) -> String {
+    board
+        .cells()
+        .chunks(board.size())
+        .map(|row| row.iter().map(|cell| cell.map_or("⬜", |mark| mark.symbol())).collect())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// The result of a game the player to move lost by running out of time.
+pub fn forfeit_text(game: &Game) -> String {
+    let (loser, winner) = (game.turn, game.turn.other());
+    format!(
+        "{}\n\n⏰ {} {} ran out of time, {} {} wins!",
+        board_text(&game.board),
+        loser.symbol(),
+        player_name(game, loser),
+        winner.symbol(),
+        player_name(game, winner),
+    )
+}
+
+/// A user's `/stats` this season: all their games and a line per game type, or only
+/// the games of `game_type`.
+pub fn stats_text(stats: &Stats, user_id: i64, game_type: Option<GameType>) -> String {
+    let line = |title: &str, record: Record| {
+        format!("{}: {} won, {} lost, {} drawn", title, record.wins, record.losses, record.draws)
+    };
+    match game_type {
+        Some(game_type) => line(game_type.title(), stats.typed_record(user_id, game_type)),
+        None => {
+            let mut text = line("📊 All games", stats.record(user_id));
+            for game_type in GameType::ALL {
+                text.push_str(&format!("\n{}", line(game_type.title(), stats.typed_record(user_id, game_type))));
+            }
+            text
+        }
+    }
+}
+
+/// The `/globalstats` summary: the games that ended on the whole bot, by type, and the
+/// chats playing now.
+pub fn global_stats_text(stats: &Stats, active_chats: usize, lang: &str) -> String {
+    let number = |n: u64| i18n::format_number(n as i64, lang);
+    let totals = GameType::ALL.map(|game_type| (game_type, stats.totals(game_type)));
+    let games: u64 = totals.iter().map(|(_, totals)| u64::from(totals.games)).sum();
+    let moves: u64 = totals.iter().map(|(_, totals)| totals.moves).sum();
+    let mut text = format!("🌍 Games played: {}", number(games));
+    for (game_type, totals) in totals {
+        text.push_str(&format!("\n{}: {}", game_type.title(), number(totals.games.into())));
+    }
+    if games > 0 {
+        text.push_str(&format!("\nAverage game: {:.1} moves", moves as f64 / games as f64));
+    }
+    text.push_str(&format!("\nChats playing now: {}", number(active_chats as u64)));
+    text
+}
+
+/// The `/winstats` histogram: how often each line ended a game, per board size.
+pub fn winstats_text(stats: &Stats) -> String {
+    const BAR: usize = 10;
+    if stats.winning_lines().is_empty() {
+        return "No game has been won with a line yet".to_owned();
+    }
+    let mut sections = Vec::new();
+    for (size, lines) in stats.winning_lines() {
+        let max = lines.values().copied().max().unwrap_or(1);
+        let mut lines: Vec<_> = lines.iter().collect();
+        lines.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let mut text = format!("Winning lines on {0}×{0} boards:", size);
+        for (line, &count) in lines {
+            let bar = (count as usize * BAR).div_ceil(max as usize);
+            text.push_str(&format!("\n{} {} {}", "▇".repeat(bar), count, line));
+        }
+        sections.push(text);
+    }
+    sections.join("\n\n")
+}
+
+/// The moves of a finished game, each marked `!` if it kept the best outcome of its
+/// position and `?` if it gave some away. `None` for boards too big to solve.
+pub fn review_text(game: &Game) -> Option<String> {
+    let annotations = ai::annotate(&game.board, &game.moves, game.condition)?;
+    let moves: Vec<_> = game
+        .moves
+        .iter()
+        .zip(annotations)
+        .filter_map(|(&cell, annotation)| {
+            let mark = game.board.cells()[cell]?;
+            Some(format!("{}{}{}", mark.symbol(), cell + 1, annotation.symbol()))
+        })
+        .collect();
+    Some(format!("Review: {}", moves.join(" ")))
+}
+
+/// The moves of a `/simulate` from the position of `game`, numbered on from the moves
+/// played so far, and where they lead.
+pub fn simulation_text(game: &Game, simulation: &ai::Simulation) -> String {
+    let mut board = game.board.clone();
+    let mut mark = game.turn;
+    let mut moves = Vec::with_capacity(simulation.moves.len());
+    for &cell in &simulation.moves {
+        board.place(cell, mark).ok();
+        moves.push(format!("{}{}", mark.symbol(), cell + 1));
+        mark = mark.other();
+    }
+    let result = match simulation.outcome {
+        Outcome::Win(mark) => format!("{} wins", mark.symbol()),
+        Outcome::Draw => "draw".to_owned(),
+    };
+    let how = if simulation.exact { "With best play from here" } else { "The hard bot playing both sides" };
+    format!(
+        "🔮 {}: {}\nAfter move {}: {}\n\n{}",
+        how,
+        result,
+        game.moves.len(),
+        moves.join(" "),
+        board_text(&board)
+    )
+}
+
+pub fn resign_text(game: &Game, resigned: Mark) -> String {
+    format!(
+        "{}\n\n🏳️ {} {} resigned, {} {} wins!",
+        board_text(&game.board),
+        resigned.symbol(),
+        player_name(game, resigned),
+        resigned.other().symbol(),
+        player_name(game, resigned.other()),
+    )
+}
+
+pub fn result_text(game: &Game, outcome: Outcome) -> String {
+    let result = match (outcome, game.condition) {
+        (Outcome::Win(mark), WinCondition::Normal) => {
+            format!("{} {} wins!", mark.symbol(), player_name(game, mark))
+        }
+        (Outcome::Win(mark), WinCondition::Misere) => format!(
+            "{} {} completed a line and loses, {} {} wins!",
+            mark.other().symbol(),
+            player_name(game, mark.other()),
+            mark.symbol(),
+            player_name(game, mark),
+        ),
+        (Outcome::Draw, _) => "It's a draw!".to_owned(),
+    };
+    format!("{}\n\n{}", board_text(&game.board), result)
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::InlineKeyboardButtonKind;
+
+    use super::*;
+    use crate::game::Player;
+
+    /// What Telegram takes of an inline keyboard.
+    const MAX_BUTTONS: usize = 100;
+    const MAX_ROW_BUTTONS: usize = 8;
+    const MAX_CALLBACK_DATA: usize = 64;
+
+    fn player(id: i64, name: &str) -> Player {
+        Player { id, name: name.to_owned(), username: None }
+    }
+
+    #[test]
+    fn keyboards_of_every_size_stay_within_the_limits() {
+        for size in MIN_BOARD_SIZE..=MAX_KEYBOARD_BOARD {
+            assert_eq!(parse_board_size(&size.to_string()), Ok(size));
+            let mut game = Game::new(size, Mode::TwoPlayer, WinCondition::Normal, 0);
+            // The host can still switch the first mark, which takes a row of its own.
+            game.host = Some(1);
+            assert!(game.can_choose_first());
+            assert!(fits_keyboard(&game.board));
+
+            let rows = board_keyboard(u32::MAX, &game).inline_keyboard;
+            assert_eq!(rows.len(), size + 1);
+            assert!(rows.iter().map(Vec::len).sum::<usize>() <= MAX_BUTTONS, "{}×{}", size, size);
+            for button in rows.iter().flatten() {
+                match &button.kind {
+                    InlineKeyboardButtonKind::CallbackData(data) => assert!(data.len() <= MAX_CALLBACK_DATA),
+                    kind => panic!("unexpected button {:?}", kind),
+                }
+            }
+            assert!(rows.iter().all(|row| row.len() <= MAX_ROW_BUTTONS));
+        }
+    }
+
+    fn cell_labels(game: &Game) -> Vec<String> {
+        let rows = board_keyboard(0, game).inline_keyboard;
+        rows.iter().take(game.board.size()).flatten().map(|button| button.text.clone()).collect()
+    }
+
+    #[test]
+    fn only_the_last_move_is_decorated() {
+        let mut game = Game::new(3, Mode::TwoPlayer, WinCondition::Normal, 0);
+        assert!(cell_labels(&game).iter().all(|label| !label.starts_with('(')));
+        for (player, cell) in [(player(1, "Alice"), 4), (player(2, "Bob"), 0), (player(1, "Alice"), 8)] {
+            game.play(player, cell).unwrap();
+            let decorated: Vec<_> =
+                cell_labels(&game).iter().enumerate().filter(|(_, l)| l.starts_with('(')).map(|(i, _)| i).collect();
+            assert_eq!(decorated, [cell]);
+        }
+        assert_eq!(cell_labels(&game)[8], format!("({})", Mark::X.symbol()));
+        assert_eq!(cell_labels(&game)[4], Mark::X.symbol());
+    }
+
+    #[test]
+    fn boards_too_big_for_a_keyboard_get_no_buttons() {
+        let game = Game::new(MAX_KEYBOARD_BOARD + 1, Mode::TwoPlayer, WinCondition::Normal, 0);
+        assert!(!fits_keyboard(&game.board));
+        assert!(board_keyboard(0, &game).inline_keyboard.is_empty());
+    }
+
+    #[test]
+    fn second_battle_of_a_player_keeps_their_game() {
+        let mut game = Game::new(3, Mode::TwoPlayer, WinCondition::Normal, 0);
+        game.play(player(1, "Alice"), 4).unwrap();
+        let mut battle = Battle::new(State::Start);
+        let id = battle.next_game_id();
+        battle.games.insert(id, BattleGame { message_id: 1, game, pinned: false });
+
+        assert!(!battle.may_start(1));
+        // Other members start their own games alongside.
+        assert!(battle.may_start(2));
+
+        battle.games.remove(&id);
+        assert!(battle.may_start(1));
+    }
+
+    #[test]
+    fn anonymous_players_are_unnamed_on_the_leaderboard() {
+        let mut stats = Stats::default();
+        stats.record_chat_result(1, &player(1, "Alice"), &player(2, "Bob"), false);
+        let mut settings = Settings::default();
+        settings.set_anonymous(1, true);
+
+        let (csv, rows) = leaderboard_csv(&stats, &settings, 1);
+        assert_eq!(rows, 2);
+        assert!(!csv.contains("Alice"));
+        assert!(csv.contains(&format!("1,{},1,0,0", settings::ANONYMOUS)));
+        assert!(csv.contains("2,Bob,0,1,0"));
+
+        let scoreboard = scoreboard_text(&stats, &settings, 1);
+        assert!(!scoreboard.contains("Alice"));
+        assert!(scoreboard.contains("Bob"));
+    }
+
+    #[test]
+    fn board_sizes_in_range_parse() {
+        assert_eq!(parse_board_size(""), Ok(MIN_BOARD_SIZE));
+        assert_eq!(parse_board_size("  "), Ok(MIN_BOARD_SIZE));
+        assert_eq!(parse_board_size(" 4 "), Ok(4));
+        assert_eq!(parse_board_size("+5"), Ok(5));
+        assert_eq!(parse_board_size("08"), Ok(MAX_KEYBOARD_BOARD));
+    }
+
+    #[test]
+    fn bad_board_sizes_say_why() {
+        for arg in ["four", "4x4", "-", "+", "3.5", "٤"] {
+            assert_eq!(parse_board_size(arg), Err(SizeError::NotNumeric), "{:?}", arg);
+        }
+        for arg in ["0", "2", "-5", "-99999999999999999999999"] {
+            assert_eq!(parse_board_size(arg), Err(SizeError::TooSmall), "{:?}", arg);
+        }
+        for arg in ["9", "100", "99999999999999999999999"] {
+            assert_eq!(parse_board_size(arg), Err(SizeError::TooLarge), "{:?}", arg);
+        }
+    }
+
+    #[test]
+    fn win_lengths_fit_the_board() {
+        assert_eq!(parse_win_length("4", 5), Ok(4));
+        assert_eq!(parse_win_length("5", 5), Ok(5));
+        assert_eq!(parse_win_length("2", 5), Err(SizeError::LineTooShort));
+        assert_eq!(parse_win_length("6", 5), Err(SizeError::LineTooLong));
+        assert_eq!(parse_win_length("99999999999999999999999", 5), Err(SizeError::LineTooLong));
+        assert_eq!(parse_win_length("-4", 5), Err(SizeError::NotNumeric));
+    }
+}
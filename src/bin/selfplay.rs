@@ -0,0 +1,75 @@
+//! Plays the medium AI against the random and the minimax players and prints the win
+//! rates, to check the heuristic weights in `ai.rs` after changing them.
+//!
+//! Run with `cargo run --release --bin selfplay [games]`.
+
+#[allow(dead_code)]
+#[path = "../ai.rs"]
+mod ai;
+#[allow(dead_code)]
+#[path = "../game.rs"]
+mod game;
+
+use ai::Difficulty;
+use game::{check_winner, Board, Mark, Outcome, WinCondition};
+
+const DEFAULT_GAMES: u32 = 1000;
+
+#[derive(Default)]
+struct Tally {
+    wins: u32,
+    draws: u32,
+    losses: u32,
+}
+
+/// Plays one game and returns its outcome.
+fn play(x: Difficulty, o: Difficulty) -> Outcome {
+    let mut board = Board::new(3);
+    let mut turn = Mark::X;
+    loop {
+        if let Some(outcome) = check_winner(&board, WinCondition::Normal) {
+            return outcome;
+        }
+        let difficulty = if turn == Mark::X { x } else { o };
+        let cell = ai::ai_move(&board, turn, difficulty, WinCondition::Normal, &mut rand::thread_rng())
+            .expect("the board isn't full");
+        board.place(cell, turn).expect("the AI picks free cells");
+        turn = turn.other();
+    }
+}
+
+/// Plays `games` games of `player` against `opponent`, swapping sides every game.
+fn run(player: Difficulty, opponent: Difficulty, games: u32) -> Tally {
+    let mut tally = Tally::default();
+    for i in 0..games {
+        let (mark, outcome) = if i % 2 == 0 {
+            (Mark::X, play(player, opponent))
+        } else {
+            (Mark::O, play(opponent, player))
+        };
+        match outcome {
+            Outcome::Win(winner) if winner == mark => tally.wins += 1,
+            Outcome::Win(_) => tally.losses += 1,
+            Outcome::Draw => tally.draws += 1,
+        }
+    }
+    tally
+}
+
+fn main() {
+    let games = std::env::args().nth(1).and_then(|s| s.parse().ok()).unwrap_or(DEFAULT_GAMES);
+
+    println!("{:<20} {:>7} {:>7} {:>7} {:>7}", "matchup", "games", "wins", "draws", "losses");
+    for (name, opponent) in [("medium vs random", Difficulty::Easy), ("medium vs minimax", Difficulty::Hard)] {
+        let tally = run(Difficulty::Medium, opponent, games);
+        let pct = |n: u32| 100.0 * n as f64 / games as f64;
+        println!(
+            "{:<20} {:>7} {:>6.1}% {:>6.1}% {:>6.1}%",
+            name,
+            games,
+            pct(tally.wins),
+            pct(tally.draws),
+            pct(tally.losses),
+        );
+    }
+}
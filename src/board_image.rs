@@ -0,0 +1,124 @@
+//! Renders finished boards as PNG images, behind the `board-images` feature.
+//!
+//! The encoder is written here instead of pulling in an image crate: a board is a few
+//! flat colours, so a PNG with uncompressed (stored) deflate blocks is all it takes.
+
+use crate::game::{Board, Mark};
+
+/// Side of a cell in pixels.
+const CELL: usize = 96;
+/// Thickness of the grid and of the marks in pixels.
+const STROKE: usize = 6;
+/// Space between a mark and the edges of its cell.
+const MARGIN: usize = 18;
+
+const BACKGROUND: [u8; 3] = [0xff, 0xff, 0xff];
+const GRID: [u8; 3] = [0x44, 0x44, 0x44];
+const X_COLOR: [u8; 3] = [0xd3, 0x2f, 0x2f];
+const O_COLOR: [u8; 3] = [0x1e, 0x63, 0xc8];
+
+pub fn render_board_png(board: &Board) -> Vec<u8> {
+    let n = board.size();
+    let side = n * CELL + (n + 1) * STROKE;
+    let mut rows = Vec::with_capacity(side * (side * 3 + 1));
+    for y in 0..side {
+        // Every row starts with its filter type, 0 is none.
+        rows.push(0);
+        for x in 0..side {
+            rows.extend_from_slice(&pixel(board, x, y));
+        }
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(side as u32).to_be_bytes());
+    header.extend_from_slice(&(side as u32).to_be_bytes());
+    // 8 bits per channel, RGB, deflate, no filtering beyond the per-row byte, no interlace.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib_stored(&rows));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn pixel(board: &Board, x: usize, y: usize) -> [u8; 3] {
+    let pitch = CELL + STROKE;
+    let (x, y) = (x as isize - STROKE as isize, y as isize - STROKE as isize);
+    if x < 0 || y < 0 || x as usize % pitch >= CELL || y as usize % pitch >= CELL {
+        return GRID;
+    }
+    let (col, row) = (x as usize / pitch, y as usize / pitch);
+    if col >= board.size() || row >= board.size() {
+        return GRID;
+    }
+    // Coordinates inside the cell, relative to its centre.
+    let cx = (x as usize % pitch) as f64 - CELL as f64 / 2.0;
+    let cy = (y as usize % pitch) as f64 - CELL as f64 / 2.0;
+    let half = (CELL / 2 - MARGIN) as f64;
+    let stroke = STROKE as f64 / 2.0;
+
+    match board.cells()[row * board.size() + col] {
+        Some(Mark::X) if cx.abs() <= half && cy.abs() <= half => {
+            // Distance to the nearest diagonal.
+            let d = (cx - cy).abs().min((cx + cy).abs()) / std::f64::consts::SQRT_2;
+            if d <= stroke {
+                X_COLOR
+            } else {
+                BACKGROUND
+            }
+        }
+        Some(Mark::O) if ((cx * cx + cy * cy).sqrt() - (half - stroke)).abs() <= stroke => O_COLOR,
+        _ => BACKGROUND,
+    }
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps `data` in a zlib stream of stored deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = u16::MAX as usize;
+    let mut out = vec![0x78, 0x01];
+    let blocks = data.chunks(MAX_BLOCK).collect::<Vec<_>>();
+    for (i, block) in blocks.iter().enumerate() {
+        let last = i + 1 == blocks.len();
+        out.push(last as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    if blocks.is_empty() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::game::Player;
+
+/// How long a challenge can be accepted, in seconds.
+pub const CHALLENGE_TTL: u64 = 5 * 60;
+
+/// Challenges waiting for an answer, by chat id and the id of the message announcing them.
+#[derive(Debug, Default)]
+pub struct Challenges {
+    pending: HashMap<(i64, i32), Challenge>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Challenge {
+    pub challenger: Player,
+    /// The username of the challenged user, without the `@`.
+    pub username: String,
+    /// Unix time in seconds.
+    pub created_at: u64,
+}
+
+impl Challenge {
+    /// Whether `username` is the challenged user's, in any case.
+    pub fn is_for(&self, username: Option<&str>) -> bool {
+        username.is_some_and(|username| username.eq_ignore_ascii_case(&self.username))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnswerError {
+    Expired,
+    NotYours,
+}
+
+impl std::fmt::Display for AnswerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnswerError::Expired => write!(f, "This challenge has expired"),
+            AnswerError::NotYours => write!(f, "This challenge isn't for you"),
+        }
+    }
+}
+
+impl std::error::Error for AnswerError {}
+
+impl Challenges {
+    pub fn insert(&mut self, chat_id: i64, message_id: i32, challenge: Challenge) {
+        self.pending.insert((chat_id, message_id), challenge);
+    }
+
+    /// Drops the challenges older than [`CHALLENGE_TTL`].
+    pub fn prune(&mut self, now: u64) {
+        self.pending.retain(|_, c| now.saturating_sub(c.created_at) < CHALLENGE_TTL);
+    }
+
+    /// Removes and returns the challenge announced by `message_id` if the user may answer
+    /// it: only the challenged user can accept, and the challenger can also withdraw.
+    pub fn answer(
+        &mut self,
+        chat_id: i64,
+        message_id: i32,
+        user_id: i64,
+        username: Option<&str>,
+        accept: bool,
+    ) -> Result<Challenge, AnswerError> {
+        let challenge = self.pending.get(&(chat_id, message_id)).ok_or(AnswerError::Expired)?;
+        if !challenge.is_for(username) && (accept || challenge.challenger.id != user_id) {
+            return Err(AnswerError::NotYours);
+        }
+        Ok(self.pending.remove(&(chat_id, message_id)).expect("found above"))
+    }
+
+    /// Removes and returns the newest challenge of `chat_id` for `username`, with the id
+    /// of its announcement.
+    pub fn take_for(&mut self, chat_id: i64, username: Option<&str>) -> Option<(i32, Challenge)> {
+        let message_id = self
+            .pending
+            .iter()
+            .filter(|((chat, _), c)| *chat == chat_id && c.is_for(username))
+            .max_by_key(|(_, c)| c.created_at)
+            .map(|(&(_, message_id), _)| message_id)?;
+        Some((message_id, self.pending.remove(&(chat_id, message_id))?))
+    }
+}
+
+pub fn challenge_keyboard() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback("Accept".to_owned(), "challenge:yes".to_owned()),
+        InlineKeyboardButton::callback("Decline".to_owned(), "challenge:no".to_owned()),
+    ]])
+}
@@ -0,0 +1,89 @@
+use rand::Rng;
+
+/// The most dice `/dice` rolls at once.
+pub const MAX_DICE: u32 = 20;
+/// The most sides a die can have.
+pub const MAX_SIDES: u32 = 1000;
+
+pub fn coinflip(rng: &mut impl Rng) -> &'static str {
+    if rng.gen() {
+        "Heads"
+    } else {
+        "Tails"
+    }
+}
+
+/// Dice in `NdM` notation: `count` dice with `sides` sides each.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dice {
+    pub count: u32,
+    pub sides: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiceError {
+    Notation,
+    TooMany,
+    TooManySides,
+}
+
+impl std::fmt::Display for DiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiceError::Notation => write!(f, "Use /dice NdM, e.g. /dice 2d6"),
+            DiceError::TooMany => write!(f, "I can roll at most {} dice at once", MAX_DICE),
+            DiceError::TooManySides => write!(f, "Dice can have from 2 to {} sides", MAX_SIDES),
+        }
+    }
+}
+
+impl std::error::Error for DiceError {}
+
+impl Dice {
+    /// Parses `NdM`, or `dM` for a single die. Without any argument, it's one six-sided die.
+    pub fn parse(s: &str) -> Result<Self, DiceError> {
+        let s = s.trim().to_lowercase();
+        if s.is_empty() {
+            return Ok(Dice { count: 1, sides: 6 });
+        }
+        let (count, sides) = s.split_once('d').ok_or(DiceError::Notation)?;
+        let count = match count {
+            "" => 1,
+            count => parse_bound(count, MAX_DICE, DiceError::TooMany)?,
+        };
+        let sides = parse_bound(sides, MAX_SIDES, DiceError::TooManySides)?;
+        if count == 0 {
+            return Err(DiceError::TooMany);
+        }
+        if sides < 2 {
+            return Err(DiceError::TooManySides);
+        }
+        Ok(Dice { count, sides })
+    }
+
+    pub fn roll(self, rng: &mut impl Rng) -> Vec<u32> {
+        (0..self.count).map(|_| rng.gen_range(1..=self.sides)).collect()
+    }
+}
+
+/// Parses a number of the dice notation. Digits that overflow are as much over `max`
+/// as any other number above it.
+fn parse_bound(s: &str, max: u32, too_big: DiceError) -> Result<u32, DiceError> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(DiceError::Notation);
+    }
+    match s.parse() {
+        Ok(n) if n <= max => Ok(n),
+        _ => Err(too_big),
+    }
+}
+
+/// The rolls and their sum, e.g. `🎲 2d6: 3 + 5 = 8`.
+pub fn roll_text(dice: Dice, rolls: &[u32]) -> String {
+    let sum: u32 = rolls.iter().sum();
+    if let [roll] = rolls {
+        return format!("🎲 d{}: {}", dice.sides, roll);
+    }
+    let rolls: Vec<_> = rolls.iter().map(u32::to_string).collect();
+    format!("🎲 {}d{}: {} = {}", dice.count, dice.sides, rolls.join(" + "), sum)
+}
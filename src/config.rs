@@ -0,0 +1,241 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+const DEFAULT_GAME_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_LOBBY_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+const DEFAULT_STORAGE_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_WELCOME: &str = "Welcome! I remember a number for this chat: send me one, then \
+change it with /add and /sub and see it with /get. You can also play tic-tac-toe with /battle, \
+against a friend or against me.";
+const DEFAULT_TOURNAMENT_JOIN_WINDOW: Duration = Duration::from_secs(2 * 60);
+const DEFAULT_GAME_START_COOLDOWN: Duration = Duration::from_secs(5);
+const DEFAULT_MAX_GAMES_PER_CHAT: usize = 5;
+const DEFAULT_TURN_WARNING: Duration = Duration::from_secs(10);
+const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Where dialogue states are kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Redis,
+    Memory,
+}
+
+impl Backend {
+    /// Parses a backend name as `/migrate` takes it, in any case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "sqlite" => Some(Backend::Sqlite),
+            "redis" => Some(Backend::Redis),
+            "memory" => Some(Backend::Memory),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Sqlite => "Sqlite",
+            Backend::Redis => "Redis",
+            Backend::Memory => "Memory",
+        }
+    }
+
+    /// The serializer the backend stores states with, if any.
+    pub fn serializer_name(self) -> &'static str {
+        match self {
+            Backend::Sqlite => "Json",
+            Backend::Redis => "Bincode",
+            Backend::Memory => "none",
+        }
+    }
+}
+
+/// Runtime settings read once from the environment at startup.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub backend: Backend,
+    /// Maps an alias to the command it stands for, both without the leading slash
+    /// (e.g. `a` -> `add`).
+    pub aliases: HashMap<String, String>,
+    /// Accept commands typed without the leading slash (e.g. `add 5`).
+    pub bare_commands: bool,
+    /// Users allowed to run administrative commands.
+    pub admins: Vec<i64>,
+    /// The chat `/feedback` is sent to, if any.
+    pub owner_chat: Option<i64>,
+    /// The channel every finished game is posted to, if any. See `feed.rs`.
+    pub replay_channel: Option<i64>,
+    /// Battles older than this are reset by the sweeper.
+    pub game_max_age: Duration,
+    /// Games still waiting for their second player after this are reset by the sweeper.
+    pub lobby_timeout: Duration,
+    /// How long a Redis operation may take before the handler gives up.
+    pub storage_timeout: Duration,
+    /// How often the storage is checked, see `storage::spawn_health_check`.
+    pub health_check_interval: Duration,
+    /// How long players can join a tournament before the bracket is seeded.
+    pub tournament_join_window: Duration,
+    /// How long a user has to wait after starting a game before starting another one.
+    pub game_start_cooldown: Duration,
+    /// How many games may run at once in a chat.
+    pub max_games_per_chat: usize,
+    /// How many times a position may occur before a game is a draw.
+    pub repetition_limit: usize,
+    /// How long before the move timer runs out the player to move is warned.
+    pub turn_warning: Duration,
+    /// How many numbers `/undo` can go back through in chats that didn't `/sethistory`.
+    pub history_limit: usize,
+    /// In groups, give every member their own number instead of one for the whole chat.
+    pub per_user_numbers: bool,
+    /// How low `/give` may take the giver's number.
+    pub give_floor: i32,
+    /// Where finished games are logged for AI analysis, if anywhere. See `telemetry.rs`.
+    pub telemetry_file: Option<PathBuf>,
+    /// Sent before anything else to a chat that talks to the bot for the first time.
+    pub welcome: Option<String>,
+}
+
+impl Config {
+    /// Reads the backend from `DB_REMEMBER_REDIS` / `DB_REMEMBER_MEMORY` (Sqlite otherwise),
+    /// and reads `COMMAND_ALIASES` (`a=add,s=sub`), `BARE_COMMANDS`, `ADMIN_IDS` (`1,2`),
+    /// `OWNER_CHAT_ID`, `REPLAY_CHANNEL_ID`, `GAME_MAX_AGE_SECS` (one hour by default),
+    /// `LOBBY_TIMEOUT_SECS` (15 minutes), `STORAGE_TIMEOUT_SECS` (five seconds),
+    /// `HEALTH_CHECK_SECS` (a minute), `TOURNAMENT_JOIN_SECS` (two minutes),
+    /// `GAME_START_COOLDOWN_SECS` (five seconds), `MAX_GAMES_PER_CHAT` (five),
+    /// `REPETITION_LIMIT` (three), `TURN_WARNING_SECS` (ten seconds), `HISTORY_LIMIT`
+    /// (20 numbers), `PER_USER_NUMBERS`, `GIVE_FLOOR` (0),
+    /// `TELEMETRY_FILE` and `WELCOME_TEXT` (empty to turn the welcome off).
+    pub fn from_env() -> Self {
+        let backend = if std::env::var("DB_REMEMBER_REDIS").is_ok() {
+            Backend::Redis
+        } else if std::env::var("DB_REMEMBER_MEMORY").is_ok() {
+            Backend::Memory
+        } else {
+            Backend::Sqlite
+        };
+        let aliases = std::env::var("COMMAND_ALIASES")
+            .map(|s| parse_aliases(&s))
+            .unwrap_or_default();
+        let bare_commands = std::env::var("BARE_COMMANDS").is_ok();
+        let admins = std::env::var("ADMIN_IDS")
+            .map(|s| s.split(',').filter_map(|id| id.trim().parse().ok()).collect())
+            .unwrap_or_default();
+        let owner_chat = std::env::var("OWNER_CHAT_ID").ok().and_then(|s| s.trim().parse().ok());
+        let replay_channel = std::env::var("REPLAY_CHANNEL_ID").ok().and_then(|s| s.trim().parse().ok());
+        let game_max_age = std::env::var("GAME_MAX_AGE_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map_or(DEFAULT_GAME_MAX_AGE, Duration::from_secs);
+        let lobby_timeout = std::env::var("LOBBY_TIMEOUT_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map_or(DEFAULT_LOBBY_TIMEOUT, Duration::from_secs);
+        let storage_timeout = std::env::var("STORAGE_TIMEOUT_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map_or(DEFAULT_STORAGE_TIMEOUT, Duration::from_secs);
+        // An interval of zero would make `tokio::time::interval` panic.
+        let health_check_interval = std::env::var("HEALTH_CHECK_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|&secs| secs > 0)
+            .map_or(DEFAULT_HEALTH_CHECK_INTERVAL, Duration::from_secs);
+        let tournament_join_window = std::env::var("TOURNAMENT_JOIN_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map_or(DEFAULT_TOURNAMENT_JOIN_WINDOW, Duration::from_secs);
+        let game_start_cooldown = std::env::var("GAME_START_COOLDOWN_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map_or(DEFAULT_GAME_START_COOLDOWN, Duration::from_secs);
+        // A limit of zero would refuse every game.
+        let max_games_per_chat = std::env::var("MAX_GAMES_PER_CHAT")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|&max| max > 0)
+            .unwrap_or(DEFAULT_MAX_GAMES_PER_CHAT);
+        // Every position occurs once, so a limit below two would draw every game at once.
+        let repetition_limit = std::env::var("REPETITION_LIMIT")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|&limit| limit >= 2)
+            .unwrap_or(crate::game::REPETITION_LIMIT);
+        let turn_warning = std::env::var("TURN_WARNING_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map_or(DEFAULT_TURN_WARNING, Duration::from_secs);
+        let history_limit = std::env::var("HISTORY_LIMIT")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(crate::history::HISTORY_LIMIT);
+        let per_user_numbers = std::env::var("PER_USER_NUMBERS").is_ok();
+        let give_floor = std::env::var("GIVE_FLOOR").ok().and_then(|s| s.trim().parse().ok()).unwrap_or(0);
+        let welcome = match std::env::var("WELCOME_TEXT") {
+            Ok(text) if text.trim().is_empty() => None,
+            Ok(text) => Some(text),
+            Err(_) => Some(DEFAULT_WELCOME.to_owned()),
+        };
+        let telemetry_file = std::env::var_os("TELEMETRY_FILE").filter(|s| !s.is_empty()).map(PathBuf::from);
+
+        Self {
+            backend,
+            aliases,
+            bare_commands,
+            admins,
+            owner_chat,
+            replay_channel,
+            game_max_age,
+            lobby_timeout,
+            storage_timeout,
+            health_check_interval,
+            tournament_join_window,
+            game_start_cooldown,
+            max_games_per_chat,
+            repetition_limit,
+            turn_warning,
+            history_limit,
+            per_user_numbers,
+            give_floor,
+            telemetry_file,
+            welcome,
+        }
+    }
+
+    pub fn is_admin(&self, user_id: i64) -> bool {
+        self.admins.contains(&user_id)
+    }
+
+    /// Rewrites the command token of `text` so that `Command::parse` understands it:
+    /// aliases are replaced by their target and, if enabled, a missing slash is added.
+    /// The `@botname` suffix and the arguments are kept as they are.
+    pub fn resolve_command(&self, text: &str) -> String {
+        let (token, rest) = match text.find(char::is_whitespace) {
+            Some(i) => text.split_at(i),
+            None => (text, ""),
+        };
+        let (name, suffix) = match token.find('@') {
+            Some(i) => token.split_at(i),
+            None => (token, ""),
+        };
+        let name = match name.strip_prefix('/') {
+            Some(name) => name,
+            None if self.bare_commands => name,
+            None => return text.to_owned(),
+        };
+        let name = self.aliases.get(name).map(String::as_str).unwrap_or(name);
+
+        format!("/{}{}{}", name, suffix, rest)
+    }
+}
+
+fn parse_aliases(s: &str) -> HashMap<String, String> {
+    s.split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(alias, command)| {
+            (
+                alias.trim().trim_start_matches('/').to_lowercase(),
+                command.trim().trim_start_matches('/').to_lowercase(),
+            )
+        })
+        .filter(|(alias, command)| !alias.is_empty() && !command.is_empty())
+        .collect()
+}
@@ -0,0 +1,103 @@
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::config::Backend;
+
+/// The actions that are only carried out once their yes/no buttons are answered, see
+/// [`confirm_keyboard`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// `/resetleaderboard`.
+    ResetLeaderboard,
+    /// `/migrate` to a backend.
+    Migrate(Backend),
+    /// `/resetstats` of the user with this id, who alone may answer it.
+    ResetStats(i64),
+    /// `/restore` of the backup the admin with this id sent, who alone may answer it.
+    Restore(i64),
+}
+
+impl Action {
+    /// The id the action's buttons carry, which [`Action::parse`] reads back.
+    pub fn id(self) -> String {
+        match self {
+            Action::ResetLeaderboard => "lbreset".to_owned(),
+            Action::Migrate(target) => format!("migrate.{}", target.name().to_lowercase()),
+            Action::ResetStats(user_id) => format!("resetstats.{}", user_id),
+            Action::Restore(admin_id) => format!("restore.{}", admin_id),
+        }
+    }
+
+    pub fn parse(id: &str) -> Option<Self> {
+        match id.split_once('.') {
+            Some(("migrate", target)) => Backend::from_name(target).map(Action::Migrate),
+            Some(("resetstats", user_id)) => user_id.parse().ok().map(Action::ResetStats),
+            Some(("restore", admin_id)) => admin_id.parse().ok().map(Action::Restore),
+            None if id == "lbreset" => Some(Action::ResetLeaderboard),
+            _ => None,
+        }
+    }
+}
+
+/// The data of the yes or no button of the action with `action_id`.
+pub fn confirm_data(action_id: &str, yes: bool) -> String {
+    format!("confirm:{}:{}", action_id, if yes { "yes" } else { "no" })
+}
+
+/// The action id and the answer of a button made by [`confirm_keyboard`].
+pub fn parse_confirm_data(data: &str) -> Option<(&str, bool)> {
+    let (action_id, answer) = data.strip_prefix("confirm:")?.rsplit_once(':')?;
+    match answer {
+        "yes" => Some((action_id, true)),
+        "no" => Some((action_id, false)),
+        _ => None,
+    }
+}
+
+/// Yes and Cancel buttons for the action with `action_id`.
+pub fn confirm_keyboard(action_id: &str) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback("Yes".to_owned(), confirm_data(action_id, true)),
+        InlineKeyboardButton::callback("Cancel".to_owned(), confirm_data(action_id, false)),
+    ]])
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::InlineKeyboardButtonKind;
+
+    use super::*;
+
+    #[test]
+    fn keyboard_buttons_parse_back_to_their_action() {
+        let actions = [
+            Action::ResetLeaderboard,
+            Action::Migrate(Backend::Sqlite),
+            Action::Migrate(Backend::Redis),
+            Action::ResetStats(-42),
+            Action::Restore(7),
+        ];
+        for action in actions {
+            let id = action.id();
+            let buttons = &confirm_keyboard(&id).inline_keyboard[0];
+            let answers: Vec<_> = buttons
+                .iter()
+                .map(|button| match &button.kind {
+                    InlineKeyboardButtonKind::CallbackData(data) => {
+                        let (action_id, yes) = parse_confirm_data(data).unwrap();
+                        (Action::parse(action_id), yes)
+                    }
+                    kind => panic!("unexpected button {:?}", kind),
+                })
+                .collect();
+            assert_eq!(answers, [(Some(action), true), (Some(action), false)]);
+        }
+    }
+
+    #[test]
+    fn other_data_isnt_an_answer() {
+        assert_eq!(parse_confirm_data("confirm:lbreset:maybe"), None);
+        assert_eq!(parse_confirm_data("confirm:lbreset"), None);
+        assert_eq!(parse_confirm_data("set:lbreset:yes"), None);
+        assert_eq!(Action::parse("resetstats.alice"), None);
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// How many callback ids are remembered at most.
+pub const SEEN_CALLBACKS_LIMIT: usize = 256;
+/// How long a callback id is remembered.
+pub const SEEN_CALLBACKS_WINDOW: Duration = Duration::from_secs(60);
+
+/// Recently handled callback query ids, so that a callback Telegram delivers twice
+/// isn't applied twice.
+#[derive(Debug, Default)]
+pub struct SeenCallbacks {
+    ids: HashSet<String>,
+    /// The ids in `ids` with the time they were seen, oldest first.
+    order: VecDeque<(String, Instant)>,
+}
+
+impl SeenCallbacks {
+    /// Remembers `id` and returns whether it was already seen within the window.
+    pub fn check(&mut self, id: &str, now: Instant) -> bool {
+        while let Some((oldest, seen_at)) = self.order.front() {
+            let expired = now.duration_since(*seen_at) >= SEEN_CALLBACKS_WINDOW;
+            if !expired && self.order.len() < SEEN_CALLBACKS_LIMIT {
+                break;
+            }
+            self.ids.remove(oldest);
+            self.order.pop_front();
+        }
+
+        if self.ids.contains(id) {
+            return true;
+        }
+        self.ids.insert(id.to_owned());
+        self.order.push_back((id.to_owned(), now));
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate_within_the_window_is_seen() {
+        let mut seen = SeenCallbacks::default();
+        let start = Instant::now();
+        assert!(!seen.check("a", start));
+        assert!(seen.check("a", start + SEEN_CALLBACKS_WINDOW - Duration::from_secs(1)));
+        assert!(!seen.check("b", start));
+    }
+
+    #[test]
+    fn id_past_the_window_is_forgotten() {
+        let mut seen = SeenCallbacks::default();
+        let start = Instant::now();
+        assert!(!seen.check("a", start));
+        assert!(!seen.check("a", start + SEEN_CALLBACKS_WINDOW));
+    }
+
+    #[test]
+    fn oldest_id_is_evicted_at_the_limit() {
+        let mut seen = SeenCallbacks::default();
+        let now = Instant::now();
+        for id in 0..SEEN_CALLBACKS_LIMIT {
+            assert!(!seen.check(&id.to_string(), now));
+        }
+        assert!(!seen.check("new", now));
+        assert_eq!(seen.order.len(), SEEN_CALLBACKS_LIMIT);
+        assert!(!seen.ids.contains("0"));
+        assert!(seen.ids.contains("1"));
+    }
+}
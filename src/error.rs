@@ -0,0 +1,76 @@
+use std::{num::ParseIntError, string::FromUtf8Error};
+
+use teloxide::{ApiError, DownloadError, RequestError};
+
+use crate::game::MoveError;
+
+/// Everything a handler can fail with.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Telegram request failed: {0}")]
+    Telegram(#[from] RequestError),
+    #[error("downloading a file failed: {0}")]
+    Download(#[from] DownloadError),
+    /// Errors of the dialogue storage, a [`StorageTimeout`](crate::storage::StorageTimeout)
+    /// among them.
+    #[error("storage error: {0}")]
+    Storage(#[from] Box<dyn std::error::Error + Send + Sync>),
+    #[error("not a number: {0}")]
+    Parse(#[from] ParseIntError),
+    #[error("can't serialize the state: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("the state isn't valid UTF-8: {0}")]
+    Utf8(#[from] FromUtf8Error),
+    /// A move the game refused, which the player is told about.
+    #[error("{0}")]
+    Game(#[from] MoveError),
+}
+
+impl Error {
+    /// Whether Telegram refused an edit because the message is gone, most likely
+    /// deleted by someone in the chat, or can't be edited any more.
+    pub fn is_message_gone(&self) -> bool {
+        matches!(
+            self,
+            Error::Telegram(RequestError::Api(ApiError::MessageToEditNotFound | ApiError::MessageCantBeEdited))
+        )
+    }
+
+    /// Whether Telegram refused an edit because the message already shows what it was
+    /// edited to.
+    pub fn is_not_modified(&self) -> bool {
+        matches!(self, Error::Telegram(RequestError::Api(ApiError::MessageNotModified)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_error(error: ApiError) -> Error {
+        Error::Telegram(RequestError::Api(error))
+    }
+
+    #[test]
+    fn deleted_message_is_gone() {
+        assert!(api_error(ApiError::MessageToEditNotFound).is_message_gone());
+    }
+
+    #[test]
+    fn message_that_cant_be_edited_is_gone() {
+        assert!(api_error(ApiError::MessageCantBeEdited).is_message_gone());
+    }
+
+    #[test]
+    fn other_errors_arent_a_gone_message() {
+        assert!(!api_error(ApiError::MessageNotModified).is_message_gone());
+        assert!(!api_error(ApiError::Unknown("Bad Request: chat not found".to_owned())).is_message_gone());
+        assert!(!Error::from("x".parse::<i32>().unwrap_err()).is_message_gone());
+    }
+
+    #[test]
+    fn unchanged_message_is_not_modified() {
+        assert!(api_error(ApiError::MessageNotModified).is_not_modified());
+        assert!(!api_error(ApiError::MessageToEditNotFound).is_not_modified());
+    }
+}
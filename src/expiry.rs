@@ -0,0 +1,591 @@
+use std::{collections::HashMap, time::Duration};
+
+use teloxide::prelude2::*;
+
+use crate::{
+    battle::{battle_text, board_keyboard, forfeit_text, record_result, takeover_keyboard, Battle, BattleGame},
+    game::{Game, GameStatus, Mode, Outcome, AI_PLAYER_ID},
+    render::BoardEdits,
+    tournament,
+    MyActiveGames, MyDialogue, MySettings, MyStats, MyStorage, MyTournaments, State,
+};
+
+/// How often the sweeper looks for abandoned games.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// How often the turn timer looks for players to nudge or to forfeit.
+const TURN_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Board messages of the games that haven't finished yet, keyed by chat and game id.
+///
+/// The dialogue storage can't be iterated, so this index is what lets the sweeper
+/// find abandoned games.
+#[derive(Debug, Default)]
+pub struct ActiveGames {
+    games: HashMap<(i64, u32), ActiveGame>,
+    /// When the current turn of each game started, see [`spawn_turn_timer`].
+    turns: HashMap<(i64, u32), TurnClock>,
+    /// When each user last started a game, see [`ActiveGames::try_start`].
+    starts: HashMap<i64, u64>,
+    /// Seconds between two games started by the same user.
+    start_cooldown: u64,
+    /// How many games may run at once in a chat, see [`ActiveGames::is_full`].
+    max_games_per_chat: usize,
+    /// The [`Game::repetition_limit`] of the games started.
+    repetition_limit: usize,
+    /// The boards edited lately, see [`render::edit_board`](crate::render::edit_board).
+    pub edits: BoardEdits,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ActiveGame {
+    pub message_id: i32,
+    /// Unix time in seconds.
+    pub created_at: u64,
+    /// Waiting for a second player, see [`Game::is_waiting_for_players`].
+    pub waiting: bool,
+}
+
+impl ActiveGame {
+    pub fn new(message_id: i32, game: &Game) -> Self {
+        Self { message_id, created_at: game.created_at, waiting: game.is_waiting_for_players() }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TurnClock {
+    /// Unix time in seconds.
+    since: u64,
+    nudged: bool,
+    /// The player to move was told that their time is almost up.
+    warned: bool,
+    /// The player to move ran out of time and the other one was asked what to do.
+    timed_out: bool,
+    /// When the game was paused, which stops the clock. Unix time in seconds.
+    paused_at: Option<u64>,
+}
+
+impl TurnClock {
+    fn new(since: u64) -> Self {
+        Self { since, nudged: false, warned: false, timed_out: false, paused_at: None }
+    }
+}
+
+impl ActiveGames {
+    pub fn new(start_cooldown: Duration, max_games_per_chat: usize, repetition_limit: usize) -> Self {
+        Self { start_cooldown: start_cooldown.as_secs(), max_games_per_chat, repetition_limit, ..Self::default() }
+    }
+
+    /// How many times a position may occur in the games started before they're a draw.
+    pub fn repetition_limit(&self) -> usize {
+        self.repetition_limit
+    }
+
+    /// Whether `user_id` started their last game at least the cooldown ago.
+    pub fn can_start(&self, user_id: i64, now: u64) -> bool {
+        self.starts.get(&user_id).is_none_or(|&last| now >= last + self.start_cooldown)
+    }
+
+    /// Records that `user_id` starts a game now, unless they started one less than the
+    /// cooldown ago. Returns whether they may start it.
+    pub fn try_start(&mut self, user_id: i64, now: u64) -> bool {
+        if !self.can_start(user_id, now) {
+            return false;
+        }
+        let cooldown = self.start_cooldown;
+        self.starts.retain(|_, &mut last| now < last + cooldown);
+        self.starts.insert(user_id, now);
+        true
+    }
+
+    /// Adds a game or moves it to another board message. A game that is already known
+    /// keeps its turn clock.
+    pub fn insert(&mut self, chat_id: i64, game_id: u32, game: ActiveGame) {
+        self.games.insert((chat_id, game_id), game);
+        self.turns.entry((chat_id, game_id)).or_insert(TurnClock::new(crate::now()));
+    }
+
+    pub fn remove(&mut self, chat_id: i64, game_id: u32) {
+        self.games.remove(&(chat_id, game_id));
+        self.turns.remove(&(chat_id, game_id));
+    }
+
+    /// Whether `chat_id` already runs as many games as it may.
+    pub fn is_full(&self, chat_id: i64) -> bool {
+        self.games.keys().filter(|&&(chat, _)| chat == chat_id).count() >= self.max_games_per_chat
+    }
+
+    /// How many chats have a game running.
+    pub fn chat_count(&self) -> usize {
+        self.games.keys().map(|&(chat_id, _)| chat_id).collect::<std::collections::HashSet<_>>().len()
+    }
+
+    pub fn remove_chat(&mut self, chat_id: i64) {
+        self.games.retain(|&(chat, _), _| chat != chat_id);
+        self.turns.retain(|&(chat, _), _| chat != chat_id);
+    }
+
+    /// Restarts the turn clock of a game, after a move or when it's resumed.
+    pub fn start_turn(&mut self, chat_id: i64, game_id: u32, now: u64) {
+        if let Some(clock) = self.turns.get_mut(&(chat_id, game_id)) {
+            *clock = TurnClock::new(now);
+        }
+    }
+
+    /// Stops the turn clock of a game that is paused, see [`ActiveGames::resume_turn`].
+    pub fn pause_turn(&mut self, chat_id: i64, game_id: u32, now: u64) {
+        if let Some(clock) = self.turns.get_mut(&(chat_id, game_id)) {
+            clock.paused_at.get_or_insert(now);
+        }
+    }
+
+    /// Lets the clock of a resumed game run on from where [`ActiveGames::pause_turn`]
+    /// stopped it. A clock that wasn't stopped, e.g. because the bot restarted while the
+    /// game was paused, starts over.
+    pub fn resume_turn(&mut self, chat_id: i64, game_id: u32, now: u64) {
+        if let Some(clock) = self.turns.get_mut(&(chat_id, game_id)) {
+            match clock.paused_at.take() {
+                Some(paused_at) => clock.since += now.saturating_sub(paused_at),
+                None => *clock = TurnClock::new(now),
+            }
+        }
+    }
+
+    /// Notes that both players of a game took their seats, so that it no longer expires
+    /// as a lobby.
+    pub fn set_seated(&mut self, chat_id: i64, game_id: u32) {
+        if let Some(game) = self.games.get_mut(&(chat_id, game_id)) {
+            game.waiting = false;
+        }
+    }
+
+    fn set_nudged(&mut self, chat_id: i64, game_id: u32) {
+        if let Some(clock) = self.turns.get_mut(&(chat_id, game_id)) {
+            clock.nudged = true;
+        }
+    }
+
+    fn set_warned(&mut self, chat_id: i64, game_id: u32) {
+        if let Some(clock) = self.turns.get_mut(&(chat_id, game_id)) {
+            clock.warned = true;
+        }
+    }
+
+    fn set_timed_out(&mut self, chat_id: i64, game_id: u32) {
+        if let Some(clock) = self.turns.get_mut(&(chat_id, game_id)) {
+            clock.timed_out = true;
+        }
+    }
+
+    /// How many seconds of a `timer` second move timer the player to move of a game has
+    /// left, if its game has a clock. A paused game keeps what it had left when paused.
+    pub fn seconds_left(&self, chat_id: i64, game_id: u32, timer: u64, now: u64) -> Option<u64> {
+        let clock = self.turns.get(&(chat_id, game_id))?;
+        let elapsed = clock.paused_at.unwrap_or(now).saturating_sub(clock.since);
+        Some(if clock.timed_out { 0 } else { timer.saturating_sub(elapsed) })
+    }
+
+    /// Whether the player to move ran out of time, so that the other player may let the
+    /// bot take over or claim the win.
+    pub fn has_timed_out(&self, chat_id: i64, game_id: u32) -> bool {
+        self.turns.get(&(chat_id, game_id)).is_some_and(|clock| clock.timed_out)
+    }
+
+    fn turn_clocks(&self) -> Vec<((i64, u32), ActiveGame, TurnClock)> {
+        self.turns
+            .iter()
+            .filter_map(|(key, &clock)| Some((*key, *self.games.get(key)?, clock)))
+            .collect()
+    }
+
+    /// Removes and returns the games created before `deadline`, and those created before
+    /// `lobby_deadline` that are still waiting for a second player.
+    fn take_expired(&mut self, deadline: u64, lobby_deadline: u64) -> Vec<((i64, u32), ActiveGame)> {
+        let expired: Vec<_> = self
+            .games
+            .iter()
+            .filter(|(_, game)| game.created_at < deadline || (game.waiting && game.created_at < lobby_deadline))
+            .map(|(&key, &game)| (key, game))
+            .collect();
+        for (key, _) in &expired {
+            self.games.remove(key);
+            self.turns.remove(key);
+        }
+        expired
+    }
+}
+
+/// Periodically resets the battles that are older than `max_age`, and those that nobody
+/// joined within `lobby_timeout`. The move timer never forfeits the latter, see
+/// [`TurnTimer::battle`].
+pub fn spawn_sweeper(
+    bot: AutoSend<Bot>,
+    storage: MyStorage,
+    active_games: MyActiveGames,
+    settings: MySettings,
+    max_age: Duration,
+    lobby_timeout: Duration,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let now = crate::now();
+            let (deadline, lobby_deadline) =
+                (now.saturating_sub(max_age.as_secs()), now.saturating_sub(lobby_timeout.as_secs()));
+            let expired = active_games.lock().unwrap().take_expired(deadline, lobby_deadline);
+            for ((chat_id, game_id), game) in expired {
+                let delete = settings.lock().unwrap().prefs(chat_id).delete_boards;
+                let text = if game.created_at < deadline {
+                    "Game expired due to inactivity"
+                } else {
+                    "Game expired, nobody joined in time"
+                };
+                if let Err(err) = expire_game(&bot, &storage, chat_id, game_id, game, delete, text).await {
+                    log::error!("Failed to expire the game in chat {}: {}", chat_id, err);
+                }
+            }
+        }
+    });
+}
+
+/// Every few seconds, nudges the players who are taking longer than the chat's
+/// `nudge_timer` to move, once per turn, and deals with the players who run out of the
+/// chat's `move_timer`: against another user, that user chooses between letting the bot
+/// take over and claiming the win, otherwise the game is forfeited. `warning` before the
+/// `move_timer` runs out, the player is warned once.
+pub fn spawn_turn_timer(
+    bot: AutoSend<Bot>,
+    storage: MyStorage,
+    active_games: MyActiveGames,
+    settings: MySettings,
+    stats: MyStats,
+    tournaments: MyTournaments,
+    warning: Duration,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TURN_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let now = crate::now();
+            let clocks = active_games.lock().unwrap().turn_clocks();
+            let running = clocks.into_iter().filter(|(_, _, clock)| !clock.timed_out && clock.paused_at.is_none());
+            for ((chat_id, game_id), game, clock) in running {
+                let prefs = settings.lock().unwrap().prefs(chat_id);
+                let idle = now.saturating_sub(clock.since);
+                let forfeit = prefs.move_timer.is_some_and(|secs| idle >= secs);
+                // Timers no longer than the warning would warn right away.
+                let warn = prefs
+                    .move_timer
+                    .filter(|&secs| !clock.warned && secs > warning.as_secs() && idle + warning.as_secs() >= secs);
+                let nudge = !clock.nudged && prefs.nudge_timer.is_some_and(|secs| idle >= secs);
+                if !forfeit && warn.is_none() && !nudge {
+                    continue;
+                }
+                let timer = TurnTimer { bot: &bot, storage: &storage, active_games: &active_games, chat_id, game_id };
+                let result = match warn {
+                    _ if forfeit => timer.forfeit(game, &stats, &tournaments).await,
+                    Some(secs) => timer.warn(game, secs - idle).await,
+                    None => timer.nudge(game).await,
+                };
+                if let Err(err) = result {
+                    log::error!("Failed to run the turn timer in chat {}: {}", chat_id, err);
+                }
+            }
+        }
+    });
+}
+
+/// A game whose turn clock ran out.
+struct TurnTimer<'a> {
+    bot: &'a AutoSend<Bot>,
+    storage: &'a MyStorage,
+    active_games: &'a MyActiveGames,
+    chat_id: i64,
+    game_id: u32,
+}
+
+impl TurnTimer<'_> {
+    /// The chat's battle, if the game is still on `game`'s board and waits for a user to
+    /// move. The clock of a game nobody can move in because a seat is free is restarted
+    /// instead: the clock only runs once both players are seated, and a game nobody joins
+    /// is left to the sweeper. A paused game keeps its stopped clock.
+    async fn battle(&self, game: ActiveGame) -> Result<Option<Battle>, crate::Error> {
+        let battle = match self.storage.clone().get_dialogue(self.chat_id).await? {
+            Some(State::Battle(battle)) => battle,
+            _ => return Ok(None),
+        };
+        let (playing, waiting) = match battle.games.get(&self.game_id) {
+            Some(entry) if entry.message_id == game.message_id => {
+                let game = &entry.game;
+                let playing = game.status == GameStatus::Playing;
+                let to_move = game.player(game.turn).is_some_and(|p| p.id != AI_PLAYER_ID);
+                (playing, playing && !game.is_waiting_for_players() && to_move)
+            }
+            _ => return Ok(None),
+        };
+        if !waiting {
+            if playing {
+                self.active_games.lock().unwrap().start_turn(self.chat_id, self.game_id, crate::now());
+            }
+            return Ok(None);
+        }
+        Ok(Some(battle))
+    }
+
+    async fn nudge(&self, game: ActiveGame) -> crate::HandlerResult {
+        let battle = match self.battle(game).await? {
+            Some(battle) => battle,
+            None => return Ok(()),
+        };
+        let entry = &battle.games[&self.game_id];
+        let player = entry.game.player(entry.game.turn).expect("checked by battle()");
+        // Once per turn, even if the edit fails.
+        self.active_games.lock().unwrap().set_nudged(self.chat_id, self.game_id);
+        let text = format!("{}\n\n⏰ {}, it's your turn", battle_text(&entry.game), player.mention());
+        self.bot
+            .edit_message_text(self.chat_id, game.message_id, text)
+            .reply_markup(board_keyboard(self.game_id, &entry.game))
+            .await?;
+        Ok(())
+    }
+
+    async fn warn(&self, game: ActiveGame, left: u64) -> crate::HandlerResult {
+        let battle = match self.battle(game).await? {
+            Some(battle) => battle,
+            None => return Ok(()),
+        };
+        let entry = &battle.games[&self.game_id];
+        let player = entry.game.player(entry.game.turn).expect("checked by battle()");
+        // Once per turn, even if the edit fails.
+        self.active_games.lock().unwrap().set_warned(self.chat_id, self.game_id);
+        let text = format!("{}\n\n⏳ {}, {} seconds left!", battle_text(&entry.game), player.mention(), left);
+        self.bot
+            .edit_message_text(self.chat_id, game.message_id, text)
+            .reply_markup(board_keyboard(self.game_id, &entry.game))
+            .await?;
+        Ok(())
+    }
+
+    async fn forfeit(&self, game: ActiveGame, stats: &MyStats, tournaments: &MyTournaments) -> crate::HandlerResult {
+        let mut battle = match self.battle(game).await? {
+            Some(battle) => battle,
+            None => return Ok(()),
+        };
+        let entry = &battle.games[&self.game_id];
+        if entry.game.mode == Mode::TwoPlayer && entry.game.player(entry.game.turn.other()).is_some() {
+            return self.offer_takeover(game, &battle).await;
+        }
+        let entry = battle.games.remove(&self.game_id).expect("checked by battle()");
+        self.active_games.lock().unwrap().remove(self.chat_id, self.game_id);
+        unpin_board(self.bot, self.chat_id, &entry).await;
+        let winner = entry.game.turn.other();
+        record_result(stats, self.chat_id, &entry.game, Outcome::Win(winner));
+        stats.lock().unwrap().record_last_game(self.chat_id, &entry.game, Outcome::Win(winner));
+        self.storage.clone().update_dialogue(self.chat_id, battle.into_state()).await?;
+        self.bot.edit_message_text(self.chat_id, game.message_id, forfeit_text(&entry.game)).await?;
+
+        let dialogue = MyDialogue::new(self.storage.clone(), self.chat_id);
+        let winner = entry.game.player(winner).map(|p| p.id);
+        let (chat_id, game_id) = (self.chat_id, self.game_id);
+        tournament::report_result(self.bot, &dialogue, self.active_games, tournaments, chat_id, game_id, winner).await
+    }
+
+    async fn offer_takeover(&self, game: ActiveGame, battle: &Battle) -> crate::HandlerResult {
+        let entry = &battle.games[&self.game_id];
+        let (late, waiting) = match (entry.game.player(entry.game.turn), entry.game.player(entry.game.turn.other())) {
+            (Some(late), Some(waiting)) => (late, waiting),
+            _ => return Ok(()),
+        };
+        self.active_games.lock().unwrap().set_timed_out(self.chat_id, self.game_id);
+        let text = format!(
+            "{}\n\n⏰ {} ran out of time. {}, let the bot take over, or take the win?",
+            battle_text(&entry.game),
+            late.name,
+            waiting.mention(),
+        );
+        self.bot
+            .edit_message_text(self.chat_id, game.message_id, text)
+            .reply_markup(takeover_keyboard(self.game_id, &entry.game))
+            .await?;
+        Ok(())
+    }
+}
+
+async fn expire_game(
+    bot: &AutoSend<Bot>,
+    storage: &MyStorage,
+    chat_id: i64,
+    game_id: u32,
+    game: ActiveGame,
+    delete: bool,
+    text: &str,
+) -> crate::HandlerResult {
+    match storage.clone().get_dialogue(chat_id).await? {
+        Some(State::Battle(mut battle))
+            if battle.games.get(&game_id).is_some_and(|g| g.message_id == game.message_id) =>
+        {
+            let entry = battle.games.remove(&game_id).expect("matched by the guard");
+            storage.clone().update_dialogue(chat_id, battle.into_state()).await?;
+            unpin_board(bot, chat_id, &entry).await;
+            retire_board(bot, chat_id, game.message_id, delete, text).await?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Pins the board of `entry`, a game that just started, if its chat turned `pin_boards`
+/// on. Without the right to pin, the board simply isn't pinned.
+pub async fn pin_board(bot: &AutoSend<Bot>, settings: &MySettings, chat_id: i64, entry: &mut BattleGame) {
+    if settings.lock().unwrap().prefs(chat_id).pin_boards {
+        entry.pinned = pin_message(bot, chat_id, entry.message_id).await;
+    }
+}
+
+/// Moves the pin of `entry` from its board message `old` to its current one, once the
+/// game was sent again below the latest messages.
+pub async fn move_pin(bot: &AutoSend<Bot>, chat_id: i64, old: i32, entry: &mut BattleGame) {
+    if entry.pinned {
+        unpin_message(bot, chat_id, old).await;
+        entry.pinned = pin_message(bot, chat_id, entry.message_id).await;
+    }
+}
+
+/// Unpins the board of `entry`, a game that just ended, if it was pinned.
+pub async fn unpin_board(bot: &AutoSend<Bot>, chat_id: i64, entry: &BattleGame) {
+    if entry.pinned {
+        unpin_message(bot, chat_id, entry.message_id).await;
+    }
+}
+
+async fn pin_message(bot: &AutoSend<Bot>, chat_id: i64, message_id: i32) -> bool {
+    match bot.pin_chat_message(chat_id, message_id).disable_notification(true).await {
+        Ok(_) => true,
+        Err(err) => {
+            log::info!("Failed to pin the board in chat {}: {}", chat_id, err);
+            false
+        }
+    }
+}
+
+async fn unpin_message(bot: &AutoSend<Bot>, chat_id: i64, message_id: i32) {
+    if let Err(err) = bot.unpin_chat_message(chat_id).message_id(message_id).await {
+        log::info!("Failed to unpin the board in chat {}: {}", chat_id, err);
+    }
+}
+
+/// Deletes the board message of a game that ended without a result if `delete` is set,
+/// and otherwise, or if the bot can't delete it (no permission, or the message is older
+/// than 48 hours), replaces the board with `text`.
+pub async fn retire_board(
+    bot: &AutoSend<Bot>,
+    chat_id: i64,
+    message_id: i32,
+    delete: bool,
+    text: &str,
+) -> crate::HandlerResult {
+    if delete {
+        match bot.delete_message(chat_id, message_id).await {
+            Ok(_) => return Ok(()),
+            Err(err) => log::info!("Editing the board in chat {} instead of deleting it: {}", chat_id, err),
+        }
+    }
+    bot.edit_message_text(chat_id, message_id, text).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use teloxide::dispatching2::dialogue::{InMemStorage, Storage};
+
+    use super::*;
+    use crate::game::{Player, WinCondition, REPETITION_LIMIT};
+
+    fn active_game() -> ActiveGame {
+        ActiveGame { message_id: 1, created_at: 0, waiting: false }
+    }
+
+    #[test]
+    fn chat_is_full_at_its_limit() {
+        let mut games = ActiveGames::new(Duration::ZERO, 2, REPETITION_LIMIT);
+        games.insert(1, 0, active_game());
+        assert!(!games.is_full(1));
+        games.insert(1, 1, active_game());
+        assert!(games.is_full(1));
+        // Other chats have their own limit.
+        assert!(!games.is_full(2));
+
+        games.remove(1, 0);
+        assert!(!games.is_full(1));
+    }
+
+    #[test]
+    fn second_start_waits_for_the_cooldown() {
+        let mut games = ActiveGames::new(Duration::from_secs(10), 2, REPETITION_LIMIT);
+        assert!(games.try_start(1, 100));
+        assert!(!games.try_start(1, 109));
+        // A refused start doesn't push the cooldown back, and other users aren't held up.
+        assert!(games.try_start(2, 109));
+        assert!(games.try_start(1, 110));
+        assert!(!games.can_start(1, 119));
+    }
+
+    /// A game that only its host joined.
+    fn lobby() -> Game {
+        let mut game = Game::new(3, Mode::TwoPlayer, WinCondition::Normal, 100);
+        game.play(Player { id: 1, name: "Alice".to_owned(), username: None }, 4).unwrap();
+        assert!(game.is_waiting_for_players());
+        game
+    }
+
+    #[test]
+    fn waiting_lobby_expires_at_the_lobby_deadline() {
+        let mut games = ActiveGames::new(Duration::ZERO, 3, REPETITION_LIMIT);
+        games.insert(1, 0, ActiveGame::new(1, &lobby()));
+        games.insert(1, 1, ActiveGame { created_at: 100, ..active_game() });
+        games.insert(1, 2, ActiveGame { created_at: 200, ..ActiveGame::new(3, &lobby()) });
+
+        assert!(games.take_expired(50, 100).is_empty());
+        let expired = games.take_expired(50, 150);
+        assert_eq!(expired.iter().map(|(key, _)| *key).collect::<Vec<_>>(), [(1, 0)]);
+        // The seated game of the same age and the newer lobby are left running.
+        assert_eq!(games.turn_clocks().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn turn_timer_leaves_lobbies_to_the_sweeper() {
+        let bot = Bot::new("0:test").set_api_url("http://127.0.0.1:9/".parse().unwrap()).auto_send();
+        let storage: MyStorage = InMemStorage::new().erase();
+        let game = lobby();
+        let entry = ActiveGame::new(1, &game);
+        let mut battle = Battle::new(State::Start);
+        let game_id = battle.next_game_id();
+        battle.games.insert(game_id, BattleGame { message_id: 1, game, pinned: false });
+        storage.clone().update_dialogue(1, State::Battle(battle)).await.unwrap();
+
+        let active_games = Arc::new(Mutex::new(ActiveGames::new(Duration::ZERO, 1, REPETITION_LIMIT)));
+        active_games.lock().unwrap().insert(1, game_id, entry);
+        active_games.lock().unwrap().start_turn(1, game_id, 0);
+
+        let timer = TurnTimer { bot: &bot, storage: &storage, active_games: &active_games, chat_id: 1, game_id };
+        assert!(timer.battle(entry).await.unwrap().is_none());
+        // The clock starts over rather than running out on the host.
+        let left = active_games.lock().unwrap().seconds_left(1, game_id, 30, crate::now());
+        assert!(left.is_some_and(|left| left >= 29));
+    }
+
+    #[test]
+    fn paused_clock_keeps_its_time() {
+        let mut games = ActiveGames::new(Duration::ZERO, 1, REPETITION_LIMIT);
+        games.insert(1, 0, active_game());
+        games.start_turn(1, 0, 100);
+        games.pause_turn(1, 0, 110);
+        assert_eq!(games.seconds_left(1, 0, 30, 500), Some(20));
+
+        games.resume_turn(1, 0, 500);
+        assert_eq!(games.seconds_left(1, 0, 30, 505), Some(15));
+    }
+}
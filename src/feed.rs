@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use teloxide::prelude2::*;
+
+use crate::{
+    battle::board_text,
+    game::{Mark, Outcome},
+    settings::{self, Settings},
+    stats::FeedPost,
+    MySettings, MyStats,
+};
+
+/// The time between two posts to the replay channel. Telegram lets a bot post about 20
+/// messages a minute to the same channel.
+pub const FEED_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Posts the games queued in the stats to `channel_id`, one every [`FEED_INTERVAL`].
+/// A post that fails, e.g. because the bot may not post in the channel, is dropped.
+pub fn spawn_feed(bot: AutoSend<Bot>, stats: MyStats, settings: MySettings, channel_id: i64) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FEED_INTERVAL);
+        loop {
+            interval.tick().await;
+            let post = match stats.lock().unwrap().next_feed_post() {
+                Some(post) => post,
+                None => continue,
+            };
+            let text = feed_text(&post, &settings.lock().unwrap());
+            if let Err(err) = bot.send_message(channel_id, text).await {
+                log::warn!("Failed to post a game to the replay channel {}: {}", channel_id, err);
+            }
+        }
+    });
+}
+
+/// The players, the result and the final board of a finished game. Players who turned
+/// `/anon` on aren't named.
+fn feed_text(post: &FeedPost, settings: &Settings) -> String {
+    let game = &post.game;
+    let name = |mark: Mark| match game.player(mark) {
+        Some(player) if settings.is_anonymous(player.id) => settings::ANONYMOUS,
+        Some(player) => player.name.as_str(),
+        None => "nobody",
+    };
+    let result = match post.outcome {
+        Outcome::Win(mark) => format!("{} {} wins", mark.symbol(), name(mark)),
+        Outcome::Draw => "Draw".to_owned(),
+    };
+    format!(
+        "🎬 {} {} vs {} {}\n{}\n\n{}",
+        Mark::X.symbol(),
+        name(Mark::X),
+        Mark::O.symbol(),
+        name(Mark::O),
+        result,
+        board_text(&game.board),
+    )
+}
@@ -0,0 +1,22 @@
+use std::collections::HashMap;
+
+/// How long a user waits between two `/feedback` messages, in seconds.
+pub const FEEDBACK_INTERVAL: u64 = 60;
+
+/// When each user last sent feedback, so that `/feedback` can't flood the owner.
+#[derive(Debug, Default)]
+pub struct FeedbackLimiter {
+    last_sent: HashMap<i64, u64>,
+}
+
+impl FeedbackLimiter {
+    /// Records feedback of `user_id` at `now`, unless they sent some less than
+    /// [`FEEDBACK_INTERVAL`] ago. Returns whether it may be sent.
+    pub fn try_send(&mut self, user_id: i64, now: u64) -> bool {
+        let allowed = self.last_sent.get(&user_id).is_none_or(|&last| now.saturating_sub(last) >= FEEDBACK_INTERVAL);
+        if allowed {
+            self.last_sent.insert(user_id, now);
+        }
+        allowed
+    }
+}
@@ -0,0 +1,1138 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ai::Difficulty;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Mark {
+    #[default]
+    X,
+    O,
+}
+
+impl Mark {
+    pub fn other(self) -> Self {
+        match self {
+            Mark::X => Mark::O,
+            Mark::O => Mark::X,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Mark::X => "❌",
+            Mark::O => "⭕",
+        }
+    }
+
+    pub fn letter(self) -> &'static str {
+        match self {
+            Mark::X => "X",
+            Mark::O => "O",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Win(Mark),
+    Draw,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameError {
+    OutOfBounds,
+    Occupied,
+}
+
+impl std::fmt::Display for GameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameError::OutOfBounds => write!(f, "There is no such cell"),
+            GameError::Occupied => write!(f, "This cell is already taken"),
+        }
+    }
+}
+
+impl std::error::Error for GameError {}
+
+/// A square board, stored row by row.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Board {
+    size: usize,
+    cells: Vec<Option<Mark>>,
+    /// How many marks in a row complete a line, if fewer than a whole side.
+    #[serde(default)]
+    win_length: Option<usize>,
+}
+
+impl Board {
+    pub fn new(size: usize) -> Self {
+        Self { size, cells: vec![None; size * size], win_length: None }
+    }
+
+    /// A board on which `win_length` marks in a row complete a line, anywhere on it.
+    /// `win_length` is at most `size`, which makes it a plain board.
+    pub fn with_win_length(size: usize, win_length: usize) -> Self {
+        debug_assert!(win_length <= size, "lines fit on the board");
+        let mut board = Board::new(size);
+        board.win_length = Some(win_length).filter(|&k| k < size);
+        board
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// How many marks in a row complete a line: a whole side unless the board was made
+    /// with [`Board::with_win_length`].
+    pub fn win_length(&self) -> usize {
+        self.win_length.unwrap_or(self.size)
+    }
+
+    pub fn cells(&self) -> &[Option<Mark>] {
+        &self.cells
+    }
+
+    /// Whether the cells fill the board and lines fit on it, which a board read from
+    /// elsewhere, e.g. a `/restore`d backup, may not do.
+    pub fn is_consistent(&self) -> bool {
+        self.size > 0 && self.cells.len() == self.size * self.size && (1..=self.size).contains(&self.win_length())
+    }
+
+    pub fn empty_cells(&self) -> impl Iterator<Item = usize> + '_ {
+        self.cells.iter().enumerate().filter(|(_, cell)| cell.is_none()).map(|(i, _)| i)
+    }
+
+    pub fn place(&mut self, cell: usize, mark: Mark) -> Result<(), GameError> {
+        match self.cells.get_mut(cell) {
+            None => Err(GameError::OutOfBounds),
+            Some(Some(_)) => Err(GameError::Occupied),
+            Some(slot) => {
+                *slot = Some(mark);
+                Ok(())
+            }
+        }
+    }
+
+    /// Empties `cell`, to go back to the position before it was played.
+    pub fn clear(&mut self, cell: usize) {
+        if let Some(slot) = self.cells.get_mut(cell) {
+            *slot = None;
+        }
+    }
+
+    /// Every line that completes, as lists of cell indices.
+    pub fn lines(&self) -> Vec<Vec<usize>> {
+        self.named_lines().into_iter().map(|line| self.line_cells(line)).collect()
+    }
+
+    /// All rows, columns and both diagonals or, with a [`Board::win_length`] shorter than
+    /// a side, every run of that length in any of the four directions.
+    pub fn named_lines(&self) -> Vec<Line> {
+        if let Some(k) = self.win_length {
+            let n = self.size;
+            let mut lines = Vec::new();
+            for (row, column) in (0..n).flat_map(|row| (0..n).map(move |column| (row, column))) {
+                let fits = |direction| match direction {
+                    Direction::Across => column + k <= n,
+                    Direction::Down => row + k <= n,
+                    Direction::DownRight => row + k <= n && column + k <= n,
+                    Direction::DownLeft => row + k <= n && column + 1 >= k,
+                };
+                let runs = Direction::ALL.into_iter().filter(|&direction| fits(direction));
+                lines.extend(runs.map(|direction| Line::Run { row, column, direction }));
+            }
+            return lines;
+        }
+        let mut lines = Vec::with_capacity(2 * self.size + 2);
+        for i in 0..self.size {
+            lines.push(Line::Row(i));
+            lines.push(Line::Column(i));
+        }
+        lines.push(Line::Diagonal);
+        lines.push(Line::AntiDiagonal);
+        lines
+    }
+
+    pub fn line_cells(&self, line: Line) -> Vec<usize> {
+        let n = self.size;
+        match line {
+            Line::Row(i) => (0..n).map(|j| i * n + j).collect(),
+            Line::Column(i) => (0..n).map(|j| j * n + i).collect(),
+            Line::Diagonal => (0..n).map(|i| i * n + i).collect(),
+            Line::AntiDiagonal => (0..n).map(|i| i * n + n - 1 - i).collect(),
+            Line::Run { row, column, direction } => (0..self.win_length())
+                .map(|i| match direction {
+                    Direction::Across => row * n + column + i,
+                    Direction::Down => (row + i) * n + column,
+                    Direction::DownRight => (row + i) * n + column + i,
+                    Direction::DownLeft => (row + i) * n + column - i,
+                })
+                .collect(),
+        }
+    }
+
+    /// The first line filled with a single mark, and that mark.
+    pub fn completed_line(&self) -> Option<(Line, Mark)> {
+        self.named_lines().into_iter().find_map(|line| {
+            let cells = self.line_cells(line);
+            let first = self.cells[cells[0]]?;
+            cells.iter().all(|&i| self.cells[i] == Some(first)).then_some((line, first))
+        })
+    }
+
+    /// A hash of the marks on the board together with the side to move, so that equal
+    /// positions hash equally.
+    pub fn position_hash(&self, to_move: Mark) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.cells.hash(&mut hasher);
+        to_move.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// A row or column, counted from the top left from 0, or a diagonal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Line {
+    Row(usize),
+    Column(usize),
+    /// From the top left to the bottom right.
+    Diagonal,
+    /// From the top right to the bottom left.
+    AntiDiagonal,
+    /// [`Board::win_length`] cells from the one at `row` and `column`, on boards where
+    /// that is shorter than a side.
+    Run { row: usize, column: usize, direction: Direction },
+}
+
+/// Which way a [`Line::Run`] goes from its first cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Direction {
+    Across,
+    Down,
+    DownRight,
+    DownLeft,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 4] = [Direction::Across, Direction::Down, Direction::DownRight, Direction::DownLeft];
+
+    fn arrow(self) -> &'static str {
+        match self {
+            Direction::Across => "→",
+            Direction::Down => "↓",
+            Direction::DownRight => "↘",
+            Direction::DownLeft => "↙",
+        }
+    }
+}
+
+impl std::fmt::Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Line::Row(i) => write!(f, "row {}", i + 1),
+            Line::Column(i) => write!(f, "column {}", i + 1),
+            Line::Diagonal => write!(f, "diagonal ↘"),
+            Line::AntiDiagonal => write!(f, "diagonal ↙"),
+            Line::Run { row, column, direction } => {
+                write!(f, "{} from row {}, column {}", direction.arrow(), row + 1, column + 1)
+            }
+        }
+    }
+}
+
+/// Parses boards written row by row, rows separated by `/`, with `X`, `O` and `.` for
+/// an empty cell: `X.O/.X./O.X`.
+impl std::str::FromStr for Board {
+    type Err = ParseBoardError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rows: Vec<&str> = s.trim().split('/').collect();
+        let size = rows.len();
+        let mut board = Board::new(size);
+        for (row, cells) in rows.iter().enumerate() {
+            if cells.chars().count() != size {
+                return Err(ParseBoardError::NotSquare { row });
+            }
+            for (col, c) in cells.chars().enumerate() {
+                board.cells[row * size + col] = match c.to_ascii_uppercase() {
+                    'X' => Some(Mark::X),
+                    'O' => Some(Mark::O),
+                    '.' => None,
+                    _ => return Err(ParseBoardError::BadCell(c)),
+                };
+            }
+        }
+        Ok(board)
+    }
+}
+
+/// Writes the board in the format [`Board::from_str`](std::str::FromStr) reads.
+impl std::fmt::Display for Board {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, row) in self.cells.chunks(self.size).enumerate() {
+            if i > 0 {
+                write!(f, "/")?;
+            }
+            for cell in row {
+                let c = match cell {
+                    Some(Mark::X) => 'X',
+                    Some(Mark::O) => 'O',
+                    None => '.',
+                };
+                write!(f, "{}", c)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseBoardError {
+    /// The row with this index doesn't have as many cells as there are rows.
+    NotSquare { row: usize },
+    BadCell(char),
+}
+
+impl std::fmt::Display for ParseBoardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseBoardError::NotSquare { row } => {
+                write!(f, "row {} doesn't have as many cells as there are rows", row + 1)
+            }
+            ParseBoardError::BadCell(c) => write!(f, "{:?} isn't a cell, use X, O or .", c),
+        }
+    }
+}
+
+impl std::error::Error for ParseBoardError {}
+
+/// How many times a position may occur before the game is declared a draw, unless
+/// `REPETITION_LIMIT` sets another limit.
+pub const REPETITION_LIMIT: usize = 3;
+
+fn default_repetition_limit() -> usize {
+    REPETITION_LIMIT
+}
+
+/// Whether the last position in `history` has occurred at least `limit` times.
+///
+/// Marks are never removed in tic-tac-toe, so no position recurs there; this is for
+/// games whose moves can come back to an earlier position.
+pub fn is_repetition_draw(history: &[u64], limit: usize) -> bool {
+    match history.last() {
+        Some(last) => history.iter().filter(|&hash| hash == last).count() >= limit,
+        None => false,
+    }
+}
+
+/// What completing a line means.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WinCondition {
+    /// The player who completes a line wins.
+    #[default]
+    Normal,
+    /// The player who completes a line loses.
+    Misere,
+}
+
+/// Returns the outcome of a finished game, or `None` if it can still go on.
+pub fn check_winner(board: &Board, condition: WinCondition) -> Option<Outcome> {
+    if let Some((_, mark)) = board.completed_line() {
+        return Some(match condition {
+            WinCondition::Normal => Outcome::Win(mark),
+            WinCondition::Misere => Outcome::Win(mark.other()),
+        });
+    }
+
+    if board.cells.iter().all(Option::is_some) {
+        Some(Outcome::Draw)
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Player {
+    pub id: i64,
+    pub name: String,
+    /// The Telegram username, without the `@`, to mention the player with.
+    #[serde(default)]
+    pub username: Option<String>,
+}
+
+impl Player {
+    /// How to address the player in a message: an `@` mention if they have a username.
+    pub fn mention(&self) -> String {
+        match &self.username {
+            Some(username) => format!("@{}", username),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// Who plays X and who plays O. A seat stays empty until somebody takes it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Players {
+    x: Option<Player>,
+    o: Option<Player>,
+}
+
+impl Players {
+    pub fn get(&self, mark: Mark) -> Option<&Player> {
+        match mark {
+            Mark::X => self.x.as_ref(),
+            Mark::O => self.o.as_ref(),
+        }
+    }
+
+    /// Puts `player` on the seat of `mark`, or empties it, and returns who sat there.
+    pub fn set(&mut self, mark: Mark, player: Option<Player>) -> Option<Player> {
+        let seat = match mark {
+            Mark::X => &mut self.x,
+            Mark::O => &mut self.o,
+        };
+        std::mem::replace(seat, player)
+    }
+
+    /// Seats `player` on the first free seat, X before O, and returns their mark. Nobody
+    /// gets both seats this way.
+    pub fn assign(&mut self, player: Player) -> Result<Mark, JoinError> {
+        if let Some(mark) = self.mark_of(player.id) {
+            return Err(JoinError::AlreadySeated(mark));
+        }
+        let mark = [Mark::X, Mark::O].into_iter().find(|&mark| self.get(mark).is_none()).ok_or(JoinError::NoFreeSeat)?;
+        self.set(mark, Some(player));
+        Ok(mark)
+    }
+
+    /// The mark `user_id` plays; X if they play both sides.
+    pub fn mark_of(&self, user_id: i64) -> Option<Mark> {
+        self.mark_of_preferring(user_id, Mark::X)
+    }
+
+    /// The mark `user_id` plays; `first` if they play both sides, as in pass-and-play.
+    pub fn mark_of_preferring(&self, user_id: i64, first: Mark) -> Option<Mark> {
+        [first, first.other()].into_iter().find(|&mark| self.get(mark).is_some_and(|p| p.id == user_id))
+    }
+
+    /// The player of the mark other than `mark`.
+    pub fn other(&self, mark: Mark) -> Option<&Player> {
+        self.get(mark.other())
+    }
+
+    pub fn contains(&self, user_id: i64) -> bool {
+        self.iter().any(|p| p.id == user_id)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.x.is_some() && self.o.is_some()
+    }
+
+    /// The seated players, X first.
+    pub fn iter(&self) -> impl Iterator<Item = &Player> {
+        self.x.iter().chain(&self.o)
+    }
+}
+
+/// Who plays the O seat.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mode {
+    /// Two different users, each taking a seat by making a move.
+    TwoPlayer,
+    /// One user plays both sides, e.g. alone in a private chat.
+    PassAndPlay,
+    /// The bot plays O, or the side it took over or was given in a fork.
+    VsAi(Difficulty),
+}
+
+/// The id the AI player is seated with. Telegram user ids are always positive.
+pub const AI_PLAYER_ID: i64 = 0;
+
+/// How long, in seconds, the opponent has to accept a `/takeback`.
+pub const TAKEBACK_TIMEOUT: u64 = 60;
+
+pub fn ai_player() -> Player {
+    Player { id: AI_PLAYER_ID, name: "🤖 Bot".to_owned(), username: None }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameStatus {
+    Playing,
+    Paused { by: String },
+}
+
+/// A two-player game. Players take the X and O seats by making their first move.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Game {
+    pub board: Board,
+    pub turn: Mark,
+    pub players: Players,
+    pub status: GameStatus,
+    pub mode: Mode,
+    pub condition: WinCondition,
+    /// Unix time in seconds.
+    pub created_at: u64,
+    /// The hash of every position reached so far, see [`is_repetition_draw`].
+    #[serde(default)]
+    pub positions: Vec<u64>,
+    /// The cells played so far, in order.
+    #[serde(default)]
+    pub moves: Vec<usize>,
+    /// The mark that moved first.
+    #[serde(default)]
+    pub first: Mark,
+    /// Show the AI's assessment of the position on the board, see `/battle learn`.
+    #[serde(default)]
+    pub learn: bool,
+    /// A `/resize` waiting for the other player's consent.
+    #[serde(default)]
+    pub pending_resize: Option<ResizeRequest>,
+    /// A `/takeback` waiting for the opponent's consent.
+    #[serde(default)]
+    pub pending_takeback: Option<TakebackRequest>,
+    /// The user who started the game, who may choose the first mark.
+    #[serde(default)]
+    pub host: Option<i64>,
+    /// Seeds the AI's random choices, set with `/seed` to make a game reproducible.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// The player the AI replaced, see [`Game::take_over`].
+    #[serde(default)]
+    pub taken_over: Option<Player>,
+    /// The line that ended the game, once it's over.
+    #[serde(default)]
+    pub winning_line: Option<Line>,
+    /// How many times a position may occur before the game is a draw, see
+    /// [`is_repetition_draw`].
+    #[serde(default = "default_repetition_limit")]
+    pub repetition_limit: usize,
+}
+
+impl Game {
+    pub fn new(size: usize, mode: Mode, condition: WinCondition, created_at: u64) -> Self {
+        let mut players = Players::default();
+        if let Mode::VsAi(_) = mode {
+            players.set(Mark::O, Some(ai_player()));
+        }
+        Self {
+            board: Board::new(size),
+            turn: Mark::X,
+            players,
+            status: GameStatus::Playing,
+            mode,
+            condition,
+            created_at,
+            positions: Vec::new(),
+            moves: Vec::new(),
+            first: Mark::X,
+            learn: false,
+            pending_resize: None,
+            pending_takeback: None,
+            host: None,
+            seed: None,
+            taken_over: None,
+            winning_line: None,
+            repetition_limit: REPETITION_LIMIT,
+        }
+    }
+
+    /// A two-player game that starts from `board`, with the side that has fewer marks to
+    /// move, X if they have as many.
+    pub fn from_position(board: Board, created_at: u64) -> Result<Self, PositionError> {
+        let count = |mark: Mark| board.cells().iter().filter(|&&cell| cell == Some(mark)).count();
+        let (x, o) = (count(Mark::X), count(Mark::O));
+        if x.abs_diff(o) > 1 {
+            return Err(PositionError::MarkCounts);
+        }
+        if board.completed_line().is_some() || board.empty_cells().next().is_none() {
+            return Err(PositionError::Finished);
+        }
+        let turn = if x > o { Mark::O } else { Mark::X };
+        let mut game = Game::new(board.size(), Mode::TwoPlayer, WinCondition::Normal, created_at);
+        game.positions.push(board.position_hash(turn));
+        game.board = board;
+        game.turn = turn;
+        game.first = turn;
+        Ok(game)
+    }
+
+    /// Seats `x` and `o` up front, so that nobody else can take their places.
+    pub fn seated(mut self, x: Player, o: Player) -> Self {
+        self.players = Players { x: Some(x), o: Some(o) };
+        self
+    }
+
+    /// A practice game against the AI from this game's position: the user takes the side
+    /// to move and the AI the other one.
+    pub fn fork(&self, created_at: u64) -> Self {
+        let mut fork = Game::new(self.board.size(), Mode::VsAi(Difficulty::Medium), self.condition, created_at);
+        fork.board = self.board.clone();
+        fork.turn = self.turn;
+        fork.positions = self.positions.clone();
+        fork.moves = self.moves.clone();
+        fork.learn = self.learn;
+        fork.repetition_limit = self.repetition_limit;
+        fork.players = Players::default();
+        fork.players.set(self.turn.other(), Some(ai_player()));
+        fork
+    }
+
+    /// Seats `player` in the first free seat. One user never gets both seats of a
+    /// two-player game.
+    pub fn join(&mut self, player: Player) -> Result<(), JoinError> {
+        if let Some(mark) = self.mark_of(player.id) {
+            return Err(JoinError::AlreadySeated(mark));
+        }
+        if self.mode != Mode::TwoPlayer {
+            return Err(JoinError::NoFreeSeat);
+        }
+        self.players.assign(player)?;
+        Ok(())
+    }
+
+    /// Seats the AI in place of the player to move, who abandoned the game, so that the
+    /// other player can finish it against the AI. Returns `false` unless this is a game
+    /// between two seated users.
+    pub fn take_over(&mut self) -> bool {
+        if self.mode != Mode::TwoPlayer || !self.players.is_full() {
+            return false;
+        }
+        self.taken_over = self.players.set(self.turn, Some(ai_player()));
+        self.mode = Mode::VsAi(Difficulty::Medium);
+        true
+    }
+
+    /// A fresh game on a board of `size` with the same players and rules. Shorter lines
+    /// stay as long as they were if they fit.
+    pub fn restarted(&self, size: usize, created_at: u64) -> Self {
+        let mut game = Game::new(size, self.mode, self.condition, created_at);
+        game.board = match self.board.win_length {
+            Some(k) if k < size => Board::with_win_length(size, k),
+            _ => Board::new(size),
+        };
+        game.players = self.players.clone();
+        game.first = self.first;
+        game.turn = self.first;
+        game.learn = self.learn;
+        game.host = self.host;
+        game.seed = self.seed;
+        game.repetition_limit = self.repetition_limit;
+        game
+    }
+
+    /// The host may switch the first mark until the first move, except against the AI,
+    /// which always plays O second.
+    pub fn can_choose_first(&self) -> bool {
+        self.host.is_some() && self.moves.is_empty() && !matches!(self.mode, Mode::VsAi(_))
+    }
+
+    /// Lets the other mark move first. Returns `false` if it's too late for that.
+    pub fn switch_first(&mut self) -> bool {
+        if !self.can_choose_first() {
+            return false;
+        }
+        self.first = self.first.other();
+        self.turn = self.first;
+        true
+    }
+
+    /// Whether a two-player game still has a free seat. Its move timer doesn't run until
+    /// both players are seated.
+    pub fn is_waiting_for_players(&self) -> bool {
+        self.mode == Mode::TwoPlayer && !self.players.is_full()
+    }
+
+    /// The cell played last, `None` before the first move.
+    pub fn last_move(&self) -> Option<usize> {
+        self.moves.last().copied()
+    }
+
+    pub fn is_player(&self, user_id: i64) -> bool {
+        self.players.contains(user_id)
+    }
+
+    /// The mark `user_id` plays; the side to move if they play both sides.
+    pub fn mark_of(&self, user_id: i64) -> Option<Mark> {
+        self.players.mark_of_preferring(user_id, self.turn)
+    }
+
+    /// How many moves the side of `mark` has made.
+    pub fn moves_by(&self, mark: Mark) -> usize {
+        let moves = self.moves.len();
+        if mark == self.first {
+            moves.div_ceil(2)
+        } else {
+            moves / 2
+        }
+    }
+
+    pub fn player(&self, mark: Mark) -> Option<&Player> {
+        self.players.get(mark)
+    }
+
+    pub fn is_over(&self) -> bool {
+        check_winner(&self.board, self.condition).is_some()
+            || is_repetition_draw(&self.positions, self.repetition_limit)
+    }
+
+    /// Asks to take back the last move of `user_id`, who is to move, and the opponent's
+    /// move since; only the opponent's move if `user_id` hasn't moved yet.
+    pub fn request_takeback(&mut self, user_id: i64, now: u64) -> Result<(), TakebackError> {
+        if self.is_over() {
+            return Err(TakebackError::GameOver);
+        }
+        let opponent = self.player(self.turn.other()).map(|p| p.id);
+        if self.mode != Mode::TwoPlayer || opponent.is_none() || opponent == Some(user_id) {
+            return Err(TakebackError::NoOpponent);
+        }
+        if self.player(self.turn).map(|p| p.id) != Some(user_id) {
+            return Err(TakebackError::NotYourTurn);
+        }
+        if self.moves.is_empty() {
+            return Err(TakebackError::NothingToTakeBack);
+        }
+        if self.pending_takeback.is_some_and(|request| request.expires_at > now) {
+            return Err(TakebackError::AlreadyPending);
+        }
+        let moves = if self.moves_by(self.turn) > 0 { 2 } else { 1 };
+        self.pending_takeback = Some(TakebackRequest {
+            by: user_id,
+            to: self.moves.len() - moves,
+            at: self.moves.len(),
+            expires_at: now + TAKEBACK_TIMEOUT,
+        });
+        Ok(())
+    }
+
+    /// Lets the opponent of whoever asked accept the pending takeback and reverts the
+    /// moves it covers. Returns how many moves were taken back.
+    pub fn accept_takeback(&mut self, user_id: i64, now: u64) -> Result<usize, TakebackError> {
+        let request = self.pending_takeback.ok_or(TakebackError::NothingPending)?;
+        if request.by == user_id {
+            return Err(TakebackError::OwnRequest);
+        }
+        self.pending_takeback = None;
+        if request.expires_at <= now {
+            return Err(TakebackError::Expired);
+        }
+        // A move was made since, so the request no longer describes this position.
+        if request.at != self.moves.len() {
+            return Err(TakebackError::NothingPending);
+        }
+        let count = self.moves.len() - request.to;
+        for cell in self.moves.split_off(request.to) {
+            self.board.clear(cell);
+            self.positions.pop();
+            self.turn = self.turn.other();
+        }
+        self.winning_line = None;
+        Ok(count)
+    }
+
+    /// Lets `player` put the current mark on `cell`, taking a free seat if needed. A
+    /// pass-and-play game is only played by its host, or by whoever took its first seat.
+    pub fn play(&mut self, player: Player, cell: usize) -> Result<Option<Outcome>, MoveError> {
+        if self.is_over() {
+            return Err(MoveError::GameOver);
+        }
+        if let GameStatus::Paused { .. } = self.status {
+            return Err(MoveError::Paused);
+        }
+        if self.mode == Mode::PassAndPlay {
+            let owner = self.host.or_else(|| self.players.iter().next().map(|p| p.id));
+            if owner.is_some_and(|owner| owner != player.id) {
+                return Err(MoveError::NotTheHost);
+            }
+        }
+        let takes_seat = match self.players.get(self.turn) {
+            Some(seated) if seated.id != player.id => return Err(MoveError::NotYourTurn),
+            Some(_) => false,
+            None if self.mode != Mode::PassAndPlay
+                && self.players.other(self.turn).is_some_and(|p| p.id == player.id) =>
+            {
+                return Err(MoveError::AlreadySeated(self.turn.other()))
+            }
+            None => true,
+        };
+
+        self.board.place(cell, self.turn).map_err(MoveError::Game)?;
+        if takes_seat {
+            self.players.set(self.turn, Some(player));
+        }
+        self.turn = self.turn.other();
+        self.moves.push(cell);
+        self.positions.push(self.board.position_hash(self.turn));
+        self.winning_line = self.board.completed_line().map(|(line, _)| line);
+        match check_winner(&self.board, self.condition) {
+            None if is_repetition_draw(&self.positions, self.repetition_limit) => Ok(Some(Outcome::Draw)),
+            outcome => Ok(outcome),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResizeRequest {
+    pub size: usize,
+    /// The player who asked.
+    pub by: i64,
+}
+
+/// A `/takeback` of the moves after the first `to`, asked for when `at` moves were made.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TakebackRequest {
+    pub by: i64,
+    pub to: usize,
+    pub at: usize,
+    /// Unix time in seconds.
+    pub expires_at: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TakebackError {
+    GameOver,
+    NoOpponent,
+    NotYourTurn,
+    NothingToTakeBack,
+    AlreadyPending,
+    NothingPending,
+    OwnRequest,
+    Expired,
+}
+
+impl std::fmt::Display for TakebackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TakebackError::GameOver => write!(f, "The game is over"),
+            TakebackError::NoOpponent => write!(f, "A takeback needs an opponent to agree to it"),
+            TakebackError::NotYourTurn => write!(f, "Only the player to move can ask for a takeback"),
+            TakebackError::NothingToTakeBack => write!(f, "There is no move to take back"),
+            TakebackError::AlreadyPending => write!(f, "A takeback is already waiting for an answer"),
+            TakebackError::NothingPending => write!(f, "Nothing to answer anymore"),
+            TakebackError::OwnRequest => write!(f, "The other player has to accept"),
+            TakebackError::Expired => write!(f, "The takeback request expired"),
+        }
+    }
+}
+
+impl std::error::Error for TakebackError {}
+
+/// Why a position can't start a game, see [`Game::from_position`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PositionError {
+    /// One side has more than one mark more than the other.
+    MarkCounts,
+    /// A line is complete or no cell is free.
+    Finished,
+}
+
+impl std::fmt::Display for PositionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PositionError::MarkCounts => write!(f, "One side can't have more than one mark more than the other"),
+            PositionError::Finished => write!(f, "That game is already over"),
+        }
+    }
+}
+
+impl std::error::Error for PositionError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveError {
+    GameOver,
+    Paused,
+    NotYourTurn,
+    /// The user tried to take the empty seat while sitting in the other one.
+    AlreadySeated(Mark),
+    /// Somebody else than the one playing both sides moved in a pass-and-play game.
+    NotTheHost,
+    Game(GameError),
+}
+
+impl std::fmt::Display for MoveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MoveError::GameOver => write!(f, "The game is over"),
+            MoveError::Paused => write!(f, "Game is paused"),
+            MoveError::NotYourTurn => write!(f, "It's not your turn"),
+            MoveError::AlreadySeated(mark) => write!(f, "You're already playing as {}", mark.letter()),
+            MoveError::NotTheHost => write!(f, "Only the host plays this pass-and-play game"),
+            MoveError::Game(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JoinError {
+    AlreadySeated(Mark),
+    NoFreeSeat,
+}
+
+impl std::fmt::Display for JoinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinError::AlreadySeated(mark) => write!(f, "You're already playing as {}", mark.letter()),
+            JoinError::NoFreeSeat => write!(f, "There is no free seat in that game"),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    use super::*;
+
+    const GAMES: u32 = 2_000;
+
+    fn users() -> Vec<Player> {
+        (1..=3).map(|id| Player { id, name: format!("user {}", id), username: None }).collect()
+    }
+
+    /// Plays one game of random moves by random users, one of whom never gets a seat, and
+    /// checks the turn invariants: marks alternate, a player never moves twice in a row,
+    /// marks only land on empty cells, nothing can be played once the game is over and no
+    /// user holds both seats, even by joining again.
+    fn check_game(rng: &mut impl Rng, users: &[Player]) -> Game {
+        let size = rng.gen_range(3..=5);
+        let condition = *[WinCondition::Normal, WinCondition::Misere].choose(rng).unwrap();
+        let mut game = Game::new(size, Mode::TwoPlayer, condition, 0);
+        let mut last_mover = None;
+        let mut over = false;
+
+        for _ in 0..size * size * 8 {
+            let user = users.choose(rng).unwrap().clone();
+            let cell = rng.gen_range(0..size * size + 1);
+            let before = game.clone();
+            let result = game.play(user.clone(), cell);
+
+            if over {
+                assert!(result.is_err(), "a move was accepted after the game ended");
+                continue;
+            }
+            match result {
+                Ok(outcome) => {
+                    assert_eq!(before.board.cells().get(cell), Some(&None), "a mark landed on a taken cell");
+                    assert_eq!(game.board.cells()[cell], Some(before.turn), "the wrong mark was placed");
+                    assert_eq!(game.turn, before.turn.other(), "the turn didn't pass to the other mark");
+                    assert_ne!(last_mover, Some(user.id), "{} moved twice in a row", user.name);
+                    assert_eq!(game.player(before.turn).map(|p| p.id), Some(user.id), "the mover isn't seated");
+                    assert_eq!(outcome, check_winner(&game.board, condition));
+                    last_mover = Some(user.id);
+                    over = outcome.is_some();
+                }
+                Err(err) => {
+                    assert_eq!(game, before, "a rejected move ({}) changed the game", err);
+                    let holds = |mark: Mark| game.player(mark).map(|p| p.id);
+                    match err {
+                        MoveError::NotYourTurn => {
+                            let seated_elsewhere = holds(game.turn).is_some_and(|id| id != user.id);
+                            assert!(seated_elsewhere, "{} was refused a move they may make", user.name);
+                        }
+                        MoveError::AlreadySeated(mark) => {
+                            assert!(holds(game.turn).is_none(), "{} was refused a free seat", user.name);
+                            assert_eq!(holds(mark), Some(user.id), "{} isn't seated as {:?}", user.name, mark);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        for user in users {
+            let before = game.clone();
+            if let Err(JoinError::AlreadySeated(mark)) = game.join(user.clone()) {
+                assert_eq!(game, before, "{} joining again changed the game", user.name);
+                assert_eq!(game.player(mark).map(|p| p.id), Some(user.id), "{} isn't seated as {:?}", user.name, mark);
+            }
+            let holds = |mark: Mark| game.player(mark).is_some_and(|p| p.id == user.id);
+            assert!(!(holds(Mark::X) && holds(Mark::O)), "{} took both seats", user.name);
+        }
+
+        let x = game.board.cells().iter().filter(|c| **c == Some(Mark::X)).count();
+        let o = game.board.cells().iter().filter(|c| **c == Some(Mark::O)).count();
+        assert!(x == o || x == o + 1, "marks didn't alternate: {} X and {} O", x, o);
+        game
+    }
+
+    /// Hands out seats to random users, some of them more than once, and checks that they
+    /// go to users in turn, X first, and are found by who holds them.
+    fn check_players(rng: &mut impl Rng, users: &[Player]) {
+        let mut players = Players::default();
+        let mut seated: Vec<i64> = Vec::new();
+        for _ in 0..4 {
+            let user = users.choose(rng).unwrap().clone();
+            let before = players.clone();
+            match players.assign(user.clone()) {
+                Ok(mark) => {
+                    assert!(!seated.contains(&user.id), "{} got a second seat", user.name);
+                    let expected = if seated.is_empty() { Mark::X } else { Mark::O };
+                    assert_eq!(mark, expected, "{} wasn't given the first free seat", user.name);
+                    seated.push(user.id);
+                }
+                Err(err) => {
+                    assert_eq!(players, before, "a refused seat ({:?}) changed the players", err);
+                    match err {
+                        JoinError::AlreadySeated(mark) => {
+                            let holder = players.get(mark).map(|p| p.id);
+                            assert_eq!(holder, Some(user.id), "{} isn't seated as {:?}", user.name, mark);
+                        }
+                        JoinError::NoFreeSeat => assert!(players.is_full(), "{} was refused a free seat", user.name),
+                    }
+                }
+            }
+            for (mark, &id) in [Mark::X, Mark::O].into_iter().zip(&seated) {
+                assert_eq!(players.mark_of(id), Some(mark), "user {} isn't found on their seat", id);
+                assert!(players.contains(id), "user {} isn't found among the players", id);
+                assert_ne!(players.other(mark).map(|p| p.id), Some(id), "user {} is their own opponent", id);
+            }
+        }
+    }
+
+    #[test]
+    fn seated_user_is_refused_the_other_seat() {
+        let users = users();
+        let mut players = Players::default();
+        assert_eq!(players.assign(users[0].clone()), Ok(Mark::X));
+        assert_eq!(players.assign(users[0].clone()), Err(JoinError::AlreadySeated(Mark::X)));
+        assert!(players.get(Mark::O).is_none());
+    }
+
+    #[test]
+    fn full_players_refuse_a_third_user() {
+        let users = users();
+        let mut players = Players::default();
+        assert_eq!(players.assign(users[0].clone()), Ok(Mark::X));
+        assert_eq!(players.assign(users[1].clone()), Ok(Mark::O));
+        assert!(players.is_full());
+        assert_eq!(players.assign(users[2].clone()), Err(JoinError::NoFreeSeat));
+        assert!(!players.contains(users[2].id));
+    }
+
+    #[test]
+    fn players_are_found_by_user_and_mark() {
+        let users = users();
+        let mut players = Players::default();
+        players.set(Mark::O, Some(users[1].clone()));
+        assert_eq!(players.mark_of(users[1].id), Some(Mark::O));
+        assert_eq!(players.mark_of(users[0].id), None);
+        assert_eq!(players.other(Mark::X), Some(&users[1]));
+        assert_eq!(players.iter().collect::<Vec<_>>(), [&users[1]]);
+
+        // Pass-and-play: one user on both seats.
+        players.set(Mark::X, Some(users[1].clone()));
+        assert_eq!(players.mark_of(users[1].id), Some(Mark::X));
+        assert_eq!(players.mark_of_preferring(users[1].id, Mark::O), Some(Mark::O));
+    }
+
+    #[test]
+    fn pass_and_play_is_played_by_the_host_alone() {
+        let users = users();
+        let mut game = Game::new(3, Mode::PassAndPlay, WinCondition::Normal, 0);
+        game.host = Some(users[0].id);
+        assert_eq!(game.play(users[1].clone(), 0), Err(MoveError::NotTheHost));
+        game.play(users[0].clone(), 4).unwrap();
+        // The empty O seat isn't free for others to take.
+        let before = game.clone();
+        assert_eq!(game.play(users[1].clone(), 0), Err(MoveError::NotTheHost));
+        assert_eq!(game, before);
+        game.play(users[0].clone(), 0).unwrap();
+        assert_eq!(game.player(Mark::O).map(|p| p.id), Some(users[0].id));
+    }
+
+    #[test]
+    fn pass_and_play_without_a_host_keeps_to_its_first_player() {
+        let users = users();
+        let mut game = Game::new(3, Mode::PassAndPlay, WinCondition::Normal, 0);
+        game.play(users[1].clone(), 4).unwrap();
+        assert_eq!(game.play(users[2].clone(), 0), Err(MoveError::NotTheHost));
+        game.play(users[1].clone(), 0).unwrap();
+    }
+
+    #[test]
+    fn third_occurrence_of_the_last_position_is_a_draw() {
+        assert!(!is_repetition_draw(&[], 3));
+        assert!(!is_repetition_draw(&[1, 2, 1, 2], 3));
+        assert!(is_repetition_draw(&[1, 2, 1, 2, 1], 3));
+        // Only the last position counts.
+        assert!(!is_repetition_draw(&[1, 1, 1, 2], 3));
+        assert!(is_repetition_draw(&[1, 2, 1], 2));
+    }
+
+    #[test]
+    fn random_games_keep_turn_invariants() {
+        let mut rng = StdRng::seed_from_u64(0x7e57);
+        let users = users();
+        for _ in 0..GAMES {
+            check_game(&mut rng, &users);
+        }
+    }
+
+    #[test]
+    fn random_seats_go_to_users_in_turn() {
+        let mut rng = StdRng::seed_from_u64(0x5ea7);
+        let users = users();
+        for _ in 0..GAMES {
+            check_players(&mut rng, &users);
+        }
+    }
+
+    #[test]
+    fn runs_of_every_length_are_found() {
+        for n in 3..=8 {
+            assert_eq!(Board::new(n).named_lines().len(), 2 * n + 2, "plain {0}×{0}", n);
+            for k in 3..n {
+                let runs = n - k + 1;
+                let expected = 2 * n * runs + 2 * runs * runs;
+                assert_eq!(Board::with_win_length(n, k).named_lines().len(), expected, "{0}×{0}, {1} in a row", n, k);
+            }
+        }
+    }
+
+    #[test]
+    fn run_at_the_edge_wins_and_wrapped_run_does_not() {
+        let mut board = Board::with_win_length(5, 3);
+        for cell in [2, 3, 4] {
+            board.place(cell, Mark::X).unwrap();
+        }
+        assert!(board.completed_line().is_some_and(|(_, mark)| mark == Mark::X));
+
+        // 3, 4 and 5 follow each other, but 5 starts the next row.
+        let mut board = Board::with_win_length(5, 3);
+        for cell in [3, 4, 5] {
+            board.place(cell, Mark::X).unwrap();
+        }
+        assert_eq!(board.completed_line(), None);
+    }
+
+    #[test]
+    fn resize_keeps_only_an_explicit_win_length() {
+        let game = Game::new(3, Mode::TwoPlayer, WinCondition::Normal, 0);
+        assert_eq!(game.restarted(5, 0).board.win_length(), 5);
+
+        let mut game = Game::new(5, Mode::TwoPlayer, WinCondition::Normal, 0);
+        game.board = Board::with_win_length(5, 4);
+        assert_eq!(game.restarted(6, 0).board.win_length(), 4);
+        assert_eq!(game.restarted(4, 0).board.win_length(), 4);
+        assert_eq!(game.restarted(3, 0).board.win_length(), 3);
+    }
+
+    #[test]
+    fn played_boards_survive_a_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0xb0a7d);
+        let users = users();
+        for _ in 0..GAMES {
+            let board = check_game(&mut rng, &users).board;
+            assert_eq!(board.to_string().parse(), Ok(board.clone()), "the board didn't survive a round trip");
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::{HashMap, VecDeque};
+
+/// How many previous numbers `/undo` can go back through, unless the config or the
+/// chat says otherwise.
+pub const HISTORY_LIMIT: usize = 20;
+/// The most numbers a chat can keep, whatever its limit.
+pub const MAX_HISTORY_LIMIT: usize = 200;
+
+/// Numbers each chat had before its last operations, newest last.
+#[derive(Debug)]
+pub struct History {
+    entries: HashMap<i64, VecDeque<i32>>,
+    /// Limits set with `/sethistory`, by chat id.
+    limits: HashMap<i64, usize>,
+    default_limit: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::with_limit(HISTORY_LIMIT)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LimitOutOfRange;
+
+impl std::fmt::Display for LimitOutOfRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Use /sethistory with a number from 1 to {}", MAX_HISTORY_LIMIT)
+    }
+}
+
+impl std::error::Error for LimitOutOfRange {}
+
+/// What `/backup` saves of [`History`]: the limits of the chats, but not their
+/// numbers. Maps are lists of entries, since JSON objects only take string keys.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct HistoryBackup {
+    limits: Vec<(i64, usize)>,
+}
+
+impl HistoryBackup {
+    /// Checks that `/sethistory` would have taken the limits.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if !self.limits.iter().all(|&(_, limit)| (1..=MAX_HISTORY_LIMIT).contains(&limit)) {
+            return Err("it has a history limit /sethistory wouldn't take");
+        }
+        Ok(())
+    }
+}
+
+impl History {
+    /// Keeps `default_limit` numbers per chat, at most [`MAX_HISTORY_LIMIT`].
+    pub fn with_limit(default_limit: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            limits: HashMap::new(),
+            default_limit: default_limit.min(MAX_HISTORY_LIMIT),
+        }
+    }
+
+    pub fn limit(&self, chat_id: i64) -> usize {
+        self.limits.get(&chat_id).copied().unwrap_or(self.default_limit)
+    }
+
+    /// Sets how many numbers the chat keeps. The numbers over a lowered limit are
+    /// dropped on the next push.
+    pub fn set_limit(&mut self, chat_id: i64, limit: usize) -> Result<(), LimitOutOfRange> {
+        if !(1..=MAX_HISTORY_LIMIT).contains(&limit) {
+            return Err(LimitOutOfRange);
+        }
+        self.limits.insert(chat_id, limit);
+        Ok(())
+    }
+
+    pub fn push(&mut self, chat_id: i64, previous: i32) {
+        let limit = self.limit(chat_id);
+        let entries = self.entries.entry(chat_id).or_default();
+        while entries.len() >= limit {
+            entries.pop_front();
+        }
+        entries.push_back(previous);
+    }
+
+    /// The number the chat had before its current one, if it's still kept.
+    pub fn last(&self, chat_id: i64) -> Option<i32> {
+        self.entries.get(&chat_id)?.back().copied()
+    }
+
+    pub fn pop(&mut self, chat_id: i64) -> Option<i32> {
+        self.entries.get_mut(&chat_id)?.pop_back()
+    }
+
+    pub fn clear(&mut self, chat_id: i64) {
+        self.entries.remove(&chat_id);
+    }
+
+    pub fn backup(&self) -> HistoryBackup {
+        HistoryBackup {
+            limits: self.limits.iter().map(|(&chat_id, &limit)| (chat_id, limit)).collect(),
+        }
+    }
+
+    /// Replaces the limits with those of `backup`, which passed
+    /// [`HistoryBackup::validate`]. The numbers the chats can `/undo` to stay.
+    pub fn restore(&mut self, backup: HistoryBackup) {
+        self.limits = backup.limits.into_iter().collect();
+    }
+}
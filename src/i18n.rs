@@ -0,0 +1,118 @@
+use teloxide::types::User;
+
+use crate::settings::Settings;
+
+/// The languages replies can be translated to. The first one is the fallback.
+pub const LANGUAGES: &[&str] = &["en", "ru"];
+/// Where `/about` sends those who want to read or change the code.
+pub const SOURCE_URL: &str = "https://github.com/Egodthan-lol/tg-board-game";
+
+/// Returns the supported language `code` stands for, ignoring the region
+/// (`en-US` is `en`).
+pub fn supported(code: &str) -> Option<&'static str> {
+    let code = code.split(['-', '_']).next()?.to_lowercase();
+    LANGUAGES.iter().copied().find(|&lang| lang == code)
+}
+
+/// The language to reply to `user` in: their `/lang` override, then the language of
+/// their Telegram client, then the fallback.
+pub fn locale(settings: &Settings, user: Option<&User>) -> &'static str {
+    let user = match user {
+        Some(user) => user,
+        None => return LANGUAGES[0],
+    };
+    settings
+        .locale(user.id)
+        .or_else(|| user.language_code.as_deref().and_then(supported))
+        .unwrap_or(LANGUAGES[0])
+}
+
+/// `n` with its thousands grouped the way `lang` writes them: `1,000,000` in English and
+/// `1 000 000`, with non-breaking spaces, in Russian.
+pub fn format_number(n: i64, lang: &str) -> String {
+    let separator = match lang {
+        "ru" => '\u{a0}',
+        _ => ',',
+    };
+    let digits = n.unsigned_abs().to_string();
+    let mut grouped = String::with_capacity(digits.len() * 4 / 3 + 1);
+    if n < 0 {
+        grouped.push('-');
+    }
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// The `/about` text: what the bot does, its version and where its source is.
+pub fn about(lang: &str) -> String {
+    format!(
+        "tg-board-game {}\n{}\n{}\n{} {}",
+        env!("CARGO_PKG_VERSION"),
+        t(lang, "about.intro"),
+        t(lang, "about.games"),
+        t(lang, "about.source"),
+        SOURCE_URL,
+    )
+}
+
+/// Looks up the translation of `key`, falling back to English.
+pub fn t(lang: &str, key: &str) -> &'static str {
+    match (lang, key) {
+        ("ru", "lang.set") => "Теперь я буду отвечать по-русски",
+        ("ru", "lang.unsupported") => "Этот язык не поддерживается. Доступные языки:",
+        ("ru", "lang.current") => "Текущий язык: русский. Доступные языки:",
+        ("ru", "help.start") => "Пришлите мне число, и я его запомню. Или начните игру в крестики-нолики: /battle",
+        ("ru", "help.number") => "Ваше число сохранено. /get покажет его, /add и /sub изменят, /flip сменит знак, \
+                                  /undo отменит последнее изменение, /reset забудет его. /target задаст цель. \
+                                  /battle начнёт игру.",
+        ("ru", "help.members") => "У каждого участника своё число: пришлите его, затем /get, /add, /sub, /flip или \
+                                   /reset. /compare @a @b сравнит два числа.",
+        ("ru", "help.battle") => "Идёт партия: нажимайте на клетки доски. /board пришлёт доску заново, /moves покажет \
+                                  свободные клетки, /pause и /resume приостановят игру, /resign — сдаться, /cancel — \
+                                  отменить партию до хода соперника, /takeback — попросить соперника вернуть ходы, \
+                                  /newgame — начать заново.",
+        ("ru", "help.party") => "Идёт игра на компанию: присоединяйтесь в лобби и ходите по очереди, когда подойдёт \
+                                 ваш ход. /leave — выйти из игры, /cancel — отменить её, если вы её начали.",
+        ("ru", "help.sandbox") => "Открыта песочница: нажимайте на клетки, чтобы менять их на пустую, X или O, и на 🔍 Solve, \
+                                     чтобы разобрать позицию. Её владелец закрывает её кнопкой Close или /cancel.",
+        ("ru", "help.race") => "Идёт гонка: доведите число до цели командами /add и /sub с шагом от 1 до 9, пока не \
+                               вышло время. /cancel — сдаться.",
+        ("ru", "help.puzzle") => "Найдите выигрывающий ход и нажмите на его клетку, или /cancel, чтобы узнать ответ.",
+        ("ru", "help.all") => "Все команды: /help all",
+        ("ru", "about.intro") => "Бот, который запоминает число для каждого чата и играет в крестики-нолики.",
+        ("ru", "about.games") => "Игры: крестики-нолики на досках от 3×3 до 8×8 вдвоём или против бота, \
+                                  турниры, задачи (/puzzle), /coinflip и /dice.",
+        ("ru", "about.source") => "Исходный код:",
+        (_, "lang.set") => "I'll reply in English from now on",
+        (_, "lang.unsupported") => "That language isn't supported. Available languages:",
+        (_, "lang.current") => "Current language: English. Available languages:",
+        (_, "help.start") => "Send me a number and I'll remember it. Or start a game of tic-tac-toe with /battle",
+        (_, "help.number") => "Your number is saved. /get shows it, /add and /sub change it, /flip negates it, \
+                               /undo takes back the last change and /reset forgets it. /target sets a number to \
+                               aim for. /battle starts a game.",
+        (_, "help.members") => "Every member has their own number: send yours, then use /get, /add, /sub, /flip or \
+                                /reset. /compare @a @b compares two numbers.",
+        (_, "help.battle") => "A battle is running: tap the cells of the board to move. /board sends the board again, \
+                               /moves shows the free cells, /pause and /resume stop and restart it, /resign gives up, \
+                               /cancel ends it before your opponent has moved, /takeback asks your opponent to undo \
+                               the last moves and /newgame starts over.",
+        (_, "help.party") => "A party game is on: join it in the lobby and tap a cell when it's your turn. /leave \
+                              takes you out of it and /cancel ends it if you started it.",
+        (_, "help.sandbox") => "A sandbox is open: tap its cells to cycle them through empty, X and O, and Solve to \
+                                analyse the position. Its owner closes it with Close or /cancel.",
+        (_, "help.race") => "A race is on: bring the number to its target with /add and /sub of 1 to 9 before the \
+                             time is up. /cancel gives up.",
+        (_, "help.puzzle") => "Find the winning move and tap its cell, or /cancel to see the answer.",
+        (_, "help.all") => "Every command: /help all",
+        (_, "about.intro") => "A bot that remembers a number for every chat and plays tic-tac-toe.",
+        (_, "about.games") => "Games: tic-tac-toe from 3×3 to 8×8 against a friend or the bot, tournaments, \
+                               puzzles (/puzzle), /coinflip and /dice.",
+        (_, "about.source") => "Source code:",
+        _ => "",
+    }
+}
@@ -1,14 +1,74 @@
-// Set the `DB_REMEMBER_REDIS` environmental variable if you want to use Redis.
-// Otherwise, the default is Sqlite.
+// Set the `DB_REMEMBER_REDIS` environmental variable if you want to use Redis,
+// or `DB_REMEMBER_MEMORY` to keep the states in memory. Otherwise, the default is Sqlite.
+mod ai;
+mod args;
+mod backup;
+mod battle;
+mod chance;
+mod challenge;
+#[cfg(feature = "board-images")]
+mod board_image;
+mod config;
+mod confirm;
+mod dedup;
+mod error;
+mod expiry;
+mod feed;
+mod feedback;
+mod game;
+mod history;
+mod i18n;
+mod members;
+mod party;
+mod profile;
+mod puzzle;
+mod race;
+mod render;
+mod replay;
+mod sandbox;
+mod settings;
+mod snapshots;
+mod stats;
+mod storage;
+mod telemetry;
+mod tournament;
+
+use ai::Difficulty;
+use backup::{Backup, PendingRestores};
+use battle::{
+    battle_text, board_keyboard, forfeit_text, games_list, moves_text, record_result, result_text, Battle, BattleGame,
+    BoardButton, SizeError, Takeover,
+};
+use challenge::{Challenge, Challenges};
+use config::{Backend, Config};
+use dedup::SeenCallbacks;
+use error::Error;
+use expiry::{ActiveGame, ActiveGames};
+use feedback::FeedbackLimiter;
+use game::{Game, GameStatus, Mode, MoveError, Outcome, Player, WinCondition};
+use history::History;
+use members::Members;
+use party::{Party, PartyButton, PartyOutcome};
+use profile::{chat_profile, ChatProfile};
+use puzzle::Puzzle;
+use race::{Race, RaceStep};
+use replay::Replays;
+use sandbox::{Sandbox, SandboxButton};
+use settings::Settings;
+use snapshots::Snapshots;
+use stats::Stats;
+use storage::{Health, StorageTimeout};
+use tournament::Tournaments;
 use dotenv::dotenv;
 use teloxide::{
     dispatching2::dialogue::{
-        serializer::{Bincode, Json},
-        ErasedStorage, RedisStorage, SqliteStorage, Storage, GetChatId,
+        serializer::Json,
+        ErasedStorage, Serializer,
     },
+    net::Download,
     macros::DialogueState,
     prelude2::*,
-    types::{Me, MessageLeftChatMember},
+    types::Me,
     utils::command::BotCommand,
     types::{
         InlineKeyboardButton,
@@ -18,48 +78,233 @@ use teloxide::{
 
 type MyDialogue = Dialogue<State, ErasedStorage<State>>;
 type MyStorage = std::sync::Arc<ErasedStorage<State>>;
-type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+type MyConfig = std::sync::Arc<Config>;
+type MyStats = std::sync::Arc<std::sync::Mutex<Stats>>;
+type MyActiveGames = std::sync::Arc<std::sync::Mutex<ActiveGames>>;
+type MyHistory = std::sync::Arc<std::sync::Mutex<History>>;
+type MyTournaments = std::sync::Arc<std::sync::Mutex<Tournaments>>;
+type MySettings = std::sync::Arc<std::sync::Mutex<Settings>>;
+type MySnapshots = std::sync::Arc<std::sync::Mutex<Snapshots>>;
+type MyChallenges = std::sync::Arc<std::sync::Mutex<Challenges>>;
+type MyFeedback = std::sync::Arc<std::sync::Mutex<FeedbackLimiter>>;
+type MyHealth = std::sync::Arc<std::sync::Mutex<Health>>;
+type MyReplays = std::sync::Arc<std::sync::Mutex<Replays>>;
+type MySeenCallbacks = std::sync::Arc<std::sync::Mutex<SeenCallbacks>>;
+type MyPendingRestores = std::sync::Arc<std::sync::Mutex<PendingRestores>>;
+type HandlerResult = Result<(), Error>;
 
-#[derive(DialogueState, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(DialogueState, Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
 #[handler_out(HandlerResult)]
 pub enum State {
+    #[default]
     #[handler(handle_start)]
     Start,
 
+    /// The number, and the `/target` it aims for.
     #[handler(handle_got_number)]
-    GotNumber(i32),
+    GotNumber(i32, Option<i32>),
 
     #[handler(handle_got_number)]
-    AddNumber(i32),
+    AddNumber(i32, Option<i32>),
 
     #[handler(handle_got_number)]
-    SubNumber(i32),
+    SubNumber(i32, Option<i32>),
 
-    #[handler(handle_got_number)]
-    BattlePlayer,
-}
+    #[handler(handle_battle)]
+    Battle(Battle),
 
-impl Default for State {
-    fn default() -> Self {
-        Self::Start
-    }
+    #[handler(handle_members)]
+    Members(Members),
+
+    #[handler(handle_puzzle)]
+    Puzzle(Puzzle),
+
+    #[handler(handle_party)]
+    Party(Party),
+
+    #[handler(handle_sandbox)]
+    Sandbox(Sandbox),
+
+    #[handler(handle_race)]
+    Race(Race),
 }
 
-#[derive(BotCommand)]
+#[derive(BotCommand, Clone)]
 #[command(rename = "lowercase", description = "These commands are supported:")]
 pub enum Command {
-    #[command(description = "get your number.")]
-    Get,
+    #[command(description = "get your number, or another member's with `/get @username` or as a reply.")]
+    Get(String),
     #[command(description = "reset your number.")]
     Reset,
     #[command(description = "add your number.")]
     Add(String),
     #[command(description = "sub your number.")]
     Sub(String),
-    #[command(description = "sub your number.")]
-    Battle,
+    #[command(description = "start a battle, on a 3×3 board unless you give a size up to 8 (`/battle 4`), \
+                             optionally followed by how many in a row win (`/battle 5 4`). \
+                             Options: `misere` (completing a line loses), \
+                             `learn` (show who wins with best play and how far the hard bot searched), \
+                             `pass` (play both sides), `ai`/`easy`/`medium`/`hard` (play the bot). \
+                             Private chats play the medium bot by default.")]
+    Battle(String),
+    #[command(description = "end your game and start a new one, with the options of /battle.")]
+    NewGame(String),
+    #[command(description = "archive the leaderboard (admins only).")]
+    ResetLeaderboard,
+    #[command(description = "send this chat's leaderboard as a CSV file (admins only).")]
+    ExportLeaderboard,
+    #[command(description = "send every stat, setting and saved position as a file, in a private chat (admins only).")]
+    Backup,
+    #[command(description = "load a /backup back, as a reply to its file (admins only).")]
+    Restore,
+    #[command(description = "clear your own wins, losses and draws.")]
+    ResetStats,
+    #[command(description = "pause the battle.")]
+    Pause,
+    #[command(description = "resume a paused battle.")]
+    Resume,
+    #[command(description = "export your state as JSON.")]
+    Export,
+    #[command(description = "import a state exported with /export.")]
+    Import(String),
+    #[command(description = "show the bot version and storage backend.")]
+    Version,
+    #[command(description = "negate your number.")]
+    Flip,
+    #[command(description = "undo the last change of your number.")]
+    Undo,
+    #[command(description = "take one Collatz step: halve an even number, triple an odd one and add 1.")]
+    Next,
+    #[command(description = "add the number before yours to it, like the next Fibonacci number.")]
+    Fib,
+    #[command(description = "`start` a tournament (admins only) or show its `status`.")]
+    Tournament(String),
+    #[command(description = "off")]
+    Seed(String),
+    #[command(description = "off")]
+    Start(String),
+    #[command(description = "restart your game on a bigger or smaller board, e.g. `/resize 4`.")]
+    Resize(String),
+    #[command(description = "ask your opponent to take back the last moves, so that you can play yours again.")]
+    Takeback,
+    #[command(description = "copy your game into a new practice game against the bot.")]
+    Clone,
+    #[command(description = "let the hard bot play your game out against itself, without touching the board.")]
+    Simulate,
+    #[command(description = "compare two members' numbers: `/compare @a @b`, or reply to someone.")]
+    Compare(String),
+    #[command(description = "give some of your number to another member: `/give @user 5`, or `/give 5` as a reply.")]
+    Give(String),
+    #[command(description = "list the games running in this chat.")]
+    Games,
+    #[command(description = "show the empty cells left and whose turn it is.")]
+    Moves,
+    #[command(description = "show how long the player to move has left, with a move timer set in /settings.")]
+    Clock,
+    #[command(description = "show what `add <number>` or `sub <number>` would give, without changing your number.")]
+    Preview(String),
+    #[command(description = "aim for a number, e.g. `/target 100`: /add and /sub then say how far it is. \
+                             `/target clear` drops it.")]
+    Target(String),
+    #[command(description = "reply in another language, e.g. `/lang en`.")]
+    Lang(String),
+    #[command(description = "show and change the game settings of this chat.")]
+    Settings,
+    #[command(description = "forget everything about this chat and end its games.")]
+    Stop,
+    #[command(description = "show this chat's state as the storage backend writes it, ids and names included \
+                             (admins only).")]
+    DumpState,
+    #[command(description = "show the configuration the bot runs with (admins only).")]
+    Config,
+    #[command(description = "copy this chat's state to another storage backend, e.g. `/migrate redis` (admins only).")]
+    Migrate(String),
+    #[command(description = "show what you can do right now, or this text with `/help all`.")]
+    Help(String),
+    #[command(description = "give up your game.")]
+    Resign,
+    #[command(description = "end your game without a result, before your opponent has moved.")]
+    Cancel,
+    #[command(description = "send the board of your game again, below the latest messages.")]
+    Board,
+    #[command(description = "show your results this season, or those of one game type, e.g. `/stats misere`.")]
+    Stats(String),
+    #[command(description = "show which lines win games most often.")]
+    Winstats,
+    #[command(description = "show how many games were played on the whole bot.")]
+    GlobalStats,
+    #[command(description = "hide your name on scoreboards with `/anon on`, your games still count.")]
+    Anon(String),
+    #[command(description = "watch the last finished game of this chat again, move by move.")]
+    Replay,
+    #[command(description = "check that the bot and its storage respond.")]
+    Ping,
+    #[command(description = "show what the bot is and where its source is.")]
+    About,
+    #[command(description = "set how many numbers /undo can go back through, e.g. `/sethistory 50`.")]
+    SetHistory(String),
+    #[command(description = "turn the number of this chat `on` or `off`, e.g. to only play (group admins only).")]
+    Numbers(String),
+    #[command(description = "reply with this to a message to take the first number in it as your number.")]
+    Adopt,
+    #[command(description = "save the position of your game under a name, e.g. `/save fork`, \
+                             or one you write row by row: `/save fork X.O/.X./O..`.")]
+    Save(String),
+    #[command(description = "start a game from a position saved with /save, e.g. `/load fork`.")]
+    Load(String),
+    #[command(description = "list the positions you saved, with buttons to delete them.")]
+    Snapshots,
+    #[command(description = "delete all the positions you saved.")]
+    ClearSnapshots,
+    #[command(description = "challenge a member of this group to a battle, e.g. `/challenge @ada`.")]
+    Challenge(String),
+    #[command(description = "accept the challenge someone sent you.")]
+    Accept,
+    #[command(description = "move your game to another chat you are in, e.g. `/transfer @group`, \
+                             or as a reply to a message forwarded from it.")]
+    Transfer(String),
+    #[command(description = "find the move that wins a position.")]
+    Puzzle,
+    #[command(description = "open a board for up to 6 players who take turns, 5×5 unless you give a size \
+                             (`/party 7`).")]
+    Party(String),
+    #[command(description = "leave the party game you joined.")]
+    Leave,
+    #[command(description = "open a board to set up positions on, 3×3 unless you give a size (`/sandbox 4`).")]
+    Sandbox(String),
+    #[command(description = "race your number to a target with /add and /sub of 1 to 9 against the clock, \
+                             e.g. `/race 50`.")]
+    Race(String),
+    #[command(description = "send feedback to the bot's owner, e.g. `/feedback the bot is too good`.")]
+    Feedback(String),
+    #[command(description = "flip a coin.")]
+    Coinflip,
+    #[command(description = "roll dice, e.g. `/dice 2d6`.")]
+    Dice(String),
+    #[command(description = "set the name you play under, e.g. `/rename Ada`; without a name, use your Telegram name again.")]
+    Rename(String),
+    #[command(description = "confirm your moves with a pop-up you have to close, with `/alerts on`.")]
+    Alerts(String),
+    #[command(description = "turn a command off in this chat, e.g. `/disable dice` (group admins only).")]
+    Disable(String),
+    #[command(description = "turn a command turned off with /disable on again, e.g. `/enable dice`.")]
+    Enable(String),
 }
 
+/// Exported states are tiny; anything bigger than this isn't one of them.
+const MAX_IMPORT_SIZE: usize = 1024;
+/// Longer than any `i32`, even with a sign: `-2147483648` has 11 characters.
+const MAX_OPERAND_LEN: usize = 12;
+const OVERSIZED_OPERAND: &str = "That number is way too large";
+const GAME_START_COOLDOWN: &str = "Please wait before starting another game";
+const TOO_MANY_GAMES: &str = "Too many games running here — finish or cancel one first";
+const NUMBERS_DISABLED: &str = "Number tracking is disabled here, but you can still /battle, /challenge someone \
+or solve a /puzzle";
+const NOT_IN_PARTY: &str = "You're not in a party game";
+const GAME_IN_PROGRESS: &str = "You already have a game in progress — /newgame to restart or /cancel to abandon";
+const NO_GAME_TO_TRANSFER: &str = "You aren't playing a game here";
+const CANT_REACH_CHAT: &str = "I can't post in that chat, add me to it first";
+
 #[tokio::main]
 async fn main() {
     dotenv().ok();
@@ -67,89 +312,1234 @@ async fn main() {
     log::info!("Starting db_remember_bot...");
 
     let bot = Bot::from_env().auto_send();
+    let config: MyConfig = std::sync::Arc::new(Config::from_env());
+    let stats = if config.replay_channel.is_some() { Stats::with_feed() } else { Stats::default() };
+    let stats: MyStats = std::sync::Arc::new(std::sync::Mutex::new(stats));
+    let active_games =
+        ActiveGames::new(config.game_start_cooldown, config.max_games_per_chat, config.repetition_limit);
+    let active_games: MyActiveGames = std::sync::Arc::new(std::sync::Mutex::new(active_games));
+    let history: MyHistory = std::sync::Arc::new(std::sync::Mutex::new(History::with_limit(config.history_limit)));
+    let tournaments: MyTournaments = Default::default();
+    let settings: MySettings = Default::default();
+    let seen_callbacks: MySeenCallbacks = Default::default();
+    let snapshots: MySnapshots = Default::default();
+    let challenges: MyChallenges = Default::default();
+    let feedback: MyFeedback = Default::default();
+    let health: MyHealth = Default::default();
+    let replays: MyReplays = Default::default();
+    let restores: MyPendingRestores = Default::default();
 
-    let storage: MyStorage = if std::env::var("DB_REMEMBER_REDIS").is_ok() {
-        RedisStorage::open("redis://127.0.0.1:6379", Bincode).await.unwrap().erase()
-    } else {
-        SqliteStorage::open("db.sqlite", Json).await.unwrap().erase()
-    };
+    let storage = storage::open(config.backend, config.storage_timeout).await.unwrap();
+    let storage: MyStorage = storage::RecoveringStorage::new(storage, bot.clone());
+
+    let health_check = storage::spawn_health_check(
+        storage.clone(),
+        health.clone(),
+        config.health_check_interval,
+        config.storage_timeout,
+    );
+    let (max_age, lobby_timeout) = (config.game_max_age, config.lobby_timeout);
+    expiry::spawn_sweeper(bot.clone(), storage.clone(), active_games.clone(), settings.clone(), max_age, lobby_timeout);
+    if let Some(channel_id) = config.replay_channel {
+        feed::spawn_feed(bot.clone(), stats.clone(), settings.clone(), channel_id);
+    }
+    expiry::spawn_turn_timer(
+        bot.clone(),
+        storage.clone(),
+        active_games.clone(),
+        settings.clone(),
+        stats.clone(),
+        tournaments.clone(),
+        config.turn_warning,
+    );
 
     let handler = dptree::entry()
         .branch(Update::filter_message()
-                .enter_dialogue::<Message, ErasedStorage<State>, State>()
-                .dispatch_by::<State>())
-        .branch(Update::filter_callback_query().endpoint(handle_callback));
+                .chain(dptree::filter(is_human_message))
+                .chain(dptree::filter(|msg: Message| chat_profile(&msg.chat).answers_messages()))
+                .branch(dptree::filter(is_disabled_command).endpoint(handle_disabled_command))
+                .branch(dptree::filter_map(parse_global_command).endpoint(handle_global_command))
+                .branch(dptree::filter_map(parse_stats_command).endpoint(handle_stats_command))
+                .branch(dptree::filter_map(parse_backup_command).endpoint(handle_backup_command))
+                .branch(dptree::filter(is_ping_command).endpoint(handle_ping))
+                .branch(dptree::filter_map(parse_feedback_command).endpoint(handle_feedback))
+                // Everything after this reads the text of the message.
+                .branch(dptree::filter(|msg: Message| msg.text().is_none()).endpoint(handle_unsupported_message))
+                .branch(dptree::entry()
+                        .enter_dialogue::<Message, ErasedStorage<State>, State>()
+                        .branch(dptree::filter_map(parse_help_command).endpoint(handle_help))
+                        .branch(dptree::filter_map(parse_snapshot_command).endpoint(handle_snapshot_command))
+                        .branch(dptree::filter_map(parse_challenge_command).endpoint(handle_challenge_command))
+                        .branch(dptree::filter_map(parse_transfer_command).endpoint(handle_transfer))
+                        .branch(dptree::filter(is_disabled_number_input).endpoint(handle_disabled_number_input))
+                        .branch(dptree::filter_map(parse_battle_input).endpoint(handle_battle_input))
+                        .branch(dptree::entry().dispatch_by::<State>())
+                        // Every variant has a handler today; this catches one that loses it
+                        // in an upgrade instead of dropping the chat's messages silently.
+                        .branch(dptree::endpoint(handle_unrouted_state))))
+        .branch(Update::filter_callback_query()
+                .chain(dptree::filter(is_human_callback))
+                .branch(dptree::filter(is_duplicate_callback).endpoint(answer_duplicate_callback))
+                .branch(dptree::filter(is_settings_callback).endpoint(handle_settings_callback))
+                .branch(dptree::filter_map(parse_restore_callback).endpoint(handle_restore_callback))
+                .branch(dptree::filter_map(parse_confirm_callback).endpoint(handle_confirm_callback))
+                .branch(dptree::filter(is_challenge_callback).endpoint(handle_challenge_callback))
+                .branch(dptree::filter(is_snapshot_callback).endpoint(handle_snapshot_callback))
+                .branch(dptree::filter(is_replay_callback).endpoint(handle_replay_callback))
+                .branch(dptree::entry()
+                        .enter_dialogue::<CallbackQuery, ErasedStorage<State>, State>()
+                        .endpoint(handle_callback)));
 
+    // The dispatcher of teloxide 0.7 handles one update at a time, in the order Telegram
+    // sends them, so two clicks on the same board never race on its state: the second one
+    // reads the state the first one stored. Only the sweeper, the turn timer and the
+    // storage health check run alongside the handlers.
+    let error_bot = bot.clone();
     Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![storage])
+        .dependencies(dptree::deps![
+            storage,
+            config,
+            stats,
+            active_games,
+            history,
+            tournaments,
+            settings,
+            seen_callbacks,
+            snapshots,
+            challenges,
+            feedback,
+            health,
+            replays,
+            restores
+        ])
+        .error_handler(std::sync::Arc::new(move |err| handle_error(error_bot.clone(), err)))
         .build()
         .setup_ctrlc_handler()
         .dispatch()
         .await;
+    health_check.abort();
+}
+
+/// Tells the user to retry when the storage timed out, and logs every error.
+async fn handle_error(bot: AutoSend<Bot>, err: Error) {
+    log::error!("Error in a handler: {}", err);
+    let timeout = match &err {
+        Error::Storage(inner) => inner.downcast_ref::<StorageTimeout>(),
+        _ => None,
+    };
+    if let Some(timeout) = timeout {
+        let text = "Sorry, I couldn't reach my storage, please try again";
+        if let Err(err) = bot.send_message(timeout.chat_id, text).await {
+            log::error!("Failed to report a storage timeout: {}", err);
+        }
+    }
+}
+
+fn parse_command(msg: &Message, me: &Me, config: &Config) -> Option<Command> {
+    let text = config.resolve_command(&normalize_command(msg.text()?));
+    Command::parse(&text, me.user.username.as_deref()?).ok()
+}
+
+/// Commands that work the same whatever the chat's state is, handled before the
+/// dialogue is entered.
+fn parse_global_command(msg: Message, me: Me, config: MyConfig) -> Option<Command> {
+    match parse_command(&msg, &me, &config) {
+        Some(
+            cmd @ (Command::Lang(_)
+            | Command::Settings
+            | Command::Tournament(_)
+            | Command::Stop
+            | Command::Rename(_)
+            | Command::Alerts(_)
+            | Command::Migrate(_)
+            | Command::DumpState
+            | Command::Config
+            | Command::Coinflip
+            | Command::Dice(_)
+            | Command::About
+            | Command::SetHistory(_)
+            | Command::Numbers(_)
+            | Command::Disable(_)
+            | Command::Enable(_)),
+        ) => Some(cmd),
+        _ => None,
+    }
+}
+
+/// Global commands about the game results, which need the stats.
+fn parse_stats_command(msg: Message, me: Me, config: MyConfig) -> Option<Command> {
+    match parse_command(&msg, &me, &config) {
+        Some(
+            cmd @ (Command::Stats(_)
+            | Command::Winstats
+            | Command::GlobalStats
+            | Command::Replay
+            | Command::Anon(_)
+            | Command::ResetStats
+            | Command::ExportLeaderboard),
+        ) => Some(cmd),
+        _ => None,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_stats_command(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    cmd: Command,
+    stats: MyStats,
+    replays: MyReplays,
+    settings: MySettings,
+    config: MyConfig,
+    active_games: MyActiveGames,
+) -> HandlerResult {
+    match cmd {
+        Command::Stats(game_type) => {
+            let user = match msg.from() {
+                Some(user) => user,
+                None => return Ok(()),
+            };
+            let text = match game_type.trim() {
+                "" => battle::stats_text(&stats.lock().unwrap(), user.id, None),
+                name => match stats::GameType::from_name(name) {
+                    Some(game_type) => battle::stats_text(&stats.lock().unwrap(), user.id, Some(game_type)),
+                    None => {
+                        let names: Vec<_> = stats::GameType::ALL.iter().map(|game_type| game_type.name()).collect();
+                        format!("Game types: {}", names.join(", "))
+                    }
+                },
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::Winstats => {
+            let text = battle::winstats_text(&stats.lock().unwrap());
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::GlobalStats => {
+            let lang = i18n::locale(&settings.lock().unwrap(), msg.from());
+            let active_chats = active_games.lock().unwrap().chat_count();
+            let text = battle::global_stats_text(&stats.lock().unwrap(), active_chats, lang);
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::Replay => {
+            let game = stats.lock().unwrap().last_game(msg.chat.id).cloned();
+            match game {
+                Some(game) => replay::start_replay(&bot, &replays, msg.chat.id, game).await?,
+                None => {
+                    bot.send_message(msg.chat.id, "No game has ended here yet").await?;
+                }
+            }
+        }
+        Command::Anon(switch) => {
+            let user = match msg.from() {
+                Some(user) => user,
+                None => return Ok(()),
+            };
+            let anonymous = match switch.trim().to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    let current = settings::on_off(settings.lock().unwrap().is_anonymous(user.id));
+                    let text = format!("Anonymous mode is {} for you, use /anon on or /anon off", current);
+                    bot.send_message(msg.chat.id, text).await?;
+                    return Ok(());
+                }
+            };
+            settings.lock().unwrap().set_anonymous(user.id, anonymous);
+            let text = if anonymous {
+                "Scoreboards now list you as Anonymous, your games still count"
+            } else {
+                "Scoreboards show your name again"
+            };
+            bot.send_message(msg.chat.id, text).await?;
+            // Other chats' scoreboards catch up after their next game.
+            let pinned = settings.lock().unwrap().prefs(msg.chat.id).pinned_scoreboard;
+            if pinned && settings.lock().unwrap().scoreboard_message(msg.chat.id).is_some() {
+                update_scoreboard(&bot, &settings, &stats, msg.chat.id).await?;
+            }
+        }
+        Command::ResetStats => {
+            let user = match msg.from() {
+                Some(user) => user,
+                None => return Ok(()),
+            };
+            let keyboard = confirm::confirm_keyboard(&confirm::Action::ResetStats(user.id).id());
+            bot.send_message(msg.chat.id, "Clear your wins, losses and draws? Other players' results stay")
+                .reply_markup(keyboard)
+                .await?;
+        }
+        Command::ExportLeaderboard => {
+            if !msg.from().is_some_and(|user| config.is_admin(user.id)) {
+                bot.send_message(msg.chat.id, "You're not allowed to do that").await?;
+                return Ok(());
+            }
+            let (csv, rows) = battle::leaderboard_csv(&stats.lock().unwrap(), &settings.lock().unwrap(), msg.chat.id);
+            if rows == 0 {
+                bot.send_message(msg.chat.id, "Nobody has played here yet").await?;
+                return Ok(());
+            }
+            let document = teloxide::types::InputFile::memory(csv.into_bytes()).file_name("leaderboard.csv");
+            let caption = if rows < battle::MAX_CSV_ROWS {
+                format!("🏆 Leaderboard, {} players", rows)
+            } else {
+                format!("🏆 Leaderboard, the best {} players", rows)
+            };
+            bot.send_document(msg.chat.id, document).caption(caption).await?;
+        }
+        _ => unreachable!("parse_stats_command only lets stats commands through"),
+    }
+
+    Ok(())
+}
+
+fn is_replay_callback(q: CallbackQuery) -> bool {
+    q.data.as_deref() == Some("replay:skip")
+}
+
+/// Answers the "Skip to end" button of a replay by stopping it on the final position.
+async fn handle_replay_callback(q: CallbackQuery, bot: AutoSend<Bot>, replays: MyReplays) -> HandlerResult {
+    let message = match &q.message {
+        Some(message) => message,
+        None => return Ok(()),
+    };
+    bot.answer_callback_query(q.id).await?;
+    let game = replays.lock().unwrap().stop(message.chat.id, message.id);
+    if let Some(game) = game {
+        let text = replay::replay_text(&game, game.moves.len());
+        bot.edit_message_text(message.chat.id, message.id, text).await?;
+    }
+
+    Ok(())
+}
+
+fn is_ping_command(msg: Message, me: Me, config: MyConfig) -> bool {
+    matches!(parse_command(&msg, &me, &config), Some(Command::Ping))
+}
+
+/// Answers `/ping` with the outcome of the last storage health check.
+async fn handle_ping(bot: AutoSend<Bot>, msg: Message, health: MyHealth) -> HandlerResult {
+    let health = *health.lock().unwrap();
+    let text = match health.latency {
+        _ if !health.healthy => format!("Pong! The storage isn't responding ({} failed checks)", health.failures),
+        Some(latency) => format!("Pong! The storage is healthy ({} ms)", latency.as_millis()),
+        None => "Pong! The storage hasn't been checked yet".to_owned(),
+    };
+    bot.send_message(msg.chat.id, text).await?;
+
+    Ok(())
+}
+
+fn parse_backup_command(msg: Message, me: Me, config: MyConfig) -> Option<Command> {
+    match parse_command(&msg, &me, &config) {
+        Some(cmd @ (Command::Backup | Command::Restore)) => Some(cmd),
+        _ => None,
+    }
+}
+
+/// `/backup` and `/restore`. Backups hold every user's data, so they're only sent and
+/// read in the private chat of an admin.
+#[allow(clippy::too_many_arguments)]
+async fn handle_backup_command(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    cmd: Command,
+    config: MyConfig,
+    stats: MyStats,
+    settings: MySettings,
+    snapshots: MySnapshots,
+    history: MyHistory,
+    restores: MyPendingRestores,
+) -> HandlerResult {
+    let admin = match msg.from() {
+        Some(user) if config.is_admin(user.id) => user.id,
+        _ => {
+            bot.send_message(msg.chat.id, "You're not allowed to do that").await?;
+            return Ok(());
+        }
+    };
+    if chat_profile(&msg.chat) != ChatProfile::Private {
+        bot.send_message(msg.chat.id, "Backups hold everybody's data, ask me for them in a private chat").await?;
+        return Ok(());
+    }
+    match cmd {
+        Command::Backup => {
+            let backup = {
+                let (stats, settings) = (stats.lock().unwrap(), settings.lock().unwrap());
+                let (snapshots, history) = (snapshots.lock().unwrap(), history.lock().unwrap());
+                Backup::new(&stats, &settings, &snapshots, &history, now())
+            };
+            let name = format!("backup-{}.json", backup.created_at);
+            let document = teloxide::types::InputFile::memory(serde_json::to_vec(&backup)?).file_name(name);
+            let caption = "🗄 Stats, settings and saved positions. Reply /restore to this file to load it back";
+            bot.send_document(msg.chat.id, document).caption(caption).await?;
+        }
+        Command::Restore => {
+            let document = match msg.reply_to_message().and_then(Message::document) {
+                Some(document) => document,
+                None => {
+                    bot.send_message(msg.chat.id, "Reply /restore to the file of a /backup").await?;
+                    return Ok(());
+                }
+            };
+            if document.file_size.is_none_or(|size| size > backup::MAX_BACKUP_SIZE) {
+                bot.send_message(msg.chat.id, "That file is too big to be a backup").await?;
+                return Ok(());
+            }
+            let file = bot.get_file(&document.file_id).await?;
+            let mut contents = Vec::new();
+            bot.download_file(&file.file_path, &mut contents).await?;
+            let backup = match backup::parse_backup(&contents) {
+                Ok(backup) => backup,
+                Err(err) => {
+                    bot.send_message(msg.chat.id, err.to_string()).await?;
+                    return Ok(());
+                }
+            };
+            let text = format!(
+                "Replace every stat, setting and saved position with this backup from {}?",
+                snapshots::date_text(backup.created_at)
+            );
+            restores.lock().unwrap().insert(admin, backup);
+            let keyboard = confirm::confirm_keyboard(&confirm::Action::Restore(admin).id());
+            bot.send_message(msg.chat.id, text).reply_markup(keyboard).await?;
+        }
+        _ => unreachable!("parse_backup_command only lets backup commands through"),
+    }
+
+    Ok(())
+}
+
+fn parse_feedback_command(msg: Message, me: Me, config: MyConfig) -> Option<String> {
+    match parse_command(&msg, &me, &config) {
+        Some(Command::Feedback(text)) => Some(text),
+        _ => None,
+    }
+}
+
+/// Sends `/feedback` to the owner chat with who sent it, so that the owner can get back
+/// to them.
+async fn handle_feedback(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    text: String,
+    config: MyConfig,
+    feedback: MyFeedback,
+) -> HandlerResult {
+    let user = match msg.from() {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    let owner_chat = match config.owner_chat {
+        Some(owner_chat) => owner_chat,
+        None => {
+            bot.send_message(msg.chat.id, "Feedback isn't set up").await?;
+            return Ok(());
+        }
+    };
+    let text = text.trim();
+    if text.is_empty() {
+        bot.send_message(msg.chat.id, "Write your feedback after the command, e.g. /feedback I love it").await?;
+        return Ok(());
+    }
+    if !feedback.lock().unwrap().try_send(user.id, now()) {
+        let text = format!("You can send feedback once every {} seconds", feedback::FEEDBACK_INTERVAL);
+        bot.send_message(msg.chat.id, text).await?;
+        return Ok(());
+    }
+
+    let sender = match &user.username {
+        Some(username) => format!("{} (@{}, id {})", user.full_name(), username, user.id),
+        None => format!("{} (id {})", user.full_name(), user.id),
+    };
+    bot.send_message(owner_chat, format!("📬 Feedback from {} in chat {}:\n{}", sender, msg.chat.id, text)).await?;
+    bot.send_message(msg.chat.id, "Thanks, your feedback was sent").await?;
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_global_command(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    cmd: Command,
+    config: MyConfig,
+    storage: MyStorage,
+    settings: MySettings,
+    tournaments: MyTournaments,
+    active_games: MyActiveGames,
+    history: MyHistory,
+) -> HandlerResult {
+    match cmd {
+        Command::Lang(code) => {
+            let code = code.trim();
+            let user = match msg.from() {
+                Some(user) => user,
+                None => return Ok(()),
+            };
+            let available = i18n::LANGUAGES.join(", ");
+            if code.is_empty() {
+                let lang = i18n::locale(&settings.lock().unwrap(), Some(user));
+                bot.send_message(msg.chat.id, format!("{} {}", i18n::t(lang, "lang.current"), available))
+                    .await?;
+                return Ok(());
+            }
+            match i18n::supported(code) {
+                Some(lang) => {
+                    settings.lock().unwrap().set_locale(user.id, lang);
+                    bot.send_message(msg.chat.id, i18n::t(lang, "lang.set")).await?;
+                }
+                None => {
+                    let lang = i18n::locale(&settings.lock().unwrap(), Some(user));
+                    bot.send_message(
+                        msg.chat.id,
+                        format!("{} {}", i18n::t(lang, "lang.unsupported"), available),
+                    )
+                    .await?;
+                }
+            }
+        }
+        Command::SetHistory(limit) => {
+            let text = match limit.trim().parse() {
+                Ok(limit) => match history.lock().unwrap().set_limit(msg.chat.id, limit) {
+                    Ok(()) => format!("/undo can now go back through {} numbers", limit),
+                    Err(err) => err.to_string(),
+                },
+                Err(_) => history::LimitOutOfRange.to_string(),
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::Numbers(switch) => {
+            let user = match msg.from() {
+                Some(user) => user,
+                None => return Ok(()),
+            };
+            let enabled = match switch.trim().to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    let current = settings.lock().unwrap().prefs(msg.chat.id).numbers_enabled;
+                    let current = settings::on_off(current);
+                    let text = format!("Number tracking is {} here, use /numbers on or /numbers off", current);
+                    bot.send_message(msg.chat.id, text).await?;
+                    return Ok(());
+                }
+            };
+            if !may_configure_chat(&bot, &msg, user.id, &config).await? {
+                bot.send_message(msg.chat.id, "Only the admins of this group can do that").await?;
+                return Ok(());
+            }
+            settings.lock().unwrap().prefs_mut(msg.chat.id).numbers_enabled = enabled;
+            let text = if enabled { "Number tracking is on again" } else { NUMBERS_DISABLED };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::Disable(name) => toggle_command(&bot, &msg, &config, &settings, &name, true).await?,
+        Command::Enable(name) => toggle_command(&bot, &msg, &config, &settings, &name, false).await?,
+        Command::About => {
+            let lang = i18n::locale(&settings.lock().unwrap(), msg.from());
+            bot.send_message(msg.chat.id, i18n::about(lang)).await?;
+        }
+        Command::Settings => {
+            let prefs = settings.lock().unwrap().prefs(msg.chat.id);
+            bot.send_message(msg.chat.id, "Game settings for this chat")
+                .reply_markup(settings::settings_keyboard(&prefs))
+                .await?;
+        }
+        Command::Tournament(action) => {
+            let dialogue = MyDialogue::new(storage, msg.chat.id);
+            handle_tournament(&bot, &msg, &dialogue, &action, &config, &tournaments, &active_games).await?;
+        }
+        Command::Stop => {
+            let chat_id = msg.chat.id;
+            let dialogue = MyDialogue::new(storage, chat_id);
+            if let Some(State::Battle(battle)) = dialogue.get().await? {
+                let delete = settings.lock().unwrap().prefs(chat_id).delete_boards;
+                for entry in battle.games.values() {
+                    expiry::unpin_board(&bot, chat_id, entry).await;
+                    let retired = expiry::retire_board(&bot, chat_id, entry.message_id, delete, "Game stopped");
+                    if let Err(err) = retired.await {
+                        log::warn!("Failed to close a board in chat {}: {}", chat_id, err);
+                    }
+                }
+            }
+            dialogue.exit().await?;
+            active_games.lock().unwrap().remove_chat(chat_id);
+            history.lock().unwrap().clear(chat_id);
+            settings.lock().unwrap().forget_status_message(chat_id);
+            if let Some(tournament) = tournaments.lock().unwrap().get_mut(chat_id) {
+                tournament.cancel();
+            }
+            bot.send_message(chat_id, "Goodbye! Send me a number whenever you want to start again").await?;
+        }
+        Command::Alerts(switch) => {
+            let user = match msg.from() {
+                Some(user) => user,
+                None => return Ok(()),
+            };
+            let alerts = match switch.trim().to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    let current = settings::on_off(settings.lock().unwrap().wants_alerts(user.id));
+                    let text = format!("Move alerts are {} for you, use /alerts on or /alerts off", current);
+                    bot.send_message(msg.chat.id, text).await?;
+                    return Ok(());
+                }
+            };
+            settings.lock().unwrap().set_alerts(user.id, alerts);
+            let text = if alerts {
+                "Your moves are now confirmed with a pop-up that says whose turn it is"
+            } else {
+                "Your moves are confirmed silently again"
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::Rename(name) => {
+            let user = match msg.from() {
+                Some(user) => user,
+                None => return Ok(()),
+            };
+            let text = match settings::clean_name(&name) {
+                Ok(Some(name)) => {
+                    let text = format!("You now play as {}", name);
+                    settings.lock().unwrap().set_name(user.id, Some(name));
+                    text
+                }
+                Ok(None) => {
+                    settings.lock().unwrap().set_name(user.id, None);
+                    format!("You play as {} again", user.full_name())
+                }
+                Err(err) => err.to_string(),
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::Coinflip => {
+            let side = chance::coinflip(&mut rand::thread_rng());
+            bot.send_message(msg.chat.id, format!("🪙 {}", side)).await?;
+        }
+        Command::Dice(notation) => {
+            let text = match chance::Dice::parse(&notation) {
+                Ok(dice) => chance::roll_text(dice, &dice.roll(&mut rand::thread_rng())),
+                Err(err) => err.to_string(),
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::DumpState => {
+            if !msg.from().is_some_and(|user| config.is_admin(user.id)) {
+                bot.send_message(msg.chat.id, "You're not allowed to do that").await?;
+                return Ok(());
+            }
+            let state = storage.clone().get_dialogue(msg.chat.id).await?;
+            let text = match state {
+                Some(state) => dump_text(config.backend, msg.chat.id, &storage::serialize(config.backend, &state)?),
+                None => "This chat has no stored state".to_owned(),
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::Config => {
+            if !msg.from().is_some_and(|user| config.is_admin(user.id)) {
+                bot.send_message(msg.chat.id, "You're not allowed to do that").await?;
+                return Ok(());
+            }
+            bot.send_message(msg.chat.id, config_text(&config)).await?;
+        }
+        Command::Migrate(target) => {
+            if !msg.from().is_some_and(|user| config.is_admin(user.id)) {
+                bot.send_message(msg.chat.id, "You're not allowed to do that").await?;
+                return Ok(());
+            }
+            match Backend::from_name(target.trim()) {
+                Some(target) if target == config.backend => {
+                    bot.send_message(msg.chat.id, format!("This chat already uses {}", target.name())).await?;
+                }
+                Some(target @ (Backend::Sqlite | Backend::Redis)) => {
+                    let text = format!(
+                        "Copy this chat's state from {0} to {1}? Whatever {1} has for this chat is replaced.",
+                        config.backend.name(),
+                        target.name(),
+                    );
+                    let keyboard = confirm::confirm_keyboard(&confirm::Action::Migrate(target).id());
+                    bot.send_message(msg.chat.id, text).reply_markup(keyboard).await?;
+                }
+                _ => {
+                    bot.send_message(msg.chat.id, "Usage: /migrate redis or /migrate sqlite").await?;
+                }
+            }
+        }
+        _ => unreachable!("parse_global_command only lets global commands through"),
+    }
+
+    Ok(())
+}
+
+/// Whether `msg` wasn't sent by a bot. Other bots in a group could otherwise talk to this
+/// one in a loop. Messages without a sender, like those of channels, pass.
+fn is_human_message(msg: Message) -> bool {
+    !msg.from().is_some_and(|user| user.is_bot)
+}
+
+/// Whether `q` comes from a button a person pressed, for the same reason as [`is_human_message`].
+fn is_human_callback(q: CallbackQuery) -> bool {
+    !q.from.is_bot
+}
+
+/// Numbers and the commands that use them, in a chat that turned its number off with
+/// `/numbers off`. Battles and puzzles read numbers their own way and are left alone.
+fn is_disabled_number_input(msg: Message, me: Me, config: MyConfig, state: State, settings: MySettings) -> bool {
+    if !matches!(state, State::Start | State::GotNumber(..) | State::Members(_)) || msg.text().is_none() {
+        return false;
+    }
+    if settings.lock().unwrap().prefs(msg.chat.id).numbers_enabled {
+        return false;
+    }
+    match parse_command(&msg, &me, &config) {
+        Some(cmd) => matches!(
+            cmd,
+            Command::Get(_)
+                | Command::Reset
+                | Command::Add(_)
+                | Command::Sub(_)
+                | Command::Flip
+                | Command::Undo
+                | Command::Next
+                | Command::Fib
+                | Command::Preview(_)
+                | Command::Compare(_)
+                | Command::Give(_)
+                | Command::Adopt
+        ),
+        // Without a number to send, plain text has nothing to do here.
+        None => true,
+    }
+}
+
+/// Whether `user_id` may change how the bot behaves in the chat of `msg`: anybody in
+/// a private chat, the admins of a group, and the bot's admins anywhere.
+async fn may_configure_chat(bot: &AutoSend<Bot>, msg: &Message, user_id: i64, config: &Config) -> Result<bool, Error> {
+    Ok(!chat_profile(&msg.chat).has_members()
+        || config.is_admin(user_id)
+        || bot.get_chat_member(msg.chat.id, user_id).await?.kind.is_privileged())
+}
+
+/// The commands `/disable` refuses, so that a chat can always turn its commands on again.
+const ALWAYS_ENABLED: &[&str] = &["disable", "enable", "help"];
+const COMMAND_DISABLED: &str = "That command is disabled in this chat";
+
+/// The name of the command `text` starts with, lowercased and without the slash or the
+/// `@botname` suffix.
+fn command_name(text: &str) -> String {
+    let token = text.trim().trim_start_matches('/');
+    token.split(|c: char| c == '@' || c.is_whitespace()).next().unwrap_or_default().to_lowercase()
+}
+
+/// Whether `msg` is a command of this bot the chat turned off with `/disable`.
+fn is_disabled_command(msg: Message, me: Me, config: MyConfig, settings: MySettings) -> bool {
+    let text = match (parse_command(&msg, &me, &config), msg.text()) {
+        (Some(_), Some(text)) => config.resolve_command(&normalize_command(text)),
+        _ => return false,
+    };
+    calls_disabled_command(&settings.lock().unwrap(), msg.chat.id, &text)
+}
+
+/// Whether the command `text` calls, with its aliases resolved, is off in `chat_id`.
+fn calls_disabled_command(settings: &Settings, chat_id: i64, text: &str) -> bool {
+    settings.is_disabled(chat_id, &command_name(text))
+}
+
+async fn handle_disabled_command(bot: AutoSend<Bot>, msg: Message) -> HandlerResult {
+    bot.send_message(msg.chat.id, COMMAND_DISABLED).await?;
+
+    Ok(())
+}
+
+/// `/disable` and `/enable`. Without a command, lists the ones turned off.
+async fn toggle_command(
+    bot: &AutoSend<Bot>,
+    msg: &Message,
+    config: &Config,
+    settings: &MySettings,
+    name: &str,
+    disable: bool,
+) -> HandlerResult {
+    let user = match msg.from() {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    let name = command_name(name);
+    if name.is_empty() {
+        let text = match settings.lock().unwrap().disabled_commands(msg.chat.id).as_slice() {
+            [] => "No command is disabled in this chat".to_owned(),
+            names => format!("Disabled here: /{}", names.join(", /")),
+        };
+        bot.send_message(msg.chat.id, text).await?;
+        return Ok(());
+    }
+    let name = config.aliases.get(&name).cloned().unwrap_or(name);
+    if !Command::bot_commands().iter().any(|command| command.command.trim_start_matches('/') == name) {
+        bot.send_message(msg.chat.id, format!("There is no /{} command", name)).await?;
+        return Ok(());
+    }
+    if disable && ALWAYS_ENABLED.contains(&name.as_str()) {
+        bot.send_message(msg.chat.id, format!("/{} can't be disabled", name)).await?;
+        return Ok(());
+    }
+    if !may_configure_chat(bot, msg, user.id, config).await? {
+        bot.send_message(msg.chat.id, "Only the admins of this group can do that").await?;
+        return Ok(());
+    }
+    let changed = settings.lock().unwrap().set_disabled(msg.chat.id, &name, disable);
+    let text = match (disable, changed) {
+        (true, true) => format!("/{} is disabled in this chat, /enable {} turns it on again", name, name),
+        (true, false) => format!("/{} is already disabled here", name),
+        (false, true) => format!("/{} works again", name),
+        (false, false) => format!("/{} isn't disabled here", name),
+    };
+    bot.send_message(msg.chat.id, text).await?;
+
+    Ok(())
+}
+
+/// A hint for a message the bot can't read, by what it holds. `None` for service
+/// messages, such as a member joining, which need no answer.
+fn describe_unsupported(msg: &Message) -> Option<&'static str> {
+    if msg.voice().is_some() || msg.audio().is_some() || msg.video_note().is_some() {
+        Some("I can't read voice messages yet — please type the number")
+    } else if msg.photo().is_some() || msg.video().is_some() {
+        Some("I can't read numbers in pictures — please type the number")
+    } else if msg.sticker().is_some() || msg.animation().is_some() {
+        Some("Nice sticker! Type a number or a command like /battle, though")
+    } else if msg.document().is_some() {
+        Some("I only read files for /import, as a reply to the file — please type the number")
+    } else if msg.location().is_some() || msg.contact().is_some() || msg.poll().is_some() || msg.dice().is_some() {
+        Some("I only understand text — please type the number or a command")
+    } else {
+        None
+    }
+}
+
+/// Answers a message without text with [`describe_unsupported`]. In groups, only
+/// replies to the bot get the hint, so that the members can share pictures in peace.
+async fn handle_unsupported_message(bot: AutoSend<Bot>, msg: Message, me: Me) -> HandlerResult {
+    let hint = match describe_unsupported(&msg) {
+        Some(hint) => hint,
+        None => return Ok(()),
+    };
+    let to_bot = msg.reply_to_message().and_then(|reply| reply.from()).is_some_and(|user| user.id == me.user.id);
+    if chat_profile(&msg.chat).has_members() && !to_bot {
+        return Ok(());
+    }
+    bot.send_message(msg.chat.id, hint).await?;
+
+    Ok(())
+}
+
+async fn handle_disabled_number_input(bot: AutoSend<Bot>, msg: Message) -> HandlerResult {
+    bot.send_message(msg.chat.id, NUMBERS_DISABLED).await?;
+
+    Ok(())
+}
+
+/// Resets a chat whose state no handler of `dispatch_by::<State>` took, which only
+/// happens when an upgrade leaves a variant without one.
+async fn handle_unrouted_state(bot: AutoSend<Bot>, msg: Message, dialogue: MyDialogue, state: State) -> HandlerResult {
+    log::warn!("Resetting chat {}, no handler took its state {:?}", msg.chat.id, state);
+    dialogue.exit().await?;
+    bot.send_message(msg.chat.id, "Your session is out of date, resetting").await?;
+
+    Ok(())
+}
+
+/// The most characters of a `/dumpstate` dump, which keeps it within a message.
+const MAX_DUMP_LEN: usize = 3500;
+
+/// A `/dumpstate` reply: the bytes as the backend stores them, as text for JSON and in
+/// hex for Bincode. It shows the user ids and names the state holds, so it says so first.
+fn dump_text(backend: Backend, chat_id: i64, bytes: &[u8]) -> String {
+    let (dump, serializer) = match backend {
+        Backend::Redis => (bytes.iter().map(|byte| format!("{:02x}", byte)).collect(), backend.serializer_name()),
+        Backend::Sqlite => (String::from_utf8_lossy(bytes).into_owned(), backend.serializer_name()),
+        Backend::Memory => (String::from_utf8_lossy(bytes).into_owned(), "Json, though Memory keeps it unserialized"),
+    };
+    let (dump, cut) = match dump.char_indices().nth(MAX_DUMP_LEN) {
+        Some((end, _)) => (&dump[..end], ", cut short"),
+        None => (dump.as_str(), ""),
+    };
+    format!(
+        "⚠️ Internal state of chat {}: {} bytes serialized with {}{}. It includes user ids and names, don't share \
+         it.\n\n{}",
+        chat_id,
+        bytes.len(),
+        serializer,
+        cut,
+        dump,
+    )
 }
 
-async fn handle_start(bot: AutoSend<Bot>, msg: Message, dialogue: MyDialogue) -> HandlerResult {
-    match msg.text().unwrap().parse() {
+async fn handle_start(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    dialogue: MyDialogue,
+    me: Me,
+    config: MyConfig,
+    active_games: MyActiveGames,
+    settings: MySettings,
+) -> HandlerResult {
+    let text = msg.text().unwrap();
+    let lang = i18n::locale(&settings.lock().unwrap(), msg.from());
+    let number_text = |n: i32| i18n::format_number(n.into(), lang);
+    let first_contact = settings.lock().unwrap().first_contact(msg.chat.id);
+    if let (true, Some(welcome)) = (first_contact, &config.welcome) {
+        bot.send_message(msg.chat.id, welcome.as_str()).await?;
+    }
+    match text.parse() {
+        Ok(number) if chat_profile(&msg.chat).per_user_numbers(&config) => {
+            let user = match msg.from() {
+                Some(user) => user,
+                None => return Ok(()),
+            };
+            dialogue.update(State::Members(Members::with(user, number))).await?;
+            let text = format!("Remembered your number {}. Now use /get or /reset", number_text(number));
+            bot.send_message(msg.chat.id, text).await?;
+        }
         Ok(number) => {
-            dialogue.update(State::GotNumber(number)).await?;
+            dialogue.update(State::GotNumber(number, None)).await?;
             bot.send_message(
                 msg.chat.id,
-                format!("Remembered number {}. Now use /get or /reset", number),
+                format!("Remembered number {}. Now use /get or /reset", number_text(number)),
             )
             .await?;
         }
         _ => {
-            bot.send_message(msg.chat.id, "Please, send me a number").await?;
+            let ans = config.resolve_command(&normalize_command(text));
+            match Command::parse(&ans, me.user.username.unwrap()) {
+                Ok(Command::Import(payload)) => import_state(&bot, &msg, &dialogue, &payload).await?,
+                // The welcome already said hello.
+                Ok(Command::Start(payload))
+                    if payload.trim().is_empty() && first_contact && config.welcome.is_some() => {}
+                Ok(Command::Start(payload)) => {
+                    let battle = Battle::new(State::Start);
+                    start_link(&bot, &msg, &dialogue, battle, &payload, &active_games, &settings).await?;
+                }
+                Ok(Command::Version) => {
+                    bot.send_message(msg.chat.id, version_text(&config)).await?;
+                }
+                Ok(Command::Moves) => {
+                    bot.send_message(msg.chat.id, "No active game").await?;
+                }
+                Ok(Command::Clock) => {
+                    bot.send_message(msg.chat.id, battle::NO_TIMER).await?;
+                }
+                Ok(Command::Games) => {
+                    bot.send_message(msg.chat.id, "No active games").await?;
+                }
+                Ok(Command::Puzzle) => start_puzzle(&bot, &dialogue, msg.chat.id, State::Start).await?,
+                Ok(Command::Party(options)) => {
+                    start_party(&bot, &msg, &dialogue, State::Start, &options, &settings).await?;
+                }
+                Ok(Command::Sandbox(size)) => start_sandbox(&bot, &msg, &dialogue, State::Start, &size).await?,
+                Ok(Command::Race(target)) => start_race(&bot, &msg, &dialogue, State::Start, 0, &target).await?,
+                Ok(Command::Adopt) => match (adopted_number(&msg), msg.from()) {
+                    (Ok(number), Some(user)) if chat_profile(&msg.chat).per_user_numbers(&config) => {
+                        dialogue.update(State::Members(Members::with(user, number))).await?;
+                        let text = format!("Adopted your number {}", number_text(number));
+                        send_confirmation(&bot, &msg, &settings, &text).await?;
+                    }
+                    (Ok(number), _) => {
+                        dialogue.update(State::GotNumber(number, None)).await?;
+                        let text = format!("Adopted number {}", number_text(number));
+                        send_confirmation(&bot, &msg, &settings, &text).await?;
+                    }
+                    (Err(text), _) => {
+                        bot.send_message(msg.chat.id, text).await?;
+                    }
+                },
+                _ => {
+                    bot.send_message(msg.chat.id, "Please, send me a number").await?;
+                }
+            }
         }
     }
 
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_got_number(
     bot: AutoSend<Bot>,
     msg: Message,
     dialogue: MyDialogue,
-    num: i32,
+    (num, target): (i32, Option<i32>),
     me: Me,
+    config: MyConfig,
+    active_games: MyActiveGames,
+    history: MyHistory,
+    settings: MySettings,
 ) -> HandlerResult {
-    let ans = msg.text().unwrap();
+    let ans = config.resolve_command(&normalize_command(msg.text().unwrap()));
     let bot_name = me.user.username.unwrap();
+    let lang = i18n::locale(&settings.lock().unwrap(), msg.from());
+    let number_text = |n: i32| i18n::format_number(n.into(), lang);
 
-    match Command::parse(ans, bot_name) {
+    match Command::parse(&ans, bot_name) {
         Ok(cmd) => match cmd {
-            Command::Get => {
-                bot.send_message(msg.chat.id, format!("Here is your number: {}", num)).await?;
+            Command::Get(_) => {
+                bot.send_message(msg.chat.id, format!("Here is your number: {}", number_text(num))).await?;
             }
             Command::Reset => {
+                history.lock().unwrap().clear(msg.chat.id);
                 dialogue.reset().await?;
-                bot.send_message(msg.chat.id, "Number resetted").await?;
+                send_confirmation(&bot, &msg, &settings, "Number resetted").await?;
+            }
+            Command::Add(number_str) | Command::Sub(number_str) if is_oversized_operand(&number_str) => {
+                bot.send_message(msg.chat.id, OVERSIZED_OPERAND).await?;
             }
             Command::Add(number_str) => {
-                let number: i32 = number_str.parse()?;
-                dialogue.update(State::AddNumber(num+number)).await?;
-                bot.send_message(msg.chat.id, format!("Number added, now {}", num+number)).await?;
+                let number = match parse_operand(&number_str) {
+                    Some(number) => number,
+                    None => {
+                        bot.send_message(msg.chat.id, Op::Add.usage()).await?;
+                        return Ok(());
+                    }
+                };
+                let result = match Op::Add.apply(num, number) {
+                    Some(result) => result,
+                    None => {
+                        bot.send_message(msg.chat.id, Op::Add.overflow_text(num, number, lang)).await?;
+                        return Ok(());
+                    }
+                };
+                history.lock().unwrap().push(msg.chat.id, num);
+                dialogue.update(State::AddNumber(result, target)).await?;
+                let text = format!("Number added, now {}{}", number_text(result), target_text(result, target, lang));
+                send_status(&bot, &msg, &settings, text).await?;
             }
             Command::Sub(number_str) => {
-                let number: i32 = number_str.parse()?;
-                dialogue.update(State::SubNumber(num-number)).await?;
-                bot.send_message(msg.chat.id, format!("Number subed, now {}", num-number)).await?;
-            }
-            Command::Battle => {
-                let mut keyboard: Vec<Vec<InlineKeyboardButton>> = vec![];
-
-                let button_name = ["0", "1", "2", "3", "4", "5", "6", "7", "8"]; 
-
-                for name in button_name.chunks(3) {
-                    let row = name
-                        .iter()
-                        .map(|&name| InlineKeyboardButton::callback(name.to_owned(), name.to_owned()))
-                        .collect();
-                    keyboard.push(row);
-                }                
-                bot.send_message(msg.chat.id, "Let's battle!")
-                .reply_markup(InlineKeyboardMarkup::new(keyboard))
-                .await?;
+                let number = match parse_operand(&number_str) {
+                    Some(number) => number,
+                    None => {
+                        bot.send_message(msg.chat.id, Op::Sub.usage()).await?;
+                        return Ok(());
+                    }
+                };
+                let result = match Op::Sub.apply(num, number) {
+                    Some(result) => result,
+                    None => {
+                        bot.send_message(msg.chat.id, Op::Sub.overflow_text(num, number, lang)).await?;
+                        return Ok(());
+                    }
+                };
+                history.lock().unwrap().push(msg.chat.id, num);
+                dialogue.update(State::SubNumber(result, target)).await?;
+                let text = format!("Number subed, now {}{}", number_text(result), target_text(result, target, lang));
+                send_status(&bot, &msg, &settings, text).await?;
+            }
+            Command::Target(args) => {
+                let text = match args.trim() {
+                    "" => match target {
+                        Some(target) => {
+                            format!("Your target is {}{}", number_text(target), target_text(num, Some(target), lang))
+                        }
+                        None => "You have no target, set one with /target <number>".to_owned(),
+                    },
+                    "clear" => {
+                        dialogue.update(State::GotNumber(num, None)).await?;
+                        "Target cleared".to_owned()
+                    }
+                    args => match args.parse() {
+                        Ok(target) => {
+                            dialogue.update(State::GotNumber(num, Some(target))).await?;
+                            format!("Target set to {}{}", number_text(target), target_text(num, Some(target), lang))
+                        }
+                        Err(_) => "Use /target <number>, or /target clear".to_owned(),
+                    },
+                };
+                bot.send_message(msg.chat.id, text).await?;
+            }
+            Command::Preview(args) => {
+                let preview = args.trim().split_once(char::is_whitespace).and_then(|(op, number)| {
+                    Some((op, Op::parse(op)?, number.trim().parse::<i32>().ok()?))
+                });
+                match preview {
+                    Some((name, op, number)) => {
+                        let text = match op.apply(num, number) {
+                            Some(result) => format!(
+                                "If you /{} {} → {}",
+                                name.to_lowercase(),
+                                number_text(number),
+                                number_text(result)
+                            ),
+                            None => op.overflow_text(num, number, lang),
+                        };
+                        bot.send_message(msg.chat.id, text).await?;
+                    }
+                    None => {
+                        bot.send_message(msg.chat.id, "Use /preview add <number> or /preview sub <number>").await?;
+                    }
+                }
+            }
+            Command::Battle(options) | Command::NewGame(options) => {
+                let battle = Battle::new(State::GotNumber(num, target));
+                start_battle(&bot, &msg, &dialogue, battle, &options, &active_games, &settings).await?;
+            }
+            Command::ResetLeaderboard => {
+                if !msg.from().is_some_and(|user| config.is_admin(user.id)) {
+                    bot.send_message(msg.chat.id, "You're not allowed to do that").await?;
+                    return Ok(());
+                }
+                let keyboard = confirm::confirm_keyboard(&confirm::Action::ResetLeaderboard.id());
+                bot.send_message(msg.chat.id, "Archive the current leaderboard and start a new season?")
+                    .reply_markup(keyboard)
+                    .await?;
+            }
+            Command::Pause
+            | Command::Resume
+            | Command::Clone
+            | Command::Simulate
+            | Command::Seed(_)
+            | Command::Resize(_)
+            | Command::Takeback
+            | Command::Resign
+            | Command::Cancel
+            | Command::Board => {
+                bot.send_message(msg.chat.id, "There is no battle in progress").await?;
+            }
+            Command::Moves => {
+                bot.send_message(msg.chat.id, "No active game").await?;
+            }
+            Command::Clock => {
+                bot.send_message(msg.chat.id, battle::NO_TIMER).await?;
+            }
+            Command::Compare(_) => {
+                bot.send_message(msg.chat.id, "Comparing needs per-user numbers").await?;
+            }
+            Command::Give(_) => {
+                bot.send_message(msg.chat.id, "Giving needs per-user numbers").await?;
+            }
+            Command::Games => {
+                bot.send_message(msg.chat.id, "No active games").await?;
+            }
+            Command::Export => {
+                let state = dialogue.get().await?.unwrap_or_default();
+                let json = String::from_utf8(Json.serialize(&state)?)?;
+                bot.send_message(msg.chat.id, format!("/import {}", json)).await?;
+            }
+            Command::Import(payload) => {
+                import_state(&bot, &msg, &dialogue, &payload).await?;
+            }
+            Command::Start(payload) => {
+                let battle = Battle::new(State::GotNumber(num, target));
+                start_link(&bot, &msg, &dialogue, battle, &payload, &active_games, &settings).await?;
+            }
+            Command::Version => {
+                bot.send_message(msg.chat.id, version_text(&config)).await?;
+            }
+            Command::Flip => match flip(num) {
+                Some(flipped) => {
+                    history.lock().unwrap().push(msg.chat.id, num);
+                    dialogue.update(State::GotNumber(flipped, target)).await?;
+                    send_status(&bot, &msg, &settings, format!("Number flipped, now {}", number_text(flipped))).await?;
+                }
+                None => {
+                    bot.send_message(msg.chat.id, flip_overflow_text(num, lang)).await?;
+                }
+            },
+            Command::Next => match collatz_step(num) {
+                Some(next) => {
+                    history.lock().unwrap().push(msg.chat.id, num);
+                    dialogue.update(State::GotNumber(next, target)).await?;
+                    send_status(&bot, &msg, &settings, format!("Collatz step, now {}", number_text(next))).await?;
+                }
+                None => {
+                    bot.send_message(msg.chat.id, format!("3 × {} + 1 doesn't fit", number_text(num))).await?;
+                }
+            },
+            Command::Fib => {
+                // The numbers pair up like Fibonacci numbers, (0, n) when there's no number before.
+                let previous = history.lock().unwrap().last(msg.chat.id).unwrap_or(0);
+                match num.checked_add(previous) {
+                    Some(next) => {
+                        history.lock().unwrap().push(msg.chat.id, num);
+                        dialogue.update(State::GotNumber(next, target)).await?;
+                        let (previous, num, next) = (number_text(previous), number_text(num), number_text(next));
+                        let text = format!("{} + {}, now {}", previous, num, next);
+                        send_status(&bot, &msg, &settings, text).await?;
+                    }
+                    None => {
+                        let text = format!("{} + {} doesn't fit", number_text(previous), number_text(num));
+                        bot.send_message(msg.chat.id, text).await?;
+                    }
+                }
+            }
+            // Handled by `handle_global_command` and the other handlers that run before the dialogue is entered.
+            Command::Lang(_)
+            | Command::Settings
+            | Command::Tournament(_)
+            | Command::Stop
+            | Command::Rename(_)
+            | Command::Alerts(_)
+            | Command::Migrate(_)
+            | Command::DumpState
+            | Command::Config
+            | Command::Coinflip
+            | Command::Dice(_)
+            | Command::Feedback(_)
+            | Command::Stats(_)
+            | Command::Winstats
+            | Command::GlobalStats
+            | Command::Anon(_)
+            | Command::ResetStats
+            | Command::ExportLeaderboard
+            | Command::Backup
+            | Command::Restore
+            | Command::Replay
+            | Command::Ping
+            | Command::About
+            | Command::SetHistory(_)
+            | Command::Numbers(_)
+            | Command::Disable(_)
+            | Command::Enable(_) => {}
+            // Handled by `handle_help`, `handle_snapshot_command`, `handle_challenge_command` and `handle_transfer`.
+            Command::Help(_) => {}
+            Command::Save(_)
+            | Command::Load(_)
+            | Command::Snapshots
+            | Command::ClearSnapshots
+            | Command::Challenge(_)
+            | Command::Accept
+            | Command::Transfer(_) => {}
+            Command::Puzzle => start_puzzle(&bot, &dialogue, msg.chat.id, State::GotNumber(num, target)).await?,
+            Command::Party(options) => {
+                start_party(&bot, &msg, &dialogue, State::GotNumber(num, target), &options, &settings).await?;
+            }
+            Command::Sandbox(size) => {
+                start_sandbox(&bot, &msg, &dialogue, State::GotNumber(num, target), &size).await?;
+            }
+            Command::Race(goal) => {
+                start_race(&bot, &msg, &dialogue, State::GotNumber(num, target), num, &goal).await?;
+            }
+            Command::Leave => {
+                bot.send_message(msg.chat.id, NOT_IN_PARTY).await?;
+            }
+            Command::Adopt => match adopted_number(&msg) {
+                Ok(number) => {
+                    history.lock().unwrap().push(msg.chat.id, num);
+                    dialogue.update(State::GotNumber(number, target)).await?;
+                    send_confirmation(&bot, &msg, &settings, &format!("Adopted number {}", number_text(number))).await?;
+                }
+                Err(text) => {
+                    bot.send_message(msg.chat.id, text).await?;
+                }
+            },
+            Command::Undo => {
+                let previous = history.lock().unwrap().pop(msg.chat.id);
+                match previous {
+                    Some(previous) => {
+                        dialogue.update(State::GotNumber(previous, target)).await?;
+                        send_status(&bot, &msg, &settings, format!("Undone, now {}", number_text(previous))).await?;
+                    }
+                    None => {
+                        bot.send_message(msg.chat.id, "Nothing to undo").await?;
+                    }
+                }
             }
         },
         Err(_) => {
@@ -160,21 +1550,2951 @@ async fn handle_got_number(
     Ok(())
 }
 
-async fn handle_callback(
-    q: CallbackQuery,
+/// Like [`handle_got_number`], but every member of the group has their own number.
+#[allow(clippy::too_many_arguments)]
+async fn handle_members(
     bot: AutoSend<Bot>,
+    msg: Message,
+    dialogue: MyDialogue,
+    mut members: Members,
+    me: Me,
+    config: MyConfig,
+    active_games: MyActiveGames,
+    settings: MySettings,
 ) -> HandlerResult {
-    bot.answer_callback_query(q.id).await?;
-    if let Some(q_data) = q.data {
-        let from = q.from;
-        match q.message {
-            Some(Message { id, chat, .. }) => {
-                bot.edit_message_text(chat.id, id, format!("{} click {}", from.full_name(), q_data)).await?;
+    let user = match msg.from() {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    let text = msg.text().unwrap();
+    let lang = i18n::locale(&settings.lock().unwrap(), msg.from());
+    let number_text = |n: i32| i18n::format_number(n.into(), lang);
+    if let Ok(number) = text.trim().parse() {
+        members.set(user, number);
+        dialogue.update(State::Members(members)).await?;
+        send_confirmation(&bot, &msg, &settings, &format!("Remembered your number {}", number_text(number))).await?;
+        return Ok(());
+    }
+
+    let ans = config.resolve_command(&normalize_command(text));
+    let bot_name = me.user.username.unwrap();
+    let num = members.get(user.id).map(|m| m.number);
+    let reply = match (Command::parse(&ans, bot_name), num) {
+        (Ok(Command::Get(who)), _) => {
+            let who = who.trim();
+            let target = if !who.is_empty() {
+                members.find(who)
+            } else {
+                let replied = msg.reply_to_message().and_then(|reply| reply.from()).map_or(user.id, |u| u.id);
+                members.get(replied)
+            };
+            match target {
+                Some(member) if member.id == user.id => {
+                    format!("Here is your number: {}", number_text(member.number))
+                }
+                Some(member) => format!("{}'s number is {}", member.name, number_text(member.number)),
+                None => "They don't have a number yet".to_owned(),
             }
-            None => {
-                log::info!("{}", q_data);
+        }
+        (Ok(Command::Compare(who)), _) => {
+            let replied = msg.reply_to_message().and_then(|reply| reply.from()).map(|u| u.id);
+            let names: Vec<_> = who.split_whitespace().collect();
+            let lookup = |name: &str| members.find(name).ok_or_else(|| format!("{} doesn't have a number yet", name));
+            let own = |id: i64| members.get(id).ok_or_else(|| "One of you doesn't have a number yet".to_owned());
+            let pair = match (names.as_slice(), replied) {
+                ([a, b], _) => lookup(a).and_then(|a| Ok((a, lookup(b)?))),
+                ([b], Some(a)) => own(a).and_then(|a| Ok((a, lookup(b)?))),
+                ([b], None) => own(user.id).and_then(|a| Ok((a, lookup(b)?))),
+                ([], Some(b)) => own(user.id).and_then(|a| Ok((a, own(b)?))),
+                _ => Err("Use /compare @a @b, or reply to someone with /compare".to_owned()),
+            };
+            match pair {
+                Ok((a, b)) => compare_text(a, b, lang),
+                Err(text) => text,
             }
         }
-    }
+        (Ok(Command::Give(args)), _) => {
+            let args: Vec<_> = args.split_whitespace().collect();
+            let replied = msg.reply_to_message().and_then(|reply| reply.from()).map(|u| u.id);
+            let recipient = match (args.as_slice(), replied) {
+                ([name, amount], _) => Some((members.find(name).map(|m| m.id), *amount)),
+                ([amount], Some(id)) => Some((Some(id), *amount)),
+                _ => None,
+            };
+            match recipient.and_then(|(to, amount)| Some((to, amount.parse::<i32>().ok()?))) {
+                None => "Use /give @user <amount>, or reply to someone with /give <amount>".to_owned(),
+                Some((None, _)) => "They don't have a number yet".to_owned(),
+                Some((Some(to), amount)) => match members.give(user.id, to, amount, config.give_floor) {
+                    Ok((own, theirs)) => {
+                        let name = members.get(to).map_or_else(String::new, |m| m.name.clone());
+                        dialogue.update(State::Members(members)).await?;
+                        let (amount, own, theirs) = (number_text(amount), number_text(own), number_text(theirs));
+                        format!("Gave {} to {}: you now have {}, they have {}", amount, name, own, theirs)
+                    }
+                    Err(err) => err.to_string(),
+                },
+            }
+        }
+        (Ok(Command::Reset), _) => {
+            members.remove(user.id);
+            if members.is_empty() {
+                dialogue.reset().await?;
+            } else {
+                dialogue.update(State::Members(members)).await?;
+            }
+            "Your number resetted".to_owned()
+        }
+        (Ok(Command::Add(number_str) | Command::Sub(number_str)), Some(_)) if is_oversized_operand(&number_str) => {
+            OVERSIZED_OPERAND.to_owned()
+        }
+        (Ok(Command::Add(number_str)), Some(num)) => match parse_operand(&number_str) {
+            None => Op::Add.usage().to_owned(),
+            Some(number) => match Op::Add.apply(num, number) {
+                Some(result) => {
+                    members.set(user, result);
+                    dialogue.update(State::Members(members)).await?;
+                    format!("Number added, now {}", number_text(result))
+                }
+                None => Op::Add.overflow_text(num, number, lang),
+            },
+        },
+        (Ok(Command::Sub(number_str)), Some(num)) => match parse_operand(&number_str) {
+            None => Op::Sub.usage().to_owned(),
+            Some(number) => match Op::Sub.apply(num, number) {
+                Some(result) => {
+                    members.set(user, result);
+                    dialogue.update(State::Members(members)).await?;
+                    format!("Number subed, now {}", number_text(result))
+                }
+                None => Op::Sub.overflow_text(num, number, lang),
+            },
+        },
+        (Ok(Command::Flip), Some(num)) => match flip(num) {
+            Some(flipped) => {
+                members.set(user, flipped);
+                dialogue.update(State::Members(members)).await?;
+                format!("Number flipped, now {}", number_text(flipped))
+            }
+            None => flip_overflow_text(num, lang),
+        },
+        (Ok(Command::Next), Some(num)) => match collatz_step(num) {
+            Some(next) => {
+                members.set(user, next);
+                dialogue.update(State::Members(members)).await?;
+                format!("Collatz step, now {}", number_text(next))
+            }
+            None => format!("3 × {} + 1 doesn't fit", number_text(num)),
+        },
+        (Ok(Command::Puzzle), _) => {
+            return start_puzzle(&bot, &dialogue, msg.chat.id, State::Members(members)).await;
+        }
+        (Ok(Command::Party(options)), _) => {
+            return start_party(&bot, &msg, &dialogue, State::Members(members), &options, &settings).await;
+        }
+        (Ok(Command::Sandbox(size)), _) => {
+            return start_sandbox(&bot, &msg, &dialogue, State::Members(members), &size).await;
+        }
+        (Ok(Command::Leave), _) => NOT_IN_PARTY.to_owned(),
+        (Ok(Command::Adopt), _) => match adopted_number(&msg) {
+            Ok(number) => {
+                members.set(user, number);
+                dialogue.update(State::Members(members)).await?;
+                format!("Adopted your number {}", number_text(number))
+            }
+            Err(text) => text.to_owned(),
+        },
+        (Ok(Command::Add(_) | Command::Sub(_) | Command::Flip | Command::Next), None) => {
+            "Send me your number first".to_owned()
+        }
+        (Ok(Command::Battle(options) | Command::NewGame(options)), _) => {
+            let battle = Battle::new(State::Members(members));
+            return start_battle(&bot, &msg, &dialogue, battle, &options, &active_games, &settings).await;
+        }
+        (Ok(Command::Export), _) => {
+            let json = String::from_utf8(Json.serialize(&State::Members(members))?)?;
+            format!("/import {}", json)
+        }
+        (Ok(Command::Import(payload)), _) => return import_state(&bot, &msg, &dialogue, &payload).await,
+        (Ok(Command::Start(payload)), _) => {
+            let battle = Battle::new(State::Members(members));
+            return start_link(&bot, &msg, &dialogue, battle, &payload, &active_games, &settings).await;
+        }
+        (Ok(Command::Version), _) => version_text(&config),
+        (
+            Ok(
+                Command::Pause
+                | Command::Resume
+                | Command::Clone
+                | Command::Simulate
+                | Command::Seed(_)
+                | Command::Resize(_)
+                | Command::Takeback
+                | Command::Resign
+                | Command::Cancel
+                | Command::Board,
+            ),
+            _,
+        ) => {
+            "There is no battle in progress".to_owned()
+        }
+        (Ok(Command::Moves), _) => "No active game".to_owned(),
+        (Ok(Command::Clock), _) => battle::NO_TIMER.to_owned(),
+        (Ok(Command::Games), _) => "No active games".to_owned(),
+        (Ok(Command::Undo | Command::Fib | Command::Preview(_) | Command::Target(_) | Command::Race(_)), _) => {
+            "Not available with per-user numbers".to_owned()
+        }
+        _ => "Please, send a number, /get or /reset".to_owned(),
+    };
+    bot.send_message(msg.chat.id, reply).await?;
+
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Sends `text` to the chat of `msg`, as a reply to `msg` with the chat's
+/// `reply_to_commands` preference on. Should `msg` be deleted in the meantime, Telegram
+/// sends the text as a plain message instead of failing.
+fn reply(
+    bot: &AutoSend<Bot>,
+    msg: &Message,
+    settings: &MySettings,
+    text: impl Into<String>,
+) -> <AutoSend<Bot> as Requester>::SendMessage {
+    let request = bot.send_message(msg.chat.id, text);
+    if settings.lock().unwrap().prefs(msg.chat.id).reply_to_commands {
+        request.reply_to_message_id(msg.id).allow_sending_without_reply(true)
+    } else {
+        request
+    }
+}
+
+/// Sends a confirmation of a number change. With the chat's `auto_delete` preference set,
+/// it's deleted after that many seconds.
+async fn send_confirmation(bot: &AutoSend<Bot>, msg: &Message, settings: &MySettings, text: &str) -> HandlerResult {
+    let chat_id = msg.chat.id;
+    let sent = reply(bot, msg, settings, text).await?;
+    let delay = settings.lock().unwrap().prefs(chat_id).auto_delete;
+    if let Some(secs) = delay {
+        let bot = bot.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_secs(secs)).await;
+            // Someone may have deleted it already; either way, it's gone.
+            if let Err(err) = bot.delete_message(chat_id, sent.id).await {
+                log::info!("Failed to delete a confirmation in chat {}: {}", chat_id, err);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Sends the result of a number operation. With the chat's `edit_status` preference on,
+/// one status message is edited instead, and sent again if it can't be edited (e.g.
+/// because it was deleted).
+async fn send_status(bot: &AutoSend<Bot>, msg: &Message, settings: &MySettings, text: String) -> HandlerResult {
+    let chat_id = msg.chat.id;
+    let status = {
+        let settings = settings.lock().unwrap();
+        settings.prefs(chat_id).edit_status.then(|| settings.status_message(chat_id))
+    };
+    let previous = match status {
+        Some(previous) => previous,
+        None => return send_confirmation(bot, msg, settings, &text).await,
+    };
+
+    if let Some(message_id) = previous {
+        match bot.edit_message_text(chat_id, message_id, text.clone()).await.map_err(Error::from) {
+            Ok(_) => return Ok(()),
+            // The status already says so, e.g. after a second `/add 0`.
+            Err(err) if err.is_not_modified() => return Ok(()),
+            Err(err) => log::info!("Sending a new status message in chat {}: {}", chat_id, err),
+        }
+    }
+    let sent = reply(bot, msg, settings, text).await?;
+    settings.lock().unwrap().set_status_message(chat_id, sent.id);
+
+    Ok(())
+}
+
+/// What follows a new number: how far `target` still is, or a cheer once it's hit.
+/// Empty without a target.
+fn target_text(number: i32, target: Option<i32>, lang: &str) -> String {
+    let target = match target {
+        Some(target) => target,
+        None => return String::new(),
+    };
+    let distance = (i64::from(target) - i64::from(number)).abs();
+    if distance == 0 {
+        format!(". 🎯 You hit your target of {}!", i18n::format_number(target.into(), lang))
+    } else {
+        format!(
+            ". {} away from your target of {}",
+            i18n::format_number(distance, lang),
+            i18n::format_number(target.into(), lang)
+        )
+    }
+}
+
+fn compare_text(a: &members::Member, b: &members::Member, lang: &str) -> String {
+    let number_text = |n: i32| i18n::format_number(n.into(), lang);
+    let (high, low) = if a.number >= b.number { (a, b) } else { (b, a) };
+    if high.number == low.number {
+        return format!("{} and {} both have {}", a.name, b.name, number_text(a.number));
+    }
+    let diff = i64::from(high.number) - i64::from(low.number);
+    let (high_number, low_number) = (number_text(high.number), number_text(low.number));
+    let diff = i18n::format_number(diff, lang);
+    format!("{} ({}) is ahead of {} ({}) by {}", high.name, high_number, low.name, low_number, diff)
+}
+
+/// The arithmetic commands, shared by the commands themselves and `/preview`.
+#[derive(Clone, Copy)]
+enum Op {
+    Add,
+    Sub,
+}
+
+impl Op {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "add" => Some(Op::Add),
+            "sub" => Some(Op::Sub),
+            _ => None,
+        }
+    }
+
+    /// `num` with `operand` added or subtracted, `None` if that overflows.
+    fn apply(self, num: i32, operand: i32) -> Option<i32> {
+        match self {
+            Op::Add => num.checked_add(operand),
+            Op::Sub => num.checked_sub(operand),
+        }
+    }
+
+    /// The reply to an operand [`parse_operand`] refused.
+    fn usage(self) -> &'static str {
+        match self {
+            Op::Add => "Use /add <number>",
+            Op::Sub => "Use /sub <number>",
+        }
+    }
+
+    /// The reply to an operation [`Op::apply`] refused.
+    fn overflow_text(self, num: i32, operand: i32, lang: &str) -> String {
+        let sign = match self {
+            Op::Add => '+',
+            Op::Sub => '-',
+        };
+        let number_text = |n: i32| i18n::format_number(n.into(), lang);
+        format!("{} {} {} doesn't fit", number_text(num), sign, number_text(operand))
+    }
+}
+
+/// The number of an `/add` or a `/sub`, `None` unless it's one that fits an `i32`.
+fn parse_operand(arg: &str) -> Option<i32> {
+    arg.trim().parse().ok()
+}
+
+/// `num` negated by `/flip`, `None` for [`i32::MIN`], whose negation doesn't fit.
+fn flip(num: i32) -> Option<i32> {
+    num.checked_neg()
+}
+
+/// The reply to a `/flip` of `num` that [`flip`] refused.
+fn flip_overflow_text(num: i32, lang: &str) -> String {
+    format!("{} can't be flipped, its negation doesn't fit", i18n::format_number(num.into(), lang))
+}
+
+/// One step of the Collatz sequence from `num`, `None` if it overflows.
+fn collatz_step(num: i32) -> Option<i32> {
+    if num % 2 == 0 {
+        Some(num / 2)
+    } else {
+        num.checked_mul(3)?.checked_add(1)
+    }
+}
+
+/// What a `/battle` asks for.
+struct BattleOptions {
+    size: usize,
+    /// How many in a row win, if fewer than the board size.
+    win_length: Option<usize>,
+    mode: Mode,
+    condition: WinCondition,
+    /// Show the AI's assessment of the position.
+    learn: bool,
+}
+
+/// Parses the `/battle` options. Without `pass` or an AI option, the game plays the
+/// [`ChatProfile::default_mode`] of the chat. Anything else is the board size, which can
+/// be given once, then how many in a row win.
+fn parse_battle_options(options: &str, profile: ChatProfile) -> Result<BattleOptions, SizeError> {
+    let mut size = None;
+    let mut win_length = None;
+    let mut mode = None;
+    let mut condition = WinCondition::Normal;
+    let mut learn = false;
+    for option in args::tokenize(options)? {
+        match option.to_lowercase().as_str() {
+            "misere" | "misère" => condition = WinCondition::Misere,
+            "learn" => learn = true,
+            "pass" => mode = Some(Mode::PassAndPlay),
+            "ai" | "medium" => mode = Some(Mode::VsAi(Difficulty::Medium)),
+            "easy" => mode = Some(Mode::VsAi(Difficulty::Easy)),
+            "hard" => mode = Some(Mode::VsAi(Difficulty::Hard)),
+            _ if win_length.is_some() => return Err(SizeError::NotNumeric),
+            option => match size {
+                Some(size) => win_length = Some(battle::parse_win_length(option, size)?),
+                None => size = Some(battle::parse_board_size(option)?),
+            },
+        }
+    }
+
+    let size = size.unwrap_or(battle::MIN_BOARD_SIZE);
+    Ok(BattleOptions { size, win_length, mode: mode.unwrap_or(profile.default_mode()), condition, learn })
+}
+
+/// Restores a state exported with `/export`. The JSON comes from the command argument,
+/// or from the text or document of the message being replied to.
+async fn import_state(
+    bot: &AutoSend<Bot>,
+    msg: &Message,
+    dialogue: &MyDialogue,
+    payload: &str,
+) -> HandlerResult {
+    let payload = match import_payload(bot, msg, payload).await? {
+        Ok(payload) => payload,
+        Err(reason) => {
+            bot.send_message(msg.chat.id, reason).await?;
+            return Ok(());
+        }
+    };
+
+    match parse_import(&payload) {
+        Ok(state) => {
+            dialogue.update(state).await?;
+            bot.send_message(msg.chat.id, "State imported").await?;
+        }
+        Err(reason) => {
+            bot.send_message(msg.chat.id, reason).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds the JSON to import. The inner error is a reply for the user.
+async fn import_payload(
+    bot: &AutoSend<Bot>,
+    msg: &Message,
+    payload: &str,
+) -> Result<Result<Vec<u8>, &'static str>, Error> {
+    let payload = payload.trim();
+    if !payload.is_empty() {
+        return Ok(Ok(payload.as_bytes().to_vec()));
+    }
+
+    let usage = "Send /import with the exported JSON, or reply with /import to a message containing it";
+    let reply = match msg.reply_to_message() {
+        Some(reply) => reply,
+        None => return Ok(Err(usage)),
+    };
+    if let Some(text) = reply.text() {
+        let text = text.trim();
+        return Ok(Ok(text.strip_prefix("/import").unwrap_or(text).trim().as_bytes().to_vec()));
+    }
+    match reply.document() {
+        Some(document) if document.file_size.is_none_or(|size| size as usize > MAX_IMPORT_SIZE) => {
+            Ok(Err("That's too big to be an exported state"))
+        }
+        Some(document) => {
+            let file = bot.get_file(&document.file_id).await?;
+            let mut contents = Vec::new();
+            bot.download_file(&file.file_path, &mut contents).await?;
+            Ok(Ok(contents))
+        }
+        None => Ok(Err(usage)),
+    }
+}
+
+/// Validates an imported state. Only number states are accepted: battles refer to
+/// messages of the chat they were exported from.
+fn parse_import(payload: &[u8]) -> Result<State, &'static str> {
+    if payload.len() > MAX_IMPORT_SIZE {
+        return Err("That's too big to be an exported state");
+    }
+    match Json.deserialize(payload) {
+        Ok(State::Battle(_)) => Err("Battles can't be imported"),
+        Ok(State::Puzzle(_)) => Err("Puzzles can't be imported"),
+        Ok(State::Party(_)) => Err("Party games can't be imported"),
+        Ok(State::Sandbox(_)) => Err("Sandboxes can't be imported"),
+        Ok(State::Race(_)) => Err("Races can't be imported"),
+        Ok(state) => Ok(state),
+        Err(_) => Err("That doesn't look like an exported state"),
+    }
+}
+
+/// Whether the operand of `/add` or `/sub` is too long to be a number at all, checked so
+/// that huge inputs aren't parsed.
+fn is_oversized_operand(input: &str) -> bool {
+    input.trim().len() > MAX_OPERAND_LEN
+}
+
+/// The number `/adopt` takes: the first integer in the text of the message `msg` replies
+/// to, with its sign. The error is a reply for the user.
+fn adopted_number(msg: &Message) -> Result<i32, &'static str> {
+    let text = match msg.reply_to_message() {
+        Some(reply) => reply.text().or_else(|| reply.caption()).unwrap_or_default(),
+        None => return Err("Reply with /adopt to a message containing a number"),
+    };
+    let start = text.find(|c: char| c.is_ascii_digit()).ok_or("There is no number in that message")?;
+    let digits = &text[start..];
+    let end = digits.find(|c: char| !c.is_ascii_digit()).unwrap_or(digits.len());
+    let negative = text[..start].ends_with('-');
+    let number = if negative { &text[start - 1..start + end] } else { &digits[..end] };
+    if is_oversized_operand(number) {
+        return Err(OVERSIZED_OPERAND);
+    }
+    number.parse().map_err(|_| OVERSIZED_OPERAND)
+}
+
+/// Trims `text` and lowercases its command token, so that `/ADD 5` and ` /Get` parse
+/// like `/add 5` and `/get`. The `@BotName` suffix and the arguments are left untouched.
+fn normalize_command(text: &str) -> String {
+    let text = text.trim();
+    let (token, rest) = match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim_start()),
+        None => (text, ""),
+    };
+    let (name, suffix) = match token.find('@') {
+        Some(i) => token.split_at(i),
+        None => (token, ""),
+    };
+
+    if rest.is_empty() {
+        format!("{}{}", name.to_lowercase(), suffix)
+    } else {
+        format!("{}{} {}", name.to_lowercase(), suffix, rest)
+    }
+}
+
+/// Sends the board of a new game and adds the game to `battle`.
+async fn start_battle(
+    bot: &AutoSend<Bot>,
+    msg: &Message,
+    dialogue: &MyDialogue,
+    mut battle: Battle,
+    options: &str,
+    active_games: &MyActiveGames,
+    settings: &MySettings,
+) -> HandlerResult {
+    let options = match parse_battle_options(options, chat_profile(&msg.chat)) {
+        Ok(options) => options,
+        Err(err) => {
+            bot.send_message(msg.chat.id, err.to_string()).await?;
+            return Ok(());
+        }
+    };
+
+    let host = msg.from().map(|user| user.id);
+    if !host.is_none_or(|id| active_games.lock().unwrap().try_start(id, now())) {
+        bot.send_message(msg.chat.id, GAME_START_COOLDOWN).await?;
+        return Ok(());
+    }
+
+    let mut game = Game::new(options.size, options.mode, options.condition, now());
+    if let Some(win_length) = options.win_length {
+        game.board = game::Board::with_win_length(options.size, win_length);
+    }
+    game.host = host;
+    game.learn = options.learn;
+    send_game(bot, msg, &mut battle, game, active_games, settings).await?;
+    dialogue.update(battle.into_state()).await?;
+
+    Ok(())
+}
+
+/// Handles `/start` and the payload of deep links (`t.me/<bot>?start=<payload>`):
+/// `battle` or `ai`, optionally followed by `/battle` options joined with `_`
+/// (`ai_hard`), starts a battle, and `join_<game id>` takes the free seat of that game.
+async fn start_link(
+    bot: &AutoSend<Bot>,
+    msg: &Message,
+    dialogue: &MyDialogue,
+    mut battle: Battle,
+    payload: &str,
+    active_games: &MyActiveGames,
+    settings: &MySettings,
+) -> HandlerResult {
+    let payload = payload.trim();
+    if payload.is_empty() {
+        let text = "Hi! Send me a number to remember, or /battle to play";
+        bot.send_message(msg.chat.id, text).await?;
+        return Ok(());
+    }
+
+    if let Some(game_id) = payload.strip_prefix("join_") {
+        let user = match msg.from() {
+            Some(user) => user,
+            None => return Ok(()),
+        };
+        let entry = game_id.parse().ok().and_then(|id| Some((id, battle.games.get_mut(&id)?)));
+        let (game_id, entry) = match entry {
+            Some(found) => found,
+            None => {
+                bot.send_message(msg.chat.id, "That game is over or doesn't exist").await?;
+                return Ok(());
+            }
+        };
+        if let Err(err) = entry.game.join(player(user, settings)) {
+            bot.send_message(msg.chat.id, err.to_string()).await?;
+            return Ok(());
+        }
+        bot.edit_message_text(msg.chat.id, entry.message_id, battle_text(&entry.game))
+            .reply_markup(board_keyboard(game_id, &entry.game))
+            .await?;
+        dialogue.update(State::Battle(battle)).await?;
+        return Ok(());
+    }
+
+    let options = match payload.split_once('_') {
+        Some((kind, options)) => (kind, options.replace('_', " ")),
+        None => (payload, String::new()),
+    };
+    match options {
+        ("battle", options) => start_battle(bot, msg, dialogue, battle, &options, active_games, settings).await,
+        ("ai", options) => {
+            let options = if options.is_empty() { "ai".to_owned() } else { options };
+            start_battle(bot, msg, dialogue, battle, &options, active_games, settings).await
+        }
+        _ => {
+            bot.send_message(msg.chat.id, "I don't know that link").await?;
+            Ok(())
+        }
+    }
+}
+
+/// Sends the board of `game_id` again as a new message, which its buttons move to.
+async fn resend_board(
+    bot: &AutoSend<Bot>,
+    dialogue: &MyDialogue,
+    chat_id: i64,
+    mut battle: Battle,
+    game_id: u32,
+    active_games: &MyActiveGames,
+) -> HandlerResult {
+    let entry = battle.games.get_mut(&game_id).expect("resent boards are of running games");
+    let sent = bot.send_message(chat_id, battle_text(&entry.game))
+        .reply_markup(board_keyboard(game_id, &entry.game))
+        .await?;
+    let old = std::mem::replace(&mut entry.message_id, sent.id);
+    active_games.lock().unwrap().insert(
+        chat_id,
+        game_id,
+        ActiveGame::new(sent.id, &entry.game),
+    );
+    expiry::move_pin(bot, chat_id, old, entry).await;
+    dialogue.update(State::Battle(battle)).await?;
+    // The old board's buttons are ignored from now on, say where the game went.
+    if let Err(err) = bot.edit_message_text(chat_id, old, "This game moved to a new message").await {
+        log::warn!("Failed to retire the old board in chat {}: {}", chat_id, err);
+    }
+    Ok(())
+}
+
+/// Shows `text` and `keyboard` on the board of `entry`. If the board was deleted, the
+/// game goes on in a new message, which `entry` and the sweeper switch to.
+async fn edit_or_repost_board(
+    bot: &AutoSend<Bot>,
+    active_games: &MyActiveGames,
+    chat_id: i64,
+    game_id: u32,
+    entry: &mut BattleGame,
+    text: String,
+    keyboard: InlineKeyboardMarkup,
+) -> HandlerResult {
+    let edited =
+        render::edit_board(bot, active_games, chat_id, entry.message_id, text.clone(), Some(keyboard.clone())).await;
+    match edited {
+        Err(err) if err.is_message_gone() => {
+            log::info!("The board of game {} in chat {} is gone, sending a new one", game_id, chat_id);
+            let sent = bot.send_message(chat_id, text).reply_markup(keyboard).await?;
+            let old = std::mem::replace(&mut entry.message_id, sent.id);
+            active_games.lock().unwrap().insert(chat_id, game_id, ActiveGame::new(sent.id, &entry.game));
+            expiry::move_pin(bot, chat_id, old, entry).await;
+            Ok(())
+        }
+        edited => edited,
+    }
+}
+
+/// Shows the restarted game of `entry` on its board message.
+async fn restart_board(
+    bot: &AutoSend<Bot>,
+    chat_id: i64,
+    game_id: u32,
+    entry: &BattleGame,
+    active_games: &MyActiveGames,
+) -> HandlerResult {
+    active_games.lock().unwrap().insert(
+        chat_id,
+        game_id,
+        ActiveGame::new(entry.message_id, &entry.game),
+    );
+    active_games.lock().unwrap().start_turn(chat_id, game_id, now());
+    let size = entry.game.board.size();
+    let text = format!("Restarted on a {0}×{0} board!\n{1}", size, battle_text(&entry.game));
+    bot.edit_message_text(chat_id, entry.message_id, text)
+        .reply_markup(board_keyboard(game_id, &entry.game))
+        .await?;
+    Ok(())
+}
+
+/// Sends the board of `game` as a new message and adds the game to `battle`.
+async fn send_game(
+    bot: &AutoSend<Bot>,
+    msg: &Message,
+    battle: &mut Battle,
+    mut game: Game,
+    active_games: &MyActiveGames,
+    settings: &MySettings,
+) -> HandlerResult {
+    let chat_id = msg.chat.id;
+    if !battle::fits_keyboard(&game.board) {
+        let size = battle::MAX_KEYBOARD_BOARD;
+        bot.send_message(chat_id, format!("Boards bigger than {0}×{0} can't be played with buttons", size)).await?;
+        return Ok(());
+    }
+    if active_games.lock().unwrap().is_full(chat_id) {
+        bot.send_message(chat_id, TOO_MANY_GAMES).await?;
+        return Ok(());
+    }
+    game.repetition_limit = active_games.lock().unwrap().repetition_limit();
+    let game_id = battle.next_game_id();
+    let sent = reply(bot, msg, settings, battle_text(&game))
+        .reply_markup(board_keyboard(game_id, &game))
+        .await?;
+    active_games.lock().unwrap().insert(
+        chat_id,
+        game_id,
+        ActiveGame::new(sent.id, &game),
+    );
+    let mut entry = BattleGame { message_id: sent.id, game, pinned: false };
+    expiry::pin_board(bot, settings, chat_id, &mut entry).await;
+    battle.games.insert(game_id, entry);
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_battle(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    dialogue: MyDialogue,
+    mut battle: Battle,
+    me: Me,
+    config: MyConfig,
+    active_games: MyActiveGames,
+    settings: MySettings,
+) -> HandlerResult {
+    let ans = config.resolve_command(&normalize_command(msg.text().unwrap()));
+    let bot_name = me.user.username.unwrap();
+    let cmd = Command::parse(&ans, bot_name);
+    match cmd {
+        Ok(Command::Version) => {
+            bot.send_message(msg.chat.id, version_text(&config)).await?;
+            return Ok(());
+        }
+        Ok(Command::Battle(_)) if msg.from().is_some_and(|user| !battle.may_start(user.id)) => {
+            bot.send_message(msg.chat.id, GAME_IN_PROGRESS).await?;
+            return Ok(());
+        }
+        Ok(Command::Battle(options)) => {
+            return start_battle(&bot, &msg, &dialogue, battle, &options, &active_games, &settings).await;
+        }
+        Ok(Command::Start(payload)) => {
+            return start_link(&bot, &msg, &dialogue, battle, &payload, &active_games, &settings).await;
+        }
+        Ok(Command::Puzzle) => {
+            bot.send_message(msg.chat.id, "Finish the battle first, then try a /puzzle").await?;
+            return Ok(());
+        }
+        Ok(Command::Party(_)) => {
+            bot.send_message(msg.chat.id, "Finish the battle first, then start a /party").await?;
+            return Ok(());
+        }
+        Ok(Command::Sandbox(_)) => {
+            bot.send_message(msg.chat.id, "Finish the battle first, then open a /sandbox").await?;
+            return Ok(());
+        }
+        Ok(Command::Race(_)) => {
+            bot.send_message(msg.chat.id, "Finish the battle first, then start a /race").await?;
+            return Ok(());
+        }
+        Ok(Command::Games) => {
+            let (text, keyboard) = games_list(&battle);
+            bot.send_message(msg.chat.id, text).reply_markup(keyboard).await?;
+            return Ok(());
+        }
+        Ok(Command::Board) => {
+            let own = msg.from().and_then(|user| battle.game_of(user.id));
+            return match own.or_else(|| battle.games.keys().last().copied()) {
+                Some(game_id) => resend_board(&bot, &dialogue, msg.chat.id, battle, game_id, &active_games).await,
+                None => Ok(()),
+            };
+        }
+        Ok(Command::Moves) => {
+            let own = msg.from().and_then(|user| battle.game_of(user.id));
+            let game = own.and_then(|id| battle.games.get(&id)).or_else(|| battle.games.values().last());
+            let text = game.map_or_else(|| "No active game".to_owned(), |entry| moves_text(&entry.game));
+            bot.send_message(msg.chat.id, text).await?;
+            return Ok(());
+        }
+        Ok(Command::Clock) => {
+            let own = msg.from().and_then(|user| battle.game_of(user.id));
+            let text = match own.or_else(|| battle.games.keys().last().copied()) {
+                Some(game_id) => {
+                    let timer = settings.lock().unwrap().prefs(msg.chat.id).move_timer;
+                    let clocks = active_games.lock().unwrap();
+                    let left = timer.and_then(|timer| clocks.seconds_left(msg.chat.id, game_id, timer, now()));
+                    battle::clock_text(&battle.games[&game_id].game, timer, left)
+                }
+                None => battle::NO_TIMER.to_owned(),
+            };
+            bot.send_message(msg.chat.id, text).await?;
+            return Ok(());
+        }
+        _ => {}
+    }
+    let (user, game_id) = match msg.from().and_then(|user| Some((user, battle.game_of(user.id)?))) {
+        Some(found) => found,
+        None => {
+            bot.send_message(msg.chat.id, "A battle is in progress, make your move on the board").await?;
+            return Ok(());
+        }
+    };
+    let entry = battle.games.get_mut(&game_id).expect("game_of returns running games");
+
+    match cmd {
+        Ok(Command::Pause) => {
+            if let GameStatus::Paused { .. } = entry.game.status {
+                bot.send_message(msg.chat.id, "The battle is already paused").await?;
+                return Ok(());
+            }
+            entry.game.status = GameStatus::Paused { by: user.full_name() };
+            active_games.lock().unwrap().pause_turn(msg.chat.id, game_id, now());
+            bot.edit_message_text(msg.chat.id, entry.message_id, battle_text(&entry.game)).await?;
+            dialogue.update(State::Battle(battle)).await?;
+        }
+        Ok(Command::Resume) => {
+            if entry.game.status == GameStatus::Playing {
+                bot.send_message(msg.chat.id, "The battle isn't paused").await?;
+                return Ok(());
+            }
+            entry.game.status = GameStatus::Playing;
+            active_games.lock().unwrap().resume_turn(msg.chat.id, game_id, now());
+            bot.edit_message_text(msg.chat.id, entry.message_id, battle_text(&entry.game))
+                .reply_markup(board_keyboard(game_id, &entry.game))
+                .await?;
+            dialogue.update(State::Battle(battle)).await?;
+        }
+        Ok(Command::Seed(seed)) => {
+            if !config.is_admin(user.id) {
+                bot.send_message(msg.chat.id, "You're not allowed to do that").await?;
+                return Ok(());
+            }
+            let seed = seed.trim();
+            let text = if seed.is_empty() {
+                entry.game.seed = None;
+                "The bot's moves are random again".to_owned()
+            } else {
+                match seed.parse() {
+                    Ok(seed) => {
+                        entry.game.seed = Some(seed);
+                        format!("The bot's moves are seeded with {}", seed)
+                    }
+                    Err(_) => {
+                        bot.send_message(msg.chat.id, "Use /seed <number>, or /seed to unset it").await?;
+                        return Ok(());
+                    }
+                }
+            };
+            dialogue.update(State::Battle(battle)).await?;
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Ok(Command::Resize(size)) => {
+            let size = match size.trim().parse::<usize>() {
+                Ok(size) if (3..=battle::MAX_KEYBOARD_BOARD).contains(&size) => size,
+                _ => {
+                    let text = format!("Use /resize with a size from 3 to {}", battle::MAX_KEYBOARD_BOARD);
+                    bot.send_message(msg.chat.id, text).await?;
+                    return Ok(());
+                }
+            };
+            let opponent = entry.game.players.iter().find(|p| p.id != user.id).map(|p| p.id);
+            let opponent = match (entry.game.mode, opponent) {
+                (Mode::TwoPlayer, None) => {
+                    bot.send_message(msg.chat.id, "Both seats need a player before resizing").await?;
+                    return Ok(());
+                }
+                (Mode::TwoPlayer, Some(opponent)) => Some(opponent),
+                // Nobody else has to agree against the bot or when playing both sides.
+                _ => None,
+            };
+            if opponent.is_none() {
+                entry.game = entry.game.restarted(size, now());
+                restart_board(&bot, msg.chat.id, game_id, entry, &active_games).await?;
+                dialogue.update(State::Battle(battle)).await?;
+                return Ok(());
+            }
+
+            entry.game.pending_resize = Some(game::ResizeRequest { size, by: user.id });
+            let keyboard = InlineKeyboardMarkup::new(vec![vec![
+                InlineKeyboardButton::callback("Accept".to_owned(), format!("resize:{}:yes", game_id)),
+                InlineKeyboardButton::callback("Decline".to_owned(), format!("resize:{}:no", game_id)),
+            ]]);
+            let text = format!("{} wants to restart on a {1}×{1} board", user.full_name(), size);
+            bot.send_message(msg.chat.id, text).reply_markup(keyboard).await?;
+            dialogue.update(State::Battle(battle)).await?;
+        }
+        Ok(Command::Takeback) => {
+            if let Err(err) = entry.game.request_takeback(user.id, now()) {
+                bot.send_message(msg.chat.id, err.to_string()).await?;
+                return Ok(());
+            }
+            let keyboard = InlineKeyboardMarkup::new(vec![vec![
+                InlineKeyboardButton::callback("Accept".to_owned(), format!("takeback:{}:yes", game_id)),
+                InlineKeyboardButton::callback("Decline".to_owned(), format!("takeback:{}:no", game_id)),
+            ]]);
+            let text = format!(
+                "{} asks to take back the last move, accept within {} seconds",
+                user.full_name(),
+                game::TAKEBACK_TIMEOUT
+            );
+            bot.send_message(msg.chat.id, text).reply_markup(keyboard).await?;
+            dialogue.update(State::Battle(battle)).await?;
+        }
+        Ok(Command::Simulate) => {
+            let game = entry.game.clone();
+            if game.board.size() > ai::MAX_SIMULATE_SIZE {
+                let text = format!("Games can be simulated on boards up to {0}×{0}", ai::MAX_SIMULATE_SIZE);
+                bot.send_message(msg.chat.id, text).await?;
+                return Ok(());
+            }
+            let (board, turn, condition) = (game.board.clone(), game.turn, game.condition);
+            let text = match tokio::task::spawn_blocking(move || ai::simulate(&board, turn, condition)).await {
+                Ok(Some(simulation)) => battle::simulation_text(&game, &simulation),
+                Ok(None) => "The game is over".to_owned(),
+                Err(err) => {
+                    log::error!("The simulation failed in chat {}: {}", msg.chat.id, err);
+                    "The simulation failed".to_owned()
+                }
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Ok(Command::Clone) => {
+            if !active_games.lock().unwrap().try_start(user.id, now()) {
+                bot.send_message(msg.chat.id, GAME_START_COOLDOWN).await?;
+                return Ok(());
+            }
+            let fork = entry.game.fork(now());
+            send_game(&bot, &msg, &mut battle, fork, &active_games, &settings).await?;
+            dialogue.update(State::Battle(battle)).await?;
+        }
+        _ => {
+            bot.send_message(msg.chat.id, "A battle is in progress, make your move on the board").await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// `/tournament start` opens registration with a join button and seeds the bracket
+/// once the join window is over; `/tournament status` shows the bracket.
+async fn handle_tournament(
+    bot: &AutoSend<Bot>,
+    msg: &Message,
+    dialogue: &MyDialogue,
+    action: &str,
+    config: &MyConfig,
+    tournaments: &MyTournaments,
+    active_games: &MyActiveGames,
+) -> HandlerResult {
+    let chat_id = msg.chat.id;
+    match action.trim() {
+        "start" => {
+            if !msg.from().is_some_and(|user| config.is_admin(user.id)) {
+                bot.send_message(chat_id, "You're not allowed to do that").await?;
+                return Ok(());
+            }
+            if !tournaments.lock().unwrap().open(chat_id) {
+                bot.send_message(chat_id, "A tournament is already running here").await?;
+                return Ok(());
+            }
+            let keyboard = InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+                "Join".to_owned(),
+                "tour:join".to_owned(),
+            )]]);
+            let window = config.tournament_join_window;
+            let sent = bot
+                .send_message(
+                    chat_id,
+                    format!("A tournament is starting! Press Join in the next {} seconds", window.as_secs()),
+                )
+                .reply_markup(keyboard)
+                .await?;
+
+            let (bot, dialogue, tournaments, active_games) =
+                (bot.clone(), dialogue.clone(), tournaments.clone(), active_games.clone());
+            tokio::spawn(async move {
+                tokio::time::sleep(window).await;
+                let (pairings, text) = {
+                    let mut tournaments = tournaments.lock().unwrap();
+                    // Cancelled in the meantime, e.g. by /stop.
+                    let tournament = match tournaments.get_mut(chat_id) {
+                        Some(tournament) if tournament.is_joining() => tournament,
+                        _ => return,
+                    };
+                    if tournament.players().len() < 2 {
+                        tournament.cancel();
+                        (Vec::new(), "Not enough players joined, the tournament is cancelled".to_owned())
+                    } else {
+                        let pairings = tournament.seed();
+                        (pairings, tournament.status_text())
+                    }
+                };
+                let result = async {
+                    bot.edit_message_text(chat_id, sent.id, text).await?;
+                    tournament::start_matches(&bot, &dialogue, &active_games, &tournaments, chat_id, pairings)
+                        .await
+                };
+                if let Err(err) = result.await {
+                    log::error!("Failed to start the tournament in chat {}: {}", chat_id, err);
+                }
+            });
+        }
+        "status" => {
+            let text = tournaments
+                .lock()
+                .unwrap()
+                .get(chat_id)
+                .map_or_else(|| "No tournament here yet".to_owned(), |t| t.status_text());
+            bot.send_message(chat_id, text).await?;
+        }
+        _ => {
+            bot.send_message(chat_id, "Use /tournament start or /tournament status").await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_duplicate_callback(q: CallbackQuery, seen_callbacks: MySeenCallbacks) -> bool {
+    seen_callbacks.lock().unwrap().check(&q.id, std::time::Instant::now())
+}
+
+/// Only stops the loading spinner of a callback that was already handled.
+async fn answer_duplicate_callback(q: CallbackQuery, bot: AutoSend<Bot>) -> HandlerResult {
+    log::debug!("Ignoring the redelivered callback {}", q.id);
+    bot.answer_callback_query(q.id).await?;
+    Ok(())
+}
+
+fn parse_confirm_callback(q: CallbackQuery) -> Option<(confirm::Action, bool)> {
+    let (action_id, yes) = confirm::parse_confirm_data(q.data.as_deref()?)?;
+    Some((confirm::Action::parse(action_id)?, yes))
+}
+
+/// The admin who sent a `/restore` and the answer to its confirmation.
+fn parse_restore_callback(q: CallbackQuery) -> Option<(i64, bool)> {
+    match parse_confirm_callback(q)? {
+        (confirm::Action::Restore(admin_id), yes) => Some((admin_id, yes)),
+        _ => None,
+    }
+}
+
+/// Answers the confirmation of a `/restore`, which only the admin who sent the backup
+/// can answer, and replaces every store the backup has on yes.
+#[allow(clippy::too_many_arguments)]
+async fn handle_restore_callback(
+    q: CallbackQuery,
+    bot: AutoSend<Bot>,
+    (admin_id, yes): (i64, bool),
+    config: MyConfig,
+    stats: MyStats,
+    settings: MySettings,
+    snapshots: MySnapshots,
+    history: MyHistory,
+    restores: MyPendingRestores,
+) -> HandlerResult {
+    let message = match &q.message {
+        Some(message) => message,
+        None => return Ok(()),
+    };
+    if q.from.id != admin_id || !config.is_admin(q.from.id) {
+        bot.answer_callback_query(q.id).text("Only the admin who sent the backup can answer").await?;
+        return Ok(());
+    }
+    bot.answer_callback_query(q.id).await?;
+
+    let text = match restores.lock().unwrap().take(admin_id) {
+        Some(backup) if yes => {
+            stats.lock().unwrap().restore(backup.stats);
+            settings.lock().unwrap().restore(backup.settings);
+            snapshots.lock().unwrap().restore(backup.snapshots);
+            history.lock().unwrap().restore(backup.history);
+            format!("Restored the backup from {}", snapshots::date_text(backup.created_at))
+        }
+        None if yes => "That backup was restored or dropped already, send /restore again".to_owned(),
+        _ => "Restore cancelled".to_owned(),
+    };
+    bot.edit_message_text(message.chat.id, message.id, text).await?;
+
+    Ok(())
+}
+
+/// Answers the buttons of [`confirm::confirm_keyboard`], carrying out the action on yes.
+/// `/resetstats` is answered by whoever asked for it, every other action by admins only.
+/// `/restore` is answered by [`handle_restore_callback`].
+async fn handle_confirm_callback(
+    q: CallbackQuery,
+    bot: AutoSend<Bot>,
+    (action, yes): (confirm::Action, bool),
+    config: MyConfig,
+    storage: MyStorage,
+    stats: MyStats,
+) -> HandlerResult {
+    let message = match &q.message {
+        Some(message) => message,
+        None => return Ok(()),
+    };
+    let refusal = match action {
+        confirm::Action::ResetStats(user_id) if q.from.id != user_id => Some("Only the player who asked can answer"),
+        confirm::Action::ResetStats(_) => None,
+        _ if !config.is_admin(q.from.id) => Some("You're not allowed to do that"),
+        _ => None,
+    };
+    if let Some(refusal) = refusal {
+        bot.answer_callback_query(q.id).text(refusal).await?;
+        return Ok(());
+    }
+    bot.answer_callback_query(q.id).await?;
+
+    let chat_id = message.chat.id;
+    let text = match (action, yes) {
+        (confirm::Action::ResetLeaderboard, true) => {
+            let cleared = stats.lock().unwrap().archive_season();
+            format!("Leaderboard reset, {} records archived", cleared)
+        }
+        (confirm::Action::ResetLeaderboard, false) => "Leaderboard reset cancelled".to_owned(),
+        (confirm::Action::Migrate(target), true) => {
+            match storage::copy_chat(&storage, target, config.storage_timeout, chat_id).await {
+                Ok(true) => format!("Copied this chat's state to {}", target.name()),
+                Ok(false) => "This chat has no state to copy".to_owned(),
+                Err(err) => {
+                    log::error!("Failed to copy the state of chat {} to {}: {}", chat_id, target.name(), err);
+                    format!("Couldn't copy the state to {}: {}", target.name(), err)
+                }
+            }
+        }
+        (confirm::Action::Migrate(_), false) => "Migration cancelled".to_owned(),
+        (confirm::Action::ResetStats(user_id), true) => match stats.lock().unwrap().reset_user(user_id) {
+            0 => "You had no games to clear".to_owned(),
+            1 => "Your stats are cleared, 1 game removed".to_owned(),
+            cleared => format!("Your stats are cleared, {} games removed", cleared),
+        },
+        (confirm::Action::ResetStats(_), false) => "Your stats stay as they are".to_owned(),
+        (confirm::Action::Restore(_), _) => unreachable!("parse_restore_callback takes the /restore answers"),
+    };
+    bot.edit_message_text(chat_id, message.id, text).await?;
+
+    Ok(())
+}
+
+fn is_settings_callback(q: CallbackQuery) -> bool {
+    q.data.as_deref().is_some_and(|data| data.starts_with("set:"))
+}
+
+/// Applies a `/settings` panel button and redraws the panel.
+async fn handle_settings_callback(
+    q: CallbackQuery,
+    bot: AutoSend<Bot>,
+    config: MyConfig,
+    settings: MySettings,
+) -> HandlerResult {
+    let message = match &q.message {
+        Some(message) => message,
+        None => return Ok(()),
+    };
+    if !may_configure_chat(&bot, message, q.from.id, &config).await? {
+        bot.answer_callback_query(q.id).text("Only admins can change settings").await?;
+        return Ok(());
+    }
+    let key = q.data.as_deref().and_then(|data| data.strip_prefix("set:")).unwrap_or_default();
+    let prefs = {
+        let mut settings = settings.lock().unwrap();
+        let prefs = settings.prefs_mut(message.chat.id);
+        prefs.toggle(key).then_some(*prefs)
+    };
+
+    match prefs {
+        Some(prefs) => {
+            bot.answer_callback_query(q.id).await?;
+            bot.edit_message_reply_markup(message.chat.id, message.id)
+                .reply_markup(settings::settings_keyboard(&prefs))
+                .await?;
+        }
+        None => {
+            bot.answer_callback_query(q.id).text("Unknown setting").await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// What a button [`handle_callback`] answers asks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CallbackData<'a> {
+    TournamentJoin,
+    /// `jump:` to the board of a game, by its id.
+    Jump(&'a str),
+    /// The answer to a `/resize` request, `<game id>:yes` or `<game id>:no`.
+    Resize(&'a str),
+    /// The answer to a `/takeback` request, like [`CallbackData::Resize`].
+    Takeback(&'a str),
+    Party(PartyButton),
+    Sandbox(SandboxButton),
+    /// A cell of a puzzle.
+    Puzzle(usize),
+    /// The button that switches the first mark of a game.
+    First(u32),
+    Board(u32, BoardButton),
+}
+
+/// Parses the data of a button [`handle_callback`] answers. Empty or unknown data, e.g.
+/// of a button an older version of the bot made, is `None`.
+fn parse_callback_data(data: &str) -> Option<CallbackData<'_>> {
+    if data == "tour:join" {
+        return Some(CallbackData::TournamentJoin);
+    }
+    if let Some(game_id) = data.strip_prefix("jump:") {
+        return Some(CallbackData::Jump(game_id));
+    }
+    if let Some(answer) = data.strip_prefix("resize:") {
+        return Some(CallbackData::Resize(answer));
+    }
+    if let Some(answer) = data.strip_prefix("takeback:") {
+        return Some(CallbackData::Takeback(answer));
+    }
+    party::parse_party_data(data)
+        .map(CallbackData::Party)
+        .or_else(|| sandbox::parse_sandbox_data(data).map(CallbackData::Sandbox))
+        .or_else(|| puzzle::parse_puzzle_data(data).map(CallbackData::Puzzle))
+        .or_else(|| battle::parse_first_data(data).map(CallbackData::First))
+        .or_else(|| battle::parse_board_data(data).map(|(game_id, button)| CallbackData::Board(game_id, button)))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_callback(
+    q: CallbackQuery,
+    bot: AutoSend<Bot>,
+    dialogue: MyDialogue,
+    state: State,
+    config: MyConfig,
+    stats: MyStats,
+    active_games: MyActiveGames,
+    tournaments: MyTournaments,
+    settings: MySettings,
+) -> HandlerResult {
+    let from = q.from;
+    let (q_data, Message { id, chat, .. }) = match (q.data, q.message) {
+        (Some(q_data), Some(message)) if !q_data.is_empty() => (q_data, message),
+        (q_data, message) => {
+            // Nothing to act on, but the button's spinner still has to stop.
+            let chat_id = message.map(|message| message.chat.id);
+            log::warn!("Ignoring a callback from {} with data {:?} in chat {:?}", from.id, q_data, chat_id);
+            bot.answer_callback_query(q.id).await?;
+            return Ok(());
+        }
+    };
+    let data = match parse_callback_data(&q_data) {
+        Some(data) => data,
+        None => {
+            log::warn!("Unknown callback data {:?} from {} in chat {}", q_data, from.id, chat.id);
+            bot.answer_callback_query(q.id).text("This button doesn't work anymore").await?;
+            return Ok(());
+        }
+    };
+
+    if data == CallbackData::TournamentJoin {
+        let joined = tournaments.lock().unwrap().get_mut(chat.id).map(|tournament| {
+            let result = tournament.join(player(&from, &settings));
+            (result, tournament.status_text())
+        });
+        match joined {
+            Some((Ok(()), text)) => {
+                bot.answer_callback_query(q.id).text("You're in!").await?;
+                bot.edit_message_text(chat.id, id, text)
+                    .reply_markup(InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+                        "Join".to_owned(),
+                        "tour:join".to_owned(),
+                    )]]))
+                    .await?;
+            }
+            Some((Err(err), _)) => {
+                bot.answer_callback_query(q.id).text(err.to_string()).await?;
+            }
+            None => {
+                bot.answer_callback_query(q.id).await?;
+            }
+        }
+        return Ok(());
+    }
+
+    if let CallbackData::Jump(game_id) = data {
+        bot.answer_callback_query(q.id).await?;
+        let battle = match state {
+            State::Battle(battle) => battle,
+            _ => return Ok(()),
+        };
+        match game_id.parse().ok().filter(|id| battle.games.contains_key(id)) {
+            Some(game_id) => resend_board(&bot, &dialogue, chat.id, battle, game_id, &active_games).await?,
+            None => {
+                bot.send_message(chat.id, "That game is over").await?;
+            }
+        }
+        return Ok(());
+    }
+
+    if let CallbackData::Resize(answer) = data {
+        let mut battle = match state {
+            State::Battle(battle) => battle,
+            _ => {
+                bot.answer_callback_query(q.id).text("That game is over").await?;
+                return Ok(());
+            }
+        };
+        let (game_id, accepted) = match answer.split_once(':') {
+            Some((game_id, answer)) => (game_id.parse().ok(), answer == "yes"),
+            None => (None, false),
+        };
+        let (game_id, entry) = match game_id.and_then(|id| Some((id, battle.games.get_mut(&id)?))) {
+            Some((game_id, entry)) if entry.game.is_player(from.id) => (game_id, entry),
+            _ => {
+                bot.answer_callback_query(q.id).text("That's not your game").await?;
+                return Ok(());
+            }
+        };
+        let request = match entry.game.pending_resize {
+            Some(request) => request,
+            None => {
+                bot.answer_callback_query(q.id).text("Nothing to answer anymore").await?;
+                return Ok(());
+            }
+        };
+        if accepted && request.by == from.id {
+            bot.answer_callback_query(q.id).text("The other player has to accept").await?;
+            return Ok(());
+        }
+
+        bot.answer_callback_query(q.id).await?;
+        entry.game.pending_resize = None;
+        if accepted {
+            entry.game = entry.game.restarted(request.size, now());
+            restart_board(&bot, chat.id, game_id, entry, &active_games).await?;
+            bot.edit_message_text(chat.id, id, format!("Resized to {0}×{0}", request.size)).await?;
+        } else {
+            bot.edit_message_text(chat.id, id, "Resize declined, the game goes on").await?;
+        }
+        dialogue.update(State::Battle(battle)).await?;
+        return Ok(());
+    }
+
+    if let CallbackData::Takeback(answer) = data {
+        let mut battle = match state {
+            State::Battle(battle) => battle,
+            _ => {
+                bot.answer_callback_query(q.id).text("That game is over").await?;
+                return Ok(());
+            }
+        };
+        let (game_id, accepted) = match answer.split_once(':') {
+            Some((game_id, answer)) => (game_id.parse().ok(), answer == "yes"),
+            None => (None, false),
+        };
+        let (game_id, entry) = match game_id.and_then(|id| Some((id, battle.games.get_mut(&id)?))) {
+            Some((game_id, entry)) if entry.game.is_player(from.id) => (game_id, entry),
+            _ => {
+                bot.answer_callback_query(q.id).text("That's not your game").await?;
+                return Ok(());
+            }
+        };
+        if !accepted {
+            if entry.game.pending_takeback.take().is_none() {
+                bot.answer_callback_query(q.id).text("Nothing to answer anymore").await?;
+                return Ok(());
+            }
+            bot.answer_callback_query(q.id).await?;
+            bot.edit_message_text(chat.id, id, "Takeback declined, the game goes on").await?;
+            dialogue.update(State::Battle(battle)).await?;
+            return Ok(());
+        }
+        match entry.game.accept_takeback(from.id, now()) {
+            Ok(count) => {
+                bot.answer_callback_query(q.id).await?;
+                active_games.lock().unwrap().start_turn(chat.id, game_id, now());
+                let (text, keyboard) = (battle_text(&entry.game), board_keyboard(game_id, &entry.game));
+                edit_or_repost_board(&bot, &active_games, chat.id, game_id, entry, text, keyboard).await?;
+                let text = match count {
+                    1 => "1 move taken back".to_owned(),
+                    count => format!("{} moves taken back", count),
+                };
+                bot.edit_message_text(chat.id, id, text).await?;
+            }
+            Err(err @ game::TakebackError::OwnRequest) => {
+                bot.answer_callback_query(q.id).text(err.to_string()).await?;
+                return Ok(());
+            }
+            Err(err) => {
+                bot.answer_callback_query(q.id).text(err.to_string()).await?;
+                bot.edit_message_text(chat.id, id, err.to_string()).await?;
+            }
+        }
+        dialogue.update(State::Battle(battle)).await?;
+        return Ok(());
+    }
+
+    if let CallbackData::Party(button) = data {
+        let mut party = match state {
+            State::Party(party) if party.message_id == id => party,
+            _ => return close_stale_board(&bot, q.id, chat.id, id).await,
+        };
+        let outcome = match button {
+            PartyButton::Join => party.join(player(&from, &settings)).map(|()| PartyOutcome::Going),
+            PartyButton::Start if party.host() != from.id => {
+                bot.answer_callback_query(q.id).text("Only the host can start the party").await?;
+                return Ok(());
+            }
+            PartyButton::Start => party.start().map(|()| PartyOutcome::Going),
+            PartyButton::Cell(cell) => party.play(from.id, cell),
+        };
+        match outcome {
+            Ok(outcome) => {
+                bot.answer_callback_query(q.id).await?;
+                close_party(&bot, &dialogue, &stats, chat.id, party, outcome).await?;
+            }
+            Err(err) => {
+                bot.answer_callback_query(q.id).text(err.to_string()).await?;
+            }
+        }
+        return Ok(());
+    }
+
+    if let CallbackData::Sandbox(button) = data {
+        let mut sandbox = match state {
+            State::Sandbox(sandbox) if sandbox.message_id == id => sandbox,
+            _ => return close_stale_board(&bot, q.id, chat.id, id).await,
+        };
+        if sandbox.owner != from.id {
+            bot.answer_callback_query(q.id).text("This sandbox is someone else's").await?;
+            return Ok(());
+        }
+        let analysis = match button {
+            SandboxButton::Cell(cell) if !sandbox.cycle(cell) => {
+                bot.answer_callback_query(q.id).text("There is no such cell").await?;
+                return Ok(());
+            }
+            SandboxButton::Cell(_) => None,
+            SandboxButton::Solve => {
+                let board = sandbox.board.clone();
+                match tokio::task::spawn_blocking(move || sandbox::analysis_text(&board)).await {
+                    Ok(analysis) => Some(analysis),
+                    Err(err) => {
+                        log::error!("The sandbox analysis failed in chat {}: {}", chat.id, err);
+                        bot.answer_callback_query(q.id).text("The analysis failed").await?;
+                        return Ok(());
+                    }
+                }
+            }
+            SandboxButton::Close => {
+                bot.answer_callback_query(q.id).await?;
+                let text = format!("{}\n\nSandbox closed", battle::board_text(&sandbox.board));
+                bot.edit_message_text(chat.id, id, text).await?;
+                dialogue.update(sandbox.into_previous()).await?;
+                return Ok(());
+            }
+        };
+        bot.answer_callback_query(q.id).await?;
+        bot.edit_message_text(chat.id, id, sandbox::sandbox_text(&sandbox, analysis.as_deref()))
+            .reply_markup(sandbox::sandbox_keyboard(&sandbox))
+            .await?;
+        dialogue.update(State::Sandbox(sandbox)).await?;
+        return Ok(());
+    }
+
+    if let CallbackData::Puzzle(cell) = data {
+        let puzzle = match state {
+            State::Puzzle(puzzle) if puzzle.message_id == id => puzzle,
+            _ => return close_stale_board(&bot, q.id, chat.id, id).await,
+        };
+        let solution = puzzle::solution_text(&puzzle);
+        let text = if cell == puzzle.solution {
+            bot.answer_callback_query(q.id).text("Correct!").await?;
+            let solved = stats.lock().unwrap().record_puzzle(from.id);
+            let name = player(&from, &settings).name;
+            format!("{}\n\n✅ {} found the winning move! Puzzles solved: {}", solution, name, solved)
+        } else {
+            bot.answer_callback_query(q.id).text("Not this one").await?;
+            format!("{}\n\n❌ That's not it, the winning move is the star", solution)
+        };
+        bot.edit_message_text(chat.id, id, text).await?;
+        dialogue.update(puzzle.into_previous()).await?;
+        return Ok(());
+    }
+
+    if let CallbackData::First(game_id) = data {
+        let mut battle = match state {
+            State::Battle(battle) => battle,
+            _ => return close_stale_board(&bot, q.id, chat.id, id).await,
+        };
+        let entry = match battle.games.get_mut(&game_id).filter(|g| g.message_id == id) {
+            Some(entry) => entry,
+            None => return close_stale_board(&bot, q.id, chat.id, id).await,
+        };
+        if entry.game.host != Some(from.id) {
+            bot.answer_callback_query(q.id).text("Only the player who started the game can choose").await?;
+            return Ok(());
+        }
+        if !entry.game.switch_first() {
+            bot.answer_callback_query(q.id).text("The game has already started").await?;
+            return Ok(());
+        }
+        bot.answer_callback_query(q.id).await?;
+        active_games.lock().unwrap().start_turn(chat.id, game_id, now());
+        bot.edit_message_text(chat.id, id, battle_text(&entry.game))
+            .reply_markup(board_keyboard(game_id, &entry.game))
+            .await?;
+        dialogue.update(State::Battle(battle)).await?;
+        return Ok(());
+    }
+
+    let (mut battle, game_id, button) = match (state, data) {
+        (State::Battle(battle), CallbackData::Board(game_id, button))
+            if battle.games.get(&game_id).is_some_and(|g| g.message_id == id) =>
+        {
+            (battle, game_id, button)
+        }
+        (_, CallbackData::Board(..)) => return close_stale_board(&bot, q.id, chat.id, id).await,
+        (_, data) => unreachable!("the {:?} button was handled above", data),
+    };
+    let entry = battle.games.get_mut(&game_id).expect("checked above");
+    let moves = Moves {
+        bot: &bot,
+        dialogue: &dialogue,
+        config: &config,
+        stats: &stats,
+        active_games: &active_games,
+        tournaments: &tournaments,
+        settings: &settings,
+        chat_id: chat.id,
+    };
+
+    let result = match button {
+        BoardButton::Cell(cell) => entry.game.play(player(&from, &settings), cell),
+        BoardButton::Takeover(takeover) => {
+            let waiting = entry.game.player(entry.game.turn.other()).map(|p| p.id);
+            if waiting != Some(from.id) {
+                bot.answer_callback_query(q.id).text("Only the player left in the game can choose").await?;
+                return Ok(());
+            }
+            if !active_games.lock().unwrap().has_timed_out(chat.id, game_id) {
+                bot.answer_callback_query(q.id).text("Your opponent is back, the game goes on").await?;
+                return Ok(());
+            }
+            if takeover == Takeover::Claim {
+                bot.answer_callback_query(q.id).await?;
+                let (outcome, text) = (Outcome::Win(entry.game.turn.other()), forfeit_text(&entry.game));
+                return moves.finish(battle, game_id, outcome, text, false).await;
+            }
+            if entry.game.take_over() {
+                Ok(None)
+            } else {
+                Err(MoveError::GameOver)
+            }
+        }
+    };
+    let alert = settings.lock().unwrap().wants_alerts(from.id).then(|| move_alert(&entry.game, &result)).flatten();
+    match (moves.show(battle, game_id, result).await, alert) {
+        (Ok(()), Some(alert)) => bot.answer_callback_query(q.id).text(alert).show_alert(true).await?,
+        (Ok(()), None) => bot.answer_callback_query(q.id).await?,
+        (Err(Error::Game(err)), _) => bot.answer_callback_query(q.id).text(err.to_string()).await?,
+        (Err(err), _) => return Err(err),
+    };
+
+    Ok(())
+}
+
+/// What the alert of a user who turned `/alerts` on says about the move that led to
+/// `game`, `None` if it was refused.
+fn move_alert(game: &Game, result: &Result<Option<Outcome>, MoveError>) -> Option<String> {
+    match result {
+        Err(_) => None,
+        Ok(Some(_)) => Some("✅ Move played, the game is over".to_owned()),
+        Ok(None) => Some(match game.player(game.turn) {
+            Some(player) if player.id == game::AI_PLAYER_ID => "✅ Move played, the bot moves next".to_owned(),
+            Some(player) => format!("✅ Move played, {} {} to move", game.turn.symbol(), player.name),
+            None => format!("✅ Move played, {} to move", game.turn.symbol()),
+        }),
+    }
+}
+
+/// Sends a random puzzle to the chat and waits for its answer, going back to `previous`
+/// after.
+async fn start_puzzle(bot: &AutoSend<Bot>, dialogue: &MyDialogue, chat_id: i64, previous: State) -> HandlerResult {
+    let mut puzzle = Puzzle::random(previous);
+    let sent = bot.send_message(chat_id, puzzle::puzzle_text(&puzzle))
+        .reply_markup(puzzle::puzzle_keyboard(&puzzle))
+        .await?;
+    puzzle.message_id = sent.id;
+    dialogue.update(State::Puzzle(puzzle)).await?;
+    Ok(())
+}
+
+async fn handle_puzzle(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    dialogue: MyDialogue,
+    puzzle: Puzzle,
+    me: Me,
+    config: MyConfig,
+) -> HandlerResult {
+    let text = match parse_command(&msg, &me, &config) {
+        Some(Command::Cancel) => {
+            let text = format!("{}\n\nThe winning move is the star", puzzle::solution_text(&puzzle));
+            bot.edit_message_text(msg.chat.id, puzzle.message_id, text).await?;
+            dialogue.update(puzzle.into_previous()).await?;
+            return Ok(());
+        }
+        Some(Command::Puzzle) => "Solve this puzzle first, or /cancel to see the answer",
+        _ => "Find the winning move on the board, or /cancel to see the answer",
+    };
+    bot.send_message(msg.chat.id, text).await?;
+
+    Ok(())
+}
+
+/// Opens a `/sandbox` for the user who asked, going back to `previous` once it's closed.
+async fn start_sandbox(
+    bot: &AutoSend<Bot>,
+    msg: &Message,
+    dialogue: &MyDialogue,
+    previous: State,
+    size: &str,
+) -> HandlerResult {
+    let owner = match msg.from() {
+        Some(user) => user.id,
+        None => return Ok(()),
+    };
+    let size = match battle::parse_board_size(size) {
+        Ok(size) => size,
+        Err(err) => {
+            bot.send_message(msg.chat.id, err.to_string()).await?;
+            return Ok(());
+        }
+    };
+    let mut sandbox = Sandbox::new(previous, size, owner);
+    let sent = bot.send_message(msg.chat.id, sandbox::sandbox_text(&sandbox, None))
+        .reply_markup(sandbox::sandbox_keyboard(&sandbox))
+        .await?;
+    sandbox.message_id = sent.id;
+    dialogue.update(State::Sandbox(sandbox)).await?;
+    Ok(())
+}
+
+async fn handle_sandbox(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    dialogue: MyDialogue,
+    sandbox: Sandbox,
+    me: Me,
+    config: MyConfig,
+) -> HandlerResult {
+    match parse_command(&msg, &me, &config) {
+        Some(Command::Cancel) if msg.from().is_some_and(|user| user.id == sandbox.owner) => {
+            let text = format!("{}\n\nSandbox closed", battle::board_text(&sandbox.board));
+            bot.edit_message_text(msg.chat.id, sandbox.message_id, text).await?;
+            dialogue.update(sandbox.into_previous()).await?;
+        }
+        _ => {
+            let text = "A sandbox is open: tap its cells to change them, Solve to analyse the position, or /cancel";
+            bot.send_message(msg.chat.id, text).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Starts a `/race` from `number`, going back to `previous` once it's over.
+async fn start_race(
+    bot: &AutoSend<Bot>,
+    msg: &Message,
+    dialogue: &MyDialogue,
+    previous: State,
+    number: i32,
+    target: &str,
+) -> HandlerResult {
+    let race = match Race::new(previous, number, target, now()) {
+        Ok(race) => race,
+        Err(err) => {
+            bot.send_message(msg.chat.id, err.to_string()).await?;
+            return Ok(());
+        }
+    };
+    bot.send_message(msg.chat.id, race::start_text(&race)).await?;
+    race::spawn_race_timer(bot.clone(), dialogue.clone(), msg.chat.id, race.id);
+    dialogue.update(State::Race(race)).await?;
+    Ok(())
+}
+
+async fn handle_race(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    dialogue: MyDialogue,
+    mut race: Race,
+    me: Me,
+    config: MyConfig,
+) -> HandlerResult {
+    let now = now();
+    // The timer doesn't survive a restart, so a race can still be on past its time.
+    if race.seconds_left(now) == 0 {
+        bot.send_message(msg.chat.id, race::timeout_text(&race)).await?;
+        dialogue.update(race.into_previous()).await?;
+        return Ok(());
+    }
+    let (arg, sign) = match parse_command(&msg, &me, &config) {
+        Some(Command::Add(arg)) => (arg, 1),
+        Some(Command::Sub(arg)) => (arg, -1),
+        Some(Command::Cancel) => {
+            bot.send_message(msg.chat.id, "Race given up").await?;
+            dialogue.update(race.into_previous()).await?;
+            return Ok(());
+        }
+        _ => {
+            let text = format!("A race to {} is on: /add and /sub to get there, or /cancel", race.target);
+            bot.send_message(msg.chat.id, text).await?;
+            return Ok(());
+        }
+    };
+    let delta = arg.trim().parse::<i32>().ok().and_then(|n| n.checked_mul(sign));
+    match delta.map(|delta| race.step(delta)) {
+        None | Some(RaceStep::TooBig) => {
+            let text = format!("In a race, /add and /sub take 1 to {}", race::MAX_RACE_STEP);
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Some(RaceStep::Going) => {
+            bot.send_message(msg.chat.id, race::progress_text(&race, now)).await?;
+            dialogue.update(State::Race(race)).await?;
+        }
+        Some(RaceStep::Reached) => {
+            bot.send_message(msg.chat.id, race::finish_text(&race, now)).await?;
+            dialogue.update(race.into_previous()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Opens the lobby of a `/party`, with the user who asked as its host.
+async fn start_party(
+    bot: &AutoSend<Bot>,
+    msg: &Message,
+    dialogue: &MyDialogue,
+    previous: State,
+    options: &str,
+    settings: &MySettings,
+) -> HandlerResult {
+    let host = match msg.from() {
+        Some(user) => player(user, settings),
+        None => return Ok(()),
+    };
+    let size = match options.trim() {
+        "" => party::DEFAULT_PARTY_SIZE,
+        options => match battle::parse_board_size(options) {
+            Ok(size) => size,
+            Err(err) => {
+                bot.send_message(msg.chat.id, err.to_string()).await?;
+                return Ok(());
+            }
+        },
+    };
+    let mut party = Party::new(previous, size, host);
+    let sent = bot.send_message(msg.chat.id, party::lobby_text(&party)).reply_markup(party::lobby_keyboard()).await?;
+    party.message_id = sent.id;
+    dialogue.update(State::Party(party)).await?;
+    Ok(())
+}
+
+async fn handle_party(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    dialogue: MyDialogue,
+    mut party: Party,
+    me: Me,
+    config: MyConfig,
+    stats: MyStats,
+) -> HandlerResult {
+    let user = match msg.from() {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    match parse_command(&msg, &me, &config) {
+        Some(Command::Leave) => match party.leave(user.id) {
+            Some(outcome) => close_party(&bot, &dialogue, &stats, msg.chat.id, party, outcome).await?,
+            None => {
+                bot.send_message(msg.chat.id, NOT_IN_PARTY).await?;
+            }
+        },
+        Some(Command::Cancel) if party.host() == user.id => {
+            close_party(&bot, &dialogue, &stats, msg.chat.id, party, PartyOutcome::Abandoned).await?;
+        }
+        _ => {
+            let text = "A party game is on: join or play on its board, /leave to leave it, or /cancel if you host it";
+            bot.send_message(msg.chat.id, text).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Shows how `party` changed on its message. Once it's over, counts the result and goes
+/// back to the previous state.
+async fn close_party(
+    bot: &AutoSend<Bot>,
+    dialogue: &MyDialogue,
+    stats: &MyStats,
+    chat_id: i64,
+    party: Party,
+    outcome: PartyOutcome,
+) -> HandlerResult {
+    let result = match outcome {
+        PartyOutcome::Going => {
+            let message_id = party.message_id;
+            let edit = match party.current {
+                Some(_) => bot.edit_message_text(chat_id, message_id, party::party_text(&party, None))
+                    .reply_markup(party::party_board(&party)),
+                None => bot.edit_message_text(chat_id, message_id, party::lobby_text(&party))
+                    .reply_markup(party::lobby_keyboard()),
+            };
+            edit.await?;
+            dialogue.update(State::Party(party)).await?;
+            return Ok(());
+        }
+        PartyOutcome::Won(seat) => {
+            stats.lock().unwrap().record_party(Some(party.player(seat).id), &party.player_ids(), party.moves());
+            format!("🏆 {} wins!", party.player(seat).name)
+        }
+        PartyOutcome::Draw => {
+            stats.lock().unwrap().record_party(None, &party.player_ids(), party.moves());
+            "The board is full, it's a draw".to_owned()
+        }
+        PartyOutcome::Abandoned => "Party game cancelled".to_owned(),
+    };
+    bot.edit_message_text(chat_id, party.message_id, party::party_text(&party, Some(&result))).await?;
+    dialogue.update(party.into_previous()).await?;
+    Ok(())
+}
+
+/// Answers a click on a board whose game isn't in the chat's state anymore, e.g. after
+/// `/reset` or `/stop`, and takes the buttons off the board.
+async fn close_stale_board(bot: &AutoSend<Bot>, query_id: String, chat_id: i64, message_id: i32) -> HandlerResult {
+    bot.answer_callback_query(query_id).text("This game is no longer active").await?;
+    if let Err(err) = bot.edit_message_reply_markup(chat_id, message_id).await {
+        log::warn!("Failed to remove the buttons of a stale board in chat {}: {}", chat_id, err);
+    }
+    Ok(())
+}
+
+fn parse_help_command(msg: Message, me: Me, config: MyConfig) -> Option<String> {
+    match parse_command(&msg, &me, &config) {
+        Some(Command::Help(args)) => Some(args),
+        _ => None,
+    }
+}
+
+/// `/help` explains what can be done in the chat's current state; `/help all` lists
+/// every command.
+async fn handle_help(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    args: String,
+    state: State,
+    settings: MySettings,
+) -> HandlerResult {
+    let text = if args.trim().eq_ignore_ascii_case("all") {
+        Command::descriptions()
+    } else {
+        let lang = i18n::locale(&settings.lock().unwrap(), msg.from());
+        contextual_help(&state, lang)
+    };
+    bot.send_message(msg.chat.id, text).await?;
+
+    Ok(())
+}
+
+fn contextual_help(state: &State, lang: &str) -> String {
+    let key = match state {
+        State::Start => "help.start",
+        State::GotNumber(..) | State::AddNumber(..) | State::SubNumber(..) => "help.number",
+        State::Members(_) => "help.members",
+        State::Battle(_) => "help.battle",
+        State::Puzzle(_) => "help.puzzle",
+        State::Party(_) => "help.party",
+        State::Sandbox(_) => "help.sandbox",
+        State::Race(_) => "help.race",
+    };
+    format!("{}\n\n{}", i18n::t(lang, key), i18n::t(lang, "help.all"))
+}
+
+fn parse_snapshot_command(msg: Message, me: Me, config: MyConfig) -> Option<Command> {
+    match parse_command(&msg, &me, &config) {
+        Some(cmd @ (Command::Save(_) | Command::Load(_) | Command::Snapshots | Command::ClearSnapshots)) => Some(cmd),
+        _ => None,
+    }
+}
+
+/// `/save`, `/load` and `/snapshots`, which work in any state: a position can be loaded
+/// next to the games already running.
+#[allow(clippy::too_many_arguments)]
+async fn handle_snapshot_command(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    dialogue: MyDialogue,
+    cmd: Command,
+    state: State,
+    snapshots: MySnapshots,
+    active_games: MyActiveGames,
+    settings: MySettings,
+) -> HandlerResult {
+    let user = match msg.from() {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    match cmd {
+        Command::Save(args) => {
+            let (name, position) = match args.trim().split_once(char::is_whitespace) {
+                Some((name, position)) => (name, Some(position)),
+                None => (args.as_str(), None),
+            };
+            let name = match snapshots::snapshot_name(name) {
+                Ok(name) => name,
+                Err(err) => {
+                    bot.send_message(msg.chat.id, err.to_string()).await?;
+                    return Ok(());
+                }
+            };
+            let board = match (position, &state) {
+                (Some(position), _) => match position.parse::<game::Board>() {
+                    Ok(board) if (3..=battle::MAX_KEYBOARD_BOARD).contains(&board.size()) => board,
+                    Ok(_) => {
+                        let text = format!("Boards can have from 3 to {} rows", battle::MAX_KEYBOARD_BOARD);
+                        bot.send_message(msg.chat.id, text).await?;
+                        return Ok(());
+                    }
+                    Err(err) => {
+                        bot.send_message(msg.chat.id, format!("That's not a board: {}", err)).await?;
+                        return Ok(());
+                    }
+                },
+                (None, State::Battle(battle)) => match battle.game_of(user.id) {
+                    Some(game_id) => battle.games[&game_id].game.board.clone(),
+                    None => {
+                        bot.send_message(msg.chat.id, "You're not playing a game, write the position after the name")
+                            .await?;
+                        return Ok(());
+                    }
+                },
+                (None, _) => {
+                    bot.send_message(msg.chat.id, "There is no battle in progress, write the position after the name")
+                        .await?;
+                    return Ok(());
+                }
+            };
+            let text = format!("Saved {} as {}, /load {} to play it", board, name, name);
+            snapshots.lock().unwrap().save(user.id, name, board, now());
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::Load(name) => {
+            let board = match snapshots::snapshot_name(&name) {
+                Ok(name) => snapshots.lock().unwrap().get(user.id, &name).cloned(),
+                Err(err) => {
+                    bot.send_message(msg.chat.id, err.to_string()).await?;
+                    return Ok(());
+                }
+            };
+            let game = match board.map(|board| Game::from_position(board, now())) {
+                Some(Ok(game)) if chat_profile(&msg.chat).plays_bot_by_default() => game.fork(now()),
+                Some(Ok(game)) => game,
+                Some(Err(err)) => {
+                    bot.send_message(msg.chat.id, format!("That position can't be played: {}", err)).await?;
+                    return Ok(());
+                }
+                None => {
+                    bot.send_message(msg.chat.id, "You have no position of that name, see /snapshots").await?;
+                    return Ok(());
+                }
+            };
+            if !active_games.lock().unwrap().try_start(user.id, now()) {
+                bot.send_message(msg.chat.id, GAME_START_COOLDOWN).await?;
+                return Ok(());
+            }
+            let mut battle = match state {
+                State::Battle(battle) => battle,
+                state => Battle::new(state),
+            };
+            send_game(&bot, &msg, &mut battle, game, &active_games, &settings).await?;
+            dialogue.update(battle.into_state()).await?;
+        }
+        Command::Snapshots => {
+            // A reply, so that the delete buttons know whose positions they are.
+            let (text, keyboard) = snapshots::snapshots_list(&snapshots.lock().unwrap(), user.id);
+            bot.send_message(msg.chat.id, text).reply_to_message_id(msg.id).reply_markup(keyboard).await?;
+        }
+        Command::ClearSnapshots => {
+            let text = match snapshots.lock().unwrap().clear(user.id) {
+                0 => "You have no saved positions".to_owned(),
+                1 => "Deleted your saved position".to_owned(),
+                count => format!("Deleted your {} saved positions", count),
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        _ => unreachable!("parse_snapshot_command only lets snapshot commands through"),
+    }
+
+    Ok(())
+}
+
+fn is_snapshot_callback(q: CallbackQuery) -> bool {
+    q.data.as_deref().and_then(snapshots::parse_delete_data).is_some()
+}
+
+/// Answers the delete buttons of `/snapshots`, which only the user who asked for the
+/// list can press.
+async fn handle_snapshot_callback(q: CallbackQuery, bot: AutoSend<Bot>, snapshots: MySnapshots) -> HandlerResult {
+    let (message, name) = match (&q.message, q.data.as_deref().and_then(snapshots::parse_delete_data)) {
+        (Some(message), Some(name)) => (message, name),
+        _ => return Ok(()),
+    };
+    let owner = message.reply_to_message().and_then(|asked| asked.from()).map(|user| user.id);
+    if owner != Some(q.from.id) {
+        bot.answer_callback_query(q.id).text("These aren't your positions").await?;
+        return Ok(());
+    }
+    let (removed, (text, keyboard)) = {
+        let mut snapshots = snapshots.lock().unwrap();
+        let removed = snapshots.remove(q.from.id, name);
+        (removed, snapshots::snapshots_list(&snapshots, q.from.id))
+    };
+    let answer = if removed { format!("Deleted {}", name) } else { format!("{} was already deleted", name) };
+    bot.answer_callback_query(q.id).text(answer).await?;
+    bot.edit_message_text(message.chat.id, message.id, text).reply_markup(keyboard).await?;
+
+    Ok(())
+}
+
+fn parse_challenge_command(msg: Message, me: Me, config: MyConfig) -> Option<Command> {
+    match parse_command(&msg, &me, &config) {
+        Some(cmd @ (Command::Challenge(_) | Command::Accept)) => Some(cmd),
+        _ => None,
+    }
+}
+
+/// `/challenge @username` announces a challenge that only that user can accept, with
+/// the buttons of [`handle_challenge_callback`] or `/accept`.
+#[allow(clippy::too_many_arguments)]
+async fn handle_challenge_command(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    dialogue: MyDialogue,
+    cmd: Command,
+    challenges: MyChallenges,
+    active_games: MyActiveGames,
+    settings: MySettings,
+) -> HandlerResult {
+    let user = match msg.from() {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    if !chat_profile(&msg.chat).has_members() {
+        bot.send_message(msg.chat.id, "Challenges are sent in a group you share with your opponent").await?;
+        return Ok(());
+    }
+    challenges.lock().unwrap().prune(now());
+    match cmd {
+        Command::Challenge(username) => {
+            let username = username.trim().trim_start_matches('@');
+            if username.is_empty() || username.contains(char::is_whitespace) {
+                bot.send_message(msg.chat.id, "Use /challenge @username").await?;
+                return Ok(());
+            }
+            if user.username.as_deref().is_some_and(|own| own.eq_ignore_ascii_case(username)) {
+                bot.send_message(msg.chat.id, "You can't challenge yourself").await?;
+                return Ok(());
+            }
+            if !active_games.lock().unwrap().try_start(user.id, now()) {
+                bot.send_message(msg.chat.id, GAME_START_COOLDOWN).await?;
+                return Ok(());
+            }
+            let challenger = player(user, &settings);
+            let text = format!(
+                "⚔️ {} challenges @{} to a battle! Accept within {} minutes with the button or /accept",
+                challenger.name,
+                username,
+                challenge::CHALLENGE_TTL / 60,
+            );
+            let sent = bot.send_message(msg.chat.id, text).reply_markup(challenge::challenge_keyboard()).await?;
+            let challenge = Challenge { challenger, username: username.to_owned(), created_at: now() };
+            challenges.lock().unwrap().insert(msg.chat.id, sent.id, challenge);
+        }
+        Command::Accept => {
+            let challenge = challenges.lock().unwrap().take_for(msg.chat.id, user.username.as_deref());
+            match challenge {
+                Some((message_id, challenge)) => {
+                    let opponent = player(user, &settings);
+                    let text = format!("{} accepted the challenge of {}", opponent.name, challenge.challenger.name);
+                    bot.edit_message_text(msg.chat.id, message_id, text).await?;
+                    start_challenge(&bot, &msg, &dialogue, challenge, opponent, &active_games, &settings).await?;
+                }
+                None => {
+                    bot.send_message(msg.chat.id, "Nobody challenged you here, or the challenge expired").await?;
+                }
+            }
+        }
+        _ => unreachable!("parse_challenge_command only lets challenge commands through"),
+    }
+
+    Ok(())
+}
+
+/// Starts the game of an accepted challenge, the challenger playing X, next to the
+/// games already running in the chat. The board replies to `msg`.
+async fn start_challenge(
+    bot: &AutoSend<Bot>,
+    msg: &Message,
+    dialogue: &MyDialogue,
+    challenge: Challenge,
+    opponent: Player,
+    active_games: &MyActiveGames,
+    settings: &MySettings,
+) -> HandlerResult {
+    let mut battle = match dialogue.get().await?.unwrap_or_default() {
+        State::Battle(battle) => battle,
+        state => Battle::new(state),
+    };
+    let host = challenge.challenger.id;
+    let mut game = Game::new(3, Mode::TwoPlayer, WinCondition::Normal, now()).seated(challenge.challenger, opponent);
+    game.host = Some(host);
+    send_game(bot, msg, &mut battle, game, active_games, settings).await?;
+    dialogue.update(battle.into_state()).await?;
+    Ok(())
+}
+
+fn is_challenge_callback(q: CallbackQuery) -> bool {
+    q.data.as_deref().is_some_and(|data| data.starts_with("challenge:"))
+}
+
+/// Answers the buttons of a `/challenge`: the challenged user accepts or declines, and
+/// the challenger can withdraw.
+async fn handle_challenge_callback(
+    q: CallbackQuery,
+    bot: AutoSend<Bot>,
+    storage: MyStorage,
+    challenges: MyChallenges,
+    active_games: MyActiveGames,
+    settings: MySettings,
+) -> HandlerResult {
+    let message = match &q.message {
+        Some(message) => message,
+        None => return Ok(()),
+    };
+    let chat_id = message.chat.id;
+    let accepted = q.data.as_deref() == Some("challenge:yes");
+    let answer = {
+        let mut challenges = challenges.lock().unwrap();
+        challenges.prune(now());
+        challenges.answer(chat_id, message.id, q.from.id, q.from.username.as_deref(), accepted)
+    };
+
+    match answer {
+        Ok(challenge) if accepted => {
+            bot.answer_callback_query(q.id).await?;
+            let opponent = player(&q.from, &settings);
+            let text = format!("{} accepted the challenge of {}", opponent.name, challenge.challenger.name);
+            bot.edit_message_text(chat_id, message.id, text).await?;
+            let dialogue = MyDialogue::new(storage, chat_id);
+            start_challenge(&bot, message, &dialogue, challenge, opponent, &active_games, &settings).await?;
+        }
+        Ok(challenge) => {
+            bot.answer_callback_query(q.id).await?;
+            let text = format!("The challenge of {} was declined", challenge.challenger.name);
+            bot.edit_message_text(chat_id, message.id, text).await?;
+        }
+        Err(err @ challenge::AnswerError::Expired) => {
+            bot.answer_callback_query(q.id).text(err.to_string()).await?;
+            bot.edit_message_text(chat_id, message.id, err.to_string()).await?;
+        }
+        Err(err) => {
+            bot.answer_callback_query(q.id).text(err.to_string()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_transfer_command(msg: Message, me: Me, config: MyConfig) -> Option<String> {
+    match parse_command(&msg, &me, &config) {
+        Some(Command::Transfer(target)) => Some(target),
+        _ => None,
+    }
+}
+
+/// `/transfer` moves the user's newest game to another chat they are in: the board is
+/// sent there again, and its old message says where it went. Tournament matches stay in
+/// the chat of their tournament.
+#[allow(clippy::too_many_arguments)]
+async fn handle_transfer(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    dialogue: MyDialogue,
+    target: String,
+    state: State,
+    storage: MyStorage,
+    active_games: MyActiveGames,
+    tournaments: MyTournaments,
+    settings: MySettings,
+) -> HandlerResult {
+    let user = match msg.from() {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    let chat_id = msg.chat.id;
+    let (mut battle, game_id) = match state {
+        State::Battle(battle) => match battle.game_of(user.id) {
+            Some(game_id) => (battle, game_id),
+            None => {
+                bot.send_message(chat_id, NO_GAME_TO_TRANSFER).await?;
+                return Ok(());
+            }
+        },
+        _ => {
+            bot.send_message(chat_id, NO_GAME_TO_TRANSFER).await?;
+            return Ok(());
+        }
+    };
+    if tournaments.lock().unwrap().get(chat_id).is_some_and(|t| t.plays(game_id)) {
+        bot.send_message(chat_id, "Tournament matches are played in the chat of the tournament").await?;
+        return Ok(());
+    }
+    let target = match transfer_target(&bot, &msg, &target).await {
+        Ok(target) => target,
+        Err(text) => {
+            bot.send_message(chat_id, text).await?;
+            return Ok(());
+        }
+    };
+    if target.id == chat_id {
+        bot.send_message(chat_id, "The game is already here").await?;
+        return Ok(());
+    }
+    match bot.get_chat_member(target.id, user.id).await {
+        Ok(member) if member.kind.is_present() => {}
+        Ok(_) => {
+            bot.send_message(chat_id, "You can only move a game to a chat you are in").await?;
+            return Ok(());
+        }
+        Err(err) => {
+            log::info!("Can't check the members of chat {}: {}", target.id, err);
+            bot.send_message(chat_id, CANT_REACH_CHAT).await?;
+            return Ok(());
+        }
+    }
+
+    let entry = battle.games.remove(&game_id).expect("game_of returns a running game");
+    let players: Vec<_> = entry.game.players.iter().map(Player::mention).collect();
+    let players = players.join(" and ");
+    if let Err(err) = bot.send_message(target.id, format!("♟ {}: your game was moved here", players)).await {
+        log::info!("Can't move a game to chat {}: {}", target.id, err);
+        bot.send_message(chat_id, CANT_REACH_CHAT).await?;
+        return Ok(());
+    }
+    let target_dialogue = MyDialogue::new(storage, target.id);
+    let mut target_battle = match target_dialogue.get().await?.unwrap_or_default() {
+        State::Battle(battle) => battle,
+        state => Battle::new(state),
+    };
+    let target_game_id = target_battle.next_game_id();
+    let sent = bot
+        .send_message(target.id, battle_text(&entry.game))
+        .reply_markup(board_keyboard(target_game_id, &entry.game))
+        .await?;
+    {
+        let mut active_games = active_games.lock().unwrap();
+        active_games.remove(chat_id, game_id);
+        let active = ActiveGame::new(sent.id, &entry.game);
+        active_games.insert(target.id, target_game_id, active);
+    }
+    expiry::unpin_board(&bot, chat_id, &entry).await;
+    let mut moved_entry = BattleGame { message_id: sent.id, game: entry.game, pinned: false };
+    expiry::pin_board(&bot, &settings, target.id, &mut moved_entry).await;
+    target_battle.games.insert(target_game_id, moved_entry);
+    target_dialogue.update(target_battle.into_state()).await?;
+    dialogue.update(battle.into_state()).await?;
+
+    let moved = format!("Game moved to {}", target.title().unwrap_or("another chat"));
+    expiry::retire_board(&bot, chat_id, entry.message_id, false, &moved).await?;
+    bot.send_message(chat_id, format!("♟ {}: {}", players, moved)).await?;
+
+    Ok(())
+}
+
+/// The chat `/transfer` moves a game to: the one given by its @username, `t.me` link or
+/// id, or else the one the replied-to message was forwarded from.
+async fn transfer_target(
+    bot: &AutoSend<Bot>,
+    msg: &Message,
+    target: &str,
+) -> Result<teloxide::types::Chat, &'static str> {
+    let target = target.trim();
+    if target.is_empty() {
+        return match msg.reply_to_message().and_then(|reply| reply.forward_from_chat()) {
+            Some(chat) => Ok(chat.clone()),
+            None => Err("Use /transfer @group, or reply to a message forwarded from the chat"),
+        };
+    }
+    let chat_id = match target.parse() {
+        Ok(id) => teloxide::types::ChatId::Id(id),
+        Err(_) => {
+            let username = target.trim_start_matches("https://").trim_start_matches("t.me/").trim_start_matches('@');
+            if username.is_empty() || !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err("Give the chat by its @username or link");
+            }
+            teloxide::types::ChatId::ChannelUsername(format!("@{}", username))
+        }
+    };
+    bot.get_chat(chat_id).await.map_err(|err| {
+        log::info!("Can't find the chat of /transfer {}: {}", target, err);
+        CANT_REACH_CHAT
+    })
+}
+
+/// A message in a battle that plays or ends a game, see [`parse_battle_input`].
+#[derive(Clone)]
+struct BattleInput {
+    battle: Battle,
+    action: BattleAction,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum BattleAction {
+    /// A cell number, counting from 1.
+    Move(usize),
+    Resign,
+    Cancel,
+    /// `/newgame` with its options.
+    NewGame(String),
+}
+
+/// Picks the messages of a battle that go to [`handle_battle_input`] rather than the
+/// state's own handler: `/resign`, `/cancel`, `/newgame` and, with the chat's `text_moves`
+/// preference on, cell numbers.
+fn parse_battle_input(
+    msg: Message,
+    me: Me,
+    config: MyConfig,
+    state: State,
+    settings: MySettings,
+) -> Option<BattleInput> {
+    let battle = match state {
+        State::Battle(battle) => battle,
+        _ => return None,
+    };
+    let action = match parse_command(&msg, &me, &config) {
+        Some(Command::Resign) => BattleAction::Resign,
+        Some(Command::Cancel) => BattleAction::Cancel,
+        Some(Command::NewGame(options)) => BattleAction::NewGame(options),
+        Some(_) => return None,
+        None if settings.lock().unwrap().prefs(msg.chat.id).text_moves => {
+            BattleAction::Move(msg.text()?.trim().parse().ok()?)
+        }
+        None => return None,
+    };
+    Some(BattleInput { battle, action })
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_battle_input(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    dialogue: MyDialogue,
+    input: BattleInput,
+    config: MyConfig,
+    stats: MyStats,
+    active_games: MyActiveGames,
+    tournaments: MyTournaments,
+    settings: MySettings,
+) -> HandlerResult {
+    let BattleInput { mut battle, action } = input;
+    let chat_id = msg.chat.id;
+    let user = match msg.from() {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    let game_id = match &action {
+        // A move may take a free seat, in the newest game if the user has none yet.
+        BattleAction::Move(_) => battle.game_of(user.id).or_else(|| battle.games.keys().last().copied()),
+        BattleAction::Resign | BattleAction::Cancel => battle.game_of(user.id),
+        BattleAction::NewGame(options) => match battle.game_of(user.id) {
+            Some(game_id) => Some(game_id),
+            None => return start_battle(&bot, &msg, &dialogue, battle, options, &active_games, &settings).await,
+        },
+    };
+    let game_id = match game_id {
+        Some(game_id) => game_id,
+        None => {
+            bot.send_message(chat_id, "You're not playing in this battle").await?;
+            return Ok(());
+        }
+    };
+    let moves = Moves {
+        bot: &bot,
+        dialogue: &dialogue,
+        config: &config,
+        stats: &stats,
+        active_games: &active_games,
+        tournaments: &tournaments,
+        settings: &settings,
+        chat_id,
+    };
+    let game = &mut battle.games.get_mut(&game_id).expect("picked from the battle").game;
+
+    match action {
+        BattleAction::Move(number) => {
+            let cells = game.board.cells().len();
+            if !(1..=cells).contains(&number) {
+                let text = format!("Cells are numbered from 1 to {}, row by row", cells);
+                bot.send_message(chat_id, text).await?;
+                return Ok(());
+            }
+            let result = game.play(player(user, &settings), number - 1);
+            match moves.show(battle, game_id, result).await {
+                Err(Error::Game(err)) => {
+                    bot.send_message(chat_id, err.to_string()).await?;
+                }
+                shown => shown?,
+            }
+        }
+        BattleAction::Resign => {
+            let mark = game.mark_of(user.id).expect("game_of found a seat");
+            if game.player(mark.other()).is_none() {
+                bot.send_message(chat_id, "Nobody has taken the other seat yet, use /cancel instead").await?;
+                return Ok(());
+            }
+            let text = battle::resign_text(game, mark);
+            moves.finish(battle, game_id, Outcome::Win(mark.other()), text, false).await?;
+        }
+        BattleAction::Cancel => {
+            let opponent = game.mark_of(user.id).expect("game_of found a seat").other();
+            if game.mode == Mode::TwoPlayer && game.player(opponent).is_some() && game.moves_by(opponent) > 0 {
+                bot.send_message(chat_id, "Your opponent has moved already, use /resign to give up").await?;
+                return Ok(());
+            }
+            moves.cancel(battle, game_id, "Game cancelled").await?;
+        }
+        BattleAction::NewGame(options) => {
+            // Nothing is ended unless the new game can start.
+            if let Err(err) = parse_battle_options(&options, chat_profile(&msg.chat)) {
+                bot.send_message(chat_id, err.to_string()).await?;
+                return Ok(());
+            }
+            if !active_games.lock().unwrap().can_start(user.id, now()) {
+                bot.send_message(chat_id, GAME_START_COOLDOWN).await?;
+                return Ok(());
+            }
+            let mark = game.mark_of(user.id).expect("game_of found a seat");
+            let opponent = mark.other();
+            if game.mode == Mode::TwoPlayer && game.player(opponent).is_some() && game.moves_by(opponent) > 0 {
+                // Past the point where /cancel is allowed, restarting gives the game up.
+                let text = battle::resign_text(game, mark);
+                moves.finish(battle, game_id, Outcome::Win(opponent), text, false).await?;
+            } else {
+                moves.cancel(battle, game_id, "Game restarted").await?;
+            }
+            let battle = match dialogue.get().await?.unwrap_or_default() {
+                State::Battle(battle) => battle,
+                state => Battle::new(state),
+            };
+            start_battle(&bot, &msg, &dialogue, battle, &options, &active_games, &settings).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// What a move needs besides the battle it's made in.
+struct Moves<'a> {
+    bot: &'a AutoSend<Bot>,
+    dialogue: &'a MyDialogue,
+    config: &'a Config,
+    stats: &'a MyStats,
+    active_games: &'a MyActiveGames,
+    tournaments: &'a MyTournaments,
+    settings: &'a MySettings,
+    chat_id: i64,
+}
+
+impl Moves<'_> {
+    /// Ends the game of `game_id` without a result, retiring its board with `text`.
+    async fn cancel(&self, mut battle: Battle, game_id: u32, text: &str) -> HandlerResult {
+        let chat_id = self.chat_id;
+        let entry = battle.games.remove(&game_id).expect("only running games end");
+        self.active_games.lock().unwrap().remove(chat_id, game_id);
+        expiry::unpin_board(self.bot, chat_id, &entry).await;
+        self.dialogue.update(battle.into_state()).await?;
+        let delete = self.settings.lock().unwrap().prefs(chat_id).delete_boards;
+        expiry::retire_board(self.bot, chat_id, entry.message_id, delete, text).await?;
+        // A cancelled tournament match is replayed, like a draw.
+        let (dialogue, active_games, tournaments) = (self.dialogue, self.active_games, self.tournaments);
+        tournament::report_result(self.bot, dialogue, active_games, tournaments, chat_id, game_id, None).await
+    }
+
+    /// Lets the AI reply to a move that left the game of `game_id` going, if it plays the
+    /// side to move, and shows the game on its board. A move that was refused is
+    /// returned as [`Error::Game`], with the battle left as it was.
+    async fn show(
+        &self,
+        mut battle: Battle,
+        game_id: u32,
+        mut result: Result<Option<Outcome>, MoveError>,
+    ) -> HandlerResult {
+        let chat_id = self.chat_id;
+        let entry = battle.games.get_mut(&game_id).expect("moves are made in running games");
+        let is_ai_turn = entry.game.player(entry.game.turn).is_some_and(|p| p.id == game::AI_PLAYER_ID);
+        let mut searched = None;
+        if let (Ok(None), Mode::VsAi(difficulty), true) = (&result, entry.game.mode, is_ai_turn) {
+            // The full search can take a moment, show that the bot is working on it.
+            if difficulty == Difficulty::Hard {
+                let thinking = format!("{}\n\n🤔 thinking…", battle_text(&entry.game));
+                let edited = self.bot.edit_message_text(chat_id, entry.message_id, thinking)
+                    .reply_markup(board_keyboard(game_id, &entry.game))
+                    .await;
+                if let Err(err) = edited {
+                    log::warn!("Failed to show the thinking indicator in chat {}: {}", chat_id, err);
+                }
+            }
+            let game = &entry.game;
+            let (board, turn, condition) = (game.board.clone(), game.turn, game.condition);
+            let mut rng = ai::move_rng(game.seed, game.positions.len());
+            let learn = game.learn;
+            let ai = tokio::task::spawn_blocking(move || match difficulty {
+                // The hard bot's search is deterministic, learn mode shows how big it was.
+                Difficulty::Hard if learn => {
+                    let search = ai::search(&board, turn, condition);
+                    (search.cell, Some(search.positions))
+                }
+                _ => (ai::ai_move(&board, turn, difficulty, condition, &mut rng), None),
+            });
+            // Whatever happens here, the board is rendered again below, which clears the indicator.
+            match ai.await {
+                Ok((Some(cell), positions)) => {
+                    searched = positions;
+                    result = entry.game.play(game::ai_player(), cell);
+                }
+                Ok((None, _)) => {}
+                Err(err) => log::error!("The AI failed to move in chat {}: {}", chat_id, err),
+            }
+        }
+        match result {
+            Err(err) => return Err(Error::Game(err)),
+            Ok(None) => {
+                {
+                    let mut active_games = self.active_games.lock().unwrap();
+                    // Once both players sat down, the game no longer expires as a lobby.
+                    if !entry.game.is_waiting_for_players() {
+                        active_games.set_seated(chat_id, game_id);
+                    }
+                    active_games.start_turn(chat_id, game_id, now());
+                }
+                let mut text = battle_text(&entry.game);
+                if let Some(positions) = searched {
+                    text.push_str(&format!("\n🔎 The bot searched {} positions", positions));
+                }
+                let keyboard = board_keyboard(game_id, &entry.game);
+                edit_or_repost_board(self.bot, self.active_games, chat_id, game_id, entry, text, keyboard).await?;
+                self.dialogue.update(State::Battle(battle)).await?;
+            }
+            Ok(Some(outcome)) => {
+                let text = result_text(&entry.game, outcome);
+                self.finish(battle, game_id, outcome, text, true).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the game of `game_id` with `outcome`, described by `text` on its board:
+    /// records the result, logs the game for `/winstats` and the telemetry if it was
+    /// played out, and reports it to the tournament.
+    async fn finish(
+        &self,
+        mut battle: Battle,
+        game_id: u32,
+        outcome: Outcome,
+        text: String,
+        played_out: bool,
+    ) -> HandlerResult {
+        let chat_id = self.chat_id;
+        let entry = battle.games.remove(&game_id).expect("only running games end");
+        self.active_games.lock().unwrap().remove(chat_id, game_id);
+        expiry::unpin_board(self.bot, chat_id, &entry).await;
+        let counted = record_result(self.stats, chat_id, &entry.game, outcome);
+        self.stats.lock().unwrap().record_last_game(chat_id, &entry.game, outcome);
+        if let (true, Some(path)) = (played_out, &self.config.telemetry_file) {
+            let completed = telemetry::CompletedGame::new(&entry.game, outcome);
+            if let Err(err) = telemetry::log_game_telemetry(path, &completed).await {
+                log::warn!("Failed to write the game telemetry: {}", err);
+            }
+        }
+        let review = self.settings.lock().unwrap().prefs(chat_id).review_moves;
+        let text = match battle::review_text(&entry.game).filter(|_| review) {
+            Some(review) => format!("{}\n\n{}", text, review),
+            None => text,
+        };
+        match render::edit_board(self.bot, self.active_games, chat_id, entry.message_id, text.clone(), None).await {
+            // The board was deleted, the result still has to be told.
+            Err(err) if err.is_message_gone() => {
+                self.bot.send_message(chat_id, text).await?;
+            }
+            edited => edited?,
+        }
+        #[cfg(feature = "board-images")]
+        {
+            let png = board_image::render_board_png(&entry.game.board);
+            let photo = teloxide::types::InputFile::memory(png).file_name("board.png");
+            if let Err(err) = self.bot.send_photo(chat_id, photo).await {
+                log::warn!("Failed to send the board image in chat {}: {}", chat_id, err);
+            }
+        }
+        if counted && self.settings.lock().unwrap().prefs(chat_id).pinned_scoreboard {
+            update_scoreboard(self.bot, self.settings, self.stats, chat_id).await?;
+        }
+        let winner = match outcome {
+            Outcome::Win(mark) => entry.game.player(mark).map(|p| p.id),
+            Outcome::Draw => None,
+        };
+        self.dialogue.update(battle.into_state()).await?;
+        let (dialogue, active_games, tournaments) = (self.dialogue, self.active_games, self.tournaments);
+        tournament::report_result(self.bot, dialogue, active_games, tournaments, chat_id, game_id, winner).await
+    }
+}
+
+/// Edits the scoreboard of `chat_id`. The first time, or if it can't be edited (e.g.
+/// because it was deleted), a new one is sent and pinned; without the right to pin, it
+/// stays a plain message.
+async fn update_scoreboard(
+    bot: &AutoSend<Bot>,
+    settings: &MySettings,
+    stats: &MyStats,
+    chat_id: i64,
+) -> HandlerResult {
+    let text = battle::scoreboard_text(&stats.lock().unwrap(), &settings.lock().unwrap(), chat_id);
+    let previous = settings.lock().unwrap().scoreboard_message(chat_id);
+    if let Some(message_id) = previous {
+        match bot.edit_message_text(chat_id, message_id, text.clone()).await {
+            Ok(_) => return Ok(()),
+            Err(err) => log::info!("Sending a new scoreboard in chat {}: {}", chat_id, err),
+        }
+    }
+    let sent = bot.send_message(chat_id, text).await?;
+    settings.lock().unwrap().set_scoreboard_message(chat_id, sent.id);
+    if let Err(err) = bot.pin_chat_message(chat_id, sent.id).disable_notification(true).await {
+        log::info!("Failed to pin the scoreboard in chat {}: {}", chat_id, err);
+    }
+    Ok(())
+}
+
+fn version_text(config: &Config) -> String {
+    format!(
+        "tg-board-game {}\nStorage: {}\nSerializer: {}",
+        env!("CARGO_PKG_VERSION"),
+        config.backend.name(),
+        config.backend.serializer_name(),
+    )
+}
+
+/// The settings `config` was read with, for `/config`. `Config` holds no secrets: the
+/// bot token is only read by `Bot::from_env`, and the Redis address has no password.
+fn config_text(config: &Config) -> String {
+    let secs = |duration: std::time::Duration| format!("{}s", duration.as_secs());
+    let on_off = settings::on_off;
+    format!(
+        "Storage: {} ({}), requests time out after {}, checked every {}\n\
+         Updates: long polling\n\
+         Boards: {min}×{min} by default, up to {max}×{max}\n\
+         Games expire after {}, lobbies after {}\n\
+         Turn warning: {} before the timer runs out\n\
+         Game start cooldown: {}\n\
+         Tournament join window: {}\n\
+         History limit: {} numbers\n\
+         Per-user numbers: {}\n\
+         Bare commands: {}, aliases: {}\n\
+         Admins: {}\n\
+         Feedback chat: {}\n\
+         Replay channel: {}\n\
+         Telemetry: {}\n\
+         Board images: {}\n\
+         Welcome text: {}",
+        config.backend.name(),
+        config.backend.serializer_name(),
+        secs(config.storage_timeout),
+        secs(config.health_check_interval),
+        secs(config.game_max_age),
+        secs(config.lobby_timeout),
+        secs(config.turn_warning),
+        secs(config.game_start_cooldown),
+        secs(config.tournament_join_window),
+        config.history_limit,
+        on_off(config.per_user_numbers),
+        on_off(config.bare_commands),
+        config.aliases.len(),
+        config.admins.len(),
+        on_off(config.owner_chat.is_some()),
+        on_off(config.replay_channel.is_some()),
+        on_off(config.telemetry_file.is_some()),
+        on_off(cfg!(feature = "board-images")),
+        on_off(config.welcome.is_some()),
+        min = battle::MIN_BOARD_SIZE,
+        max = battle::MAX_KEYBOARD_BOARD,
+    )
+}
+
+/// Seats `user` under the name they picked with `/rename`, or their Telegram name.
+fn player(user: &teloxide::types::User, settings: &MySettings) -> Player {
+    let name = settings.lock().unwrap().name(user.id).map_or_else(|| user.full_name(), str::to_owned);
+    Player { id: user.id, name, username: user.username.clone() }
+}
+
+/// Unix time in seconds.
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use teloxide::dispatching2::dialogue::{serializer::Bincode, InMemStorage, SqliteStorage, Storage};
+
+    use super::*;
+
+    fn player(id: i64, name: &str) -> Player {
+        Player { id, name: name.to_owned(), username: None }
+    }
+
+    /// One state of every variant, with something in each of them.
+    fn states() -> Vec<State> {
+        let mut game = Game::new(3, Mode::TwoPlayer, WinCondition::Normal, 1_000);
+        game.play(player(1, "Alice"), 4).unwrap();
+        game.play(player(2, "Bob"), 0).unwrap();
+        game.play(player(1, "Alice"), 8).unwrap();
+        let mut battle = Battle::new(State::GotNumber(7, None));
+        let id = battle.next_game_id();
+        battle.games.insert(id, BattleGame { message_id: 42, game, pinned: true });
+
+        let user = teloxide::types::User {
+            id: 1,
+            is_bot: false,
+            first_name: "Alice".to_owned(),
+            last_name: None,
+            username: Some("alice".to_owned()),
+            language_code: None,
+        };
+        let mut party = Party::new(State::Start, party::DEFAULT_PARTY_SIZE, player(1, "Alice"));
+        party.message_id = 3;
+        let mut sandbox = Sandbox::new(State::SubNumber(-4, None), 4, 1);
+        sandbox.cycle(5);
+
+        vec![
+            State::Start,
+            State::GotNumber(i32::MIN, None),
+            State::AddNumber(12, Some(20)),
+            State::SubNumber(-12, Some(i32::MAX)),
+            State::Battle(battle),
+            State::Members(Members::with(&user, 99)),
+            State::Puzzle(Puzzle::random(State::AddNumber(1, None))),
+            State::Party(party),
+            State::Sandbox(sandbox),
+            State::Race(Race::new(State::GotNumber(10, None), 10, "20", 1_000).unwrap()),
+        ]
+    }
+
+    async fn round_trip<S>(storage: Arc<S>)
+    where
+        S: Storage<State> + ?Sized,
+        S::Error: std::fmt::Debug,
+    {
+        for (chat_id, state) in (1..).zip(states()) {
+            storage.clone().update_dialogue(chat_id, state.clone()).await.unwrap();
+            assert_eq!(storage.clone().get_dialogue(chat_id).await.unwrap(), Some(state));
+        }
+    }
+
+    /// A database file of its own for each test, removed before it's opened.
+    fn temp_db(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("tg-board-game-{}-{}.sqlite", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn operand_longer_than_the_limit_is_oversized() {
+        assert!(!is_oversized_operand("-12345678901"));
+        assert!(!is_oversized_operand(" 123456789012 "));
+        assert!(is_oversized_operand("1234567890123"));
+    }
+
+    fn callback_from(is_bot: bool) -> CallbackQuery {
+        CallbackQuery {
+            id: "1".to_owned(),
+            from: teloxide::types::User {
+                id: 7,
+                is_bot,
+                first_name: "Loop".to_owned(),
+                last_name: None,
+                username: None,
+                language_code: None,
+            },
+            message: None,
+            inline_message_id: None,
+            chat_instance: "1".to_owned(),
+            data: Some("set:delete_boards".to_owned()),
+            game_short_name: None,
+        }
+    }
+
+    #[test]
+    fn callbacks_from_bots_are_ignored() {
+        assert!(!is_human_callback(callback_from(true)));
+        assert!(is_human_callback(callback_from(false)));
+    }
+
+    #[test]
+    fn command_name_drops_the_slash_suffix_and_arguments() {
+        assert_eq!(command_name("/battle"), "battle");
+        assert_eq!(command_name(" /Battle@MyBot 5 4"), "battle");
+        assert_eq!(command_name("/add\t3"), "add");
+        assert_eq!(command_name("get"), "get");
+        assert_eq!(command_name(""), "");
+    }
+
+    #[test]
+    fn only_disabled_commands_are_refused() {
+        let mut settings = Settings::default();
+        assert!(settings.set_disabled(1, "battle", true));
+
+        assert!(calls_disabled_command(&settings, 1, "/battle"));
+        assert!(calls_disabled_command(&settings, 1, "/BATTLE@MyBot 4"));
+        assert!(!calls_disabled_command(&settings, 1, "/battles"));
+        assert!(!calls_disabled_command(&settings, 1, "/add 5"));
+        // Other chats keep the command.
+        assert!(!calls_disabled_command(&settings, 2, "/battle"));
+
+        settings.set_disabled(1, "battle", false);
+        assert!(!calls_disabled_command(&settings, 1, "/battle"));
+    }
+
+    #[test]
+    fn empty_callback_data_is_unknown() {
+        assert_eq!(parse_callback_data(""), None);
+    }
+
+    #[test]
+    fn unknown_callback_data_is_unknown() {
+        for data in ["bogus", "tour:leave", "g:x:first", "puzzle:", "party:dance", "set"] {
+            assert_eq!(parse_callback_data(data), None, "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn known_callback_data_parses() {
+        assert_eq!(parse_callback_data("tour:join"), Some(CallbackData::TournamentJoin));
+        assert_eq!(parse_callback_data("jump:3"), Some(CallbackData::Jump("3")));
+        assert_eq!(parse_callback_data("takeback:3:yes"), Some(CallbackData::Takeback("3:yes")));
+        assert_eq!(parse_callback_data("g:2:first"), Some(CallbackData::First(2)));
+        let cell = battle::cell_data(2, 4);
+        assert_eq!(parse_callback_data(&cell), Some(CallbackData::Board(2, BoardButton::Cell(4))));
+    }
+
+    #[test]
+    fn operands_are_numbers() {
+        assert_eq!(parse_operand("5"), Some(5));
+        assert_eq!(parse_operand(" -12 "), Some(-12));
+        assert_eq!(parse_operand("abc"), None);
+        assert_eq!(parse_operand(""), None);
+        assert_eq!(parse_operand("3000000000"), None);
+        assert_eq!(Op::Add.usage(), "Use /add <number>");
+    }
+
+    #[test]
+    fn flip_negates_the_number() {
+        assert_eq!(flip(0), Some(0));
+        assert_eq!(flip(42), Some(-42));
+        assert_eq!(flip(-42), Some(42));
+        assert_eq!(flip(i32::MAX), Some(-i32::MAX));
+    }
+
+    #[test]
+    fn flip_refuses_the_minimum() {
+        assert_eq!(flip(i32::MIN), None);
+        let text = flip_overflow_text(i32::MIN, "en");
+        assert_eq!(text, "-2,147,483,648 can't be flipped, its negation doesn't fit");
+    }
+
+    #[test]
+    fn command_token_is_lowercased() {
+        assert_eq!(normalize_command("/ADD 5"), "/add 5");
+        assert_eq!(normalize_command("/Get"), "/get");
+        assert_eq!(normalize_command("/SeT@MyBot Hello World"), "/set@MyBot Hello World");
+    }
+
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        assert_eq!(normalize_command("  /Get \n"), "/get");
+        assert_eq!(normalize_command(" /Sub \t  7 "), "/sub 7");
+        assert_eq!(normalize_command("/add  1  2"), "/add 1  2");
+        assert_eq!(normalize_command("   "), "");
+    }
+
+    #[test]
+    fn unclosed_quote_in_battle_options_says_so() {
+        let err = parse_battle_options(r#"4 "misere"#, ChatProfile::Group).err();
+        assert_eq!(err, Some(SizeError::UnclosedQuote));
+        assert!(parse_battle_options(r#"4 "misere""#, ChatProfile::Group).is_ok());
+    }
+
+    #[tokio::test]
+    async fn states_round_trip_in_memory() {
+        round_trip(InMemStorage::new()).await;
+    }
+
+    #[tokio::test]
+    async fn states_round_trip_as_json() {
+        let path = temp_db("json");
+        round_trip(SqliteStorage::open(&path, Json).await.unwrap()).await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn states_round_trip_as_bincode() {
+        let path = temp_db("bincode");
+        round_trip(SqliteStorage::open(&path, Bincode).await.unwrap()).await;
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use teloxide::types::User;
+
+/// The numbers of a group's members, when each member keeps their own number
+/// (`PER_USER_NUMBERS`). Keyed by user id.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Members {
+    numbers: HashMap<i64, Member>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Member {
+    pub id: i64,
+    pub name: String,
+    pub username: Option<String>,
+    pub number: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GiveError {
+    NotPositive,
+    ToSelf,
+    NoNumber,
+    NoRecipient,
+    /// The sender would end up below the floor, which is given.
+    BelowFloor(i32),
+    Overflow,
+}
+
+impl std::fmt::Display for GiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GiveError::NotPositive => write!(f, "You can only give a positive amount"),
+            GiveError::ToSelf => write!(f, "You can't give to yourself"),
+            GiveError::NoNumber => write!(f, "Send me your number first"),
+            GiveError::NoRecipient => write!(f, "They don't have a number yet"),
+            GiveError::BelowFloor(floor) => write!(f, "That would take your number below {}", floor),
+            GiveError::Overflow => write!(f, "Their number can't take that much"),
+        }
+    }
+}
+
+impl std::error::Error for GiveError {}
+
+impl Members {
+    /// A chat whose only number is `user`'s.
+    pub fn with(user: &User, number: i32) -> Self {
+        let mut members = Self::default();
+        members.set(user, number);
+        members
+    }
+
+    pub fn get(&self, user_id: i64) -> Option<&Member> {
+        self.numbers.get(&user_id)
+    }
+
+    /// Sets `user`'s number, refreshing the name they're shown and found by.
+    pub fn set(&mut self, user: &User, number: i32) {
+        let member = Member { id: user.id, name: user.full_name(), username: user.username.clone(), number };
+        self.numbers.insert(user.id, member);
+    }
+
+    /// Moves `amount` from `from`'s number to `to`'s and returns both new numbers. Neither
+    /// number changes unless both can: `from` mustn't go below `floor` and `to` mustn't
+    /// overflow.
+    pub fn give(&mut self, from: i64, to: i64, amount: i32, floor: i32) -> Result<(i32, i32), GiveError> {
+        if amount <= 0 {
+            return Err(GiveError::NotPositive);
+        }
+        if from == to {
+            return Err(GiveError::ToSelf);
+        }
+        let sender = self.get(from).ok_or(GiveError::NoNumber)?.number;
+        let recipient = self.get(to).ok_or(GiveError::NoRecipient)?.number;
+        let sender = sender.checked_sub(amount).filter(|&n| n >= floor).ok_or(GiveError::BelowFloor(floor))?;
+        let recipient = recipient.checked_add(amount).ok_or(GiveError::Overflow)?;
+        for (id, number) in [(from, sender), (to, recipient)] {
+            if let Some(member) = self.numbers.get_mut(&id) {
+                member.number = number;
+            }
+        }
+        Ok((sender, recipient))
+    }
+
+    pub fn remove(&mut self, user_id: i64) {
+        self.numbers.remove(&user_id);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.numbers.is_empty()
+    }
+
+    /// Finds a member by their username, with or without the leading `@`.
+    pub fn find(&self, username: &str) -> Option<&Member> {
+        let username = username.trim_start_matches('@');
+        self.numbers
+            .values()
+            .find(|m| m.username.as_deref().is_some_and(|u| u.eq_ignore_ascii_case(username)))
+    }
+}
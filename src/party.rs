@@ -0,0 +1,295 @@
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::{game::Player, State};
+
+/// The most players a party game takes, one per symbol.
+pub const MAX_PARTY_PLAYERS: usize = 6;
+/// What each player marks their cells with, in the order they joined.
+const SYMBOLS: [&str; MAX_PARTY_PLAYERS] = ["🟥", "🟦", "🟩", "🟨", "🟪", "🟧"];
+/// The board of a `/party` without a size.
+pub const DEFAULT_PARTY_SIZE: usize = 5;
+
+/// A shared board on which the players who joined take turns, in the order they
+/// joined, until one of them has [`Party::line_length`] cells in a row.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Party {
+    /// The state to go back to once the party is over.
+    previous: Box<State>,
+    size: usize,
+    /// By the index of the seat that marked the cell.
+    cells: Vec<Option<usize>>,
+    /// In turn order. Seats are kept when their player leaves, so that the marks they
+    /// made stay theirs.
+    seats: Vec<Seat>,
+    /// The seat to move, once the party started.
+    pub current: Option<usize>,
+    /// The lobby, then the board.
+    pub message_id: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct Seat {
+    player: Player,
+    left: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartyError {
+    Full,
+    AlreadyIn,
+    Started,
+    NotStarted,
+    NotEnoughPlayers,
+    NotYourTurn,
+    Occupied,
+}
+
+impl std::fmt::Display for PartyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PartyError::Full => write!(f, "The party is full, {} players at most", MAX_PARTY_PLAYERS),
+            PartyError::AlreadyIn => write!(f, "You're in already"),
+            PartyError::Started => write!(f, "The party has started already"),
+            PartyError::NotStarted => write!(f, "The party hasn't started yet"),
+            PartyError::NotEnoughPlayers => write!(f, "A party needs at least 2 players"),
+            PartyError::NotYourTurn => write!(f, "It's not your turn"),
+            PartyError::Occupied => write!(f, "This cell is already taken"),
+        }
+    }
+}
+
+impl std::error::Error for PartyError {}
+
+/// How a move or a player leaving changed the party.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartyOutcome {
+    Going,
+    /// The seat that won, by a line or by being the last one left.
+    Won(usize),
+    Draw,
+    /// Everybody left.
+    Abandoned,
+}
+
+impl Party {
+    /// A party hosted by `host`, who takes the first seat.
+    pub fn new(previous: State, size: usize, host: Player) -> Self {
+        Self {
+            previous: Box::new(previous),
+            size,
+            cells: vec![None; size * size],
+            seats: vec![Seat { player: host, left: false }],
+            current: None,
+            message_id: 0,
+        }
+    }
+
+    /// The state the chat was in before the party.
+    pub fn into_previous(self) -> State {
+        *self.previous
+    }
+
+    /// How many cells in a row win: 3, or 4 from 6×6 on.
+    pub fn line_length(&self) -> usize {
+        if self.size < 6 {
+            3
+        } else {
+            4
+        }
+    }
+
+    /// How many cells were marked so far.
+    pub fn moves(&self) -> usize {
+        self.cells.iter().filter(|cell| cell.is_some()).count()
+    }
+
+    pub fn host(&self) -> i64 {
+        self.seats[0].player.id
+    }
+
+    fn seat_of(&self, user_id: i64) -> Option<usize> {
+        self.seats.iter().position(|seat| !seat.left && seat.player.id == user_id)
+    }
+
+    pub fn is_in(&self, user_id: i64) -> bool {
+        self.seat_of(user_id).is_some()
+    }
+
+    /// Everybody who played, including those who left.
+    pub fn player_ids(&self) -> Vec<i64> {
+        self.seats.iter().map(|seat| seat.player.id).collect()
+    }
+
+    pub fn player(&self, seat: usize) -> &Player {
+        &self.seats[seat].player
+    }
+
+    pub fn join(&mut self, player: Player) -> Result<(), PartyError> {
+        if self.current.is_some() {
+            return Err(PartyError::Started);
+        }
+        if self.is_in(player.id) {
+            return Err(PartyError::AlreadyIn);
+        }
+        if self.seats.len() >= MAX_PARTY_PLAYERS {
+            return Err(PartyError::Full);
+        }
+        self.seats.push(Seat { player, left: false });
+        Ok(())
+    }
+
+    pub fn start(&mut self) -> Result<(), PartyError> {
+        if self.current.is_some() {
+            return Err(PartyError::Started);
+        }
+        if self.seats.len() < 2 {
+            return Err(PartyError::NotEnoughPlayers);
+        }
+        self.current = Some(0);
+        Ok(())
+    }
+
+    /// Lets `user_id` mark `cell` if it's their turn, and passes the turn on.
+    pub fn play(&mut self, user_id: i64, cell: usize) -> Result<PartyOutcome, PartyError> {
+        let current = self.current.ok_or(PartyError::NotStarted)?;
+        if self.seat_of(user_id) != Some(current) {
+            return Err(PartyError::NotYourTurn);
+        }
+        match self.cells.get_mut(cell) {
+            Some(slot @ None) => *slot = Some(current),
+            _ => return Err(PartyError::Occupied),
+        }
+        if self.has_line(current) {
+            return Ok(PartyOutcome::Won(current));
+        }
+        if self.cells.iter().all(Option::is_some) {
+            return Ok(PartyOutcome::Draw);
+        }
+        self.current = self.next_seat(current);
+        Ok(PartyOutcome::Going)
+    }
+
+    /// Takes `user_id` out of the queue. In the lobby their seat is freed; once the party
+    /// started, the turn skips them and the last player left wins.
+    pub fn leave(&mut self, user_id: i64) -> Option<PartyOutcome> {
+        let seat = self.seat_of(user_id)?;
+        let current = match self.current {
+            Some(current) => current,
+            None => {
+                self.seats.remove(seat);
+                return Some(if self.seats.is_empty() { PartyOutcome::Abandoned } else { PartyOutcome::Going });
+            }
+        };
+        self.seats[seat].left = true;
+        let remaining: Vec<usize> = (0..self.seats.len()).filter(|&i| !self.seats[i].left).collect();
+        Some(match remaining.as_slice() {
+            [] => PartyOutcome::Abandoned,
+            [last] => PartyOutcome::Won(*last),
+            _ => {
+                if seat == current {
+                    self.current = self.next_seat(current);
+                }
+                PartyOutcome::Going
+            }
+        })
+    }
+
+    /// The seat after `seat` in turn order that is still in the party.
+    fn next_seat(&self, seat: usize) -> Option<usize> {
+        (1..=self.seats.len()).map(|step| (seat + step) % self.seats.len()).find(|&i| !self.seats[i].left)
+    }
+
+    /// Whether `seat` has [`Party::line_length`] cells in a row, in any direction.
+    fn has_line(&self, seat: usize) -> bool {
+        let (n, k) = (self.size as isize, self.line_length() as isize);
+        let owns = |row: isize, col: isize| {
+            (0..n).contains(&row) && (0..n).contains(&col) && self.cells[(row * n + col) as usize] == Some(seat)
+        };
+        (0..n).any(|row| {
+            (0..n).any(|col| {
+                [(0, 1), (1, 0), (1, 1), (1, -1)]
+                    .iter()
+                    .any(|&(dr, dc)| (0..k).all(|i| owns(row + i * dr, col + i * dc)))
+            })
+        })
+    }
+}
+
+/// The lobby, with the players who joined so far.
+pub fn lobby_text(party: &Party) -> String {
+    let mut text = format!(
+        "🎉 Party game on a {0}×{0} board, {1} in a row wins. Join, then the host starts it.",
+        party.size,
+        party.line_length()
+    );
+    for (i, seat) in party.seats.iter().enumerate() {
+        text.push_str(&format!("\n{} {}", SYMBOLS[i], seat.player.name));
+    }
+    text
+}
+
+pub fn lobby_keyboard() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback("Join".to_owned(), "party:join".to_owned()),
+        InlineKeyboardButton::callback("Start".to_owned(), "party:start".to_owned()),
+    ]])
+}
+
+/// The turn order, pointing at the player to move, or the final board and `result`
+/// once it's over.
+pub fn party_text(party: &Party, result: Option<&str>) -> String {
+    let mut text = String::from("🎉 Party game");
+    for (i, seat) in party.seats.iter().enumerate() {
+        let marker = match (seat.left, party.current == Some(i) && result.is_none()) {
+            (true, _) => " (left)",
+            (false, true) => " ⬅️",
+            (false, false) => "",
+        };
+        text.push_str(&format!("\n{} {}{}", SYMBOLS[i], seat.player.name, marker));
+    }
+    if let Some(result) = result {
+        let rows: Vec<String> =
+            party.cells.chunks(party.size).map(|cells| cells.iter().map(|&cell| symbol(cell)).collect()).collect();
+        text.push_str(&format!("\n\n{}\n\n{}", rows.join("\n"), result));
+    }
+    text
+}
+
+fn symbol(cell: Option<usize>) -> &'static str {
+    cell.map_or("⬜", |seat| SYMBOLS[seat])
+}
+
+/// The board, with a button per cell.
+pub fn party_board(party: &Party) -> InlineKeyboardMarkup {
+    let keyboard: Vec<Vec<_>> = party
+        .cells
+        .chunks(party.size)
+        .enumerate()
+        .map(|(row, cells)| {
+            cells
+                .iter()
+                .enumerate()
+                .map(|(col, &cell)| {
+                    InlineKeyboardButton::callback(symbol(cell).to_owned(), format!("party:{}", row * party.size + col))
+                })
+                .collect()
+        })
+        .collect();
+    InlineKeyboardMarkup::new(keyboard)
+}
+
+/// What a party button asks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartyButton {
+    Join,
+    Start,
+    Cell(usize),
+}
+
+pub fn parse_party_data(data: &str) -> Option<PartyButton> {
+    match data.strip_prefix("party:")? {
+        "join" => Some(PartyButton::Join),
+        "start" => Some(PartyButton::Start),
+        cell => cell.parse().ok().map(PartyButton::Cell),
+    }
+}
@@ -0,0 +1,60 @@
+use teloxide::types::Chat;
+
+use crate::{ai::Difficulty, config::Config, game::Mode};
+
+/// The kinds of chats the bot behaves differently in, by default. Handlers ask
+/// [`chat_profile`] rather than checking the kind of chat themselves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChatProfile {
+    /// A user alone with the bot: their number, and games against the bot.
+    Private,
+    /// A group or a supergroup: games between members, and a number for each of them
+    /// if `PER_USER_NUMBERS` is set.
+    Group,
+    /// A channel, where the bot only posts, e.g. the replay feed.
+    Channel,
+}
+
+pub fn chat_profile(chat: &Chat) -> ChatProfile {
+    if chat.is_private() {
+        ChatProfile::Private
+    } else if chat.is_channel() {
+        ChatProfile::Channel
+    } else {
+        ChatProfile::Group
+    }
+}
+
+impl ChatProfile {
+    /// Whether the bot answers messages here. Nobody can play or keep a number in a
+    /// channel, so its posts are ignored.
+    pub fn answers_messages(self) -> bool {
+        self != ChatProfile::Channel
+    }
+
+    /// Whether games against the bot are the default: a private chat has nobody to take
+    /// the second seat.
+    pub fn plays_bot_by_default(self) -> bool {
+        self == ChatProfile::Private
+    }
+
+    /// What a `/battle` without a mode option plays.
+    pub fn default_mode(self) -> Mode {
+        if self.plays_bot_by_default() {
+            Mode::VsAi(Difficulty::Medium)
+        } else {
+            Mode::TwoPlayer
+        }
+    }
+
+    /// Whether several users share the chat, so that challenges can be sent in it and
+    /// its settings are left to its admins.
+    pub fn has_members(self) -> bool {
+        self == ChatProfile::Group
+    }
+
+    /// Whether every member gets their own number instead of one for the whole chat.
+    pub fn per_user_numbers(self, config: &Config) -> bool {
+        config.per_user_numbers && self.has_members()
+    }
+}
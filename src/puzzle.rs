@@ -0,0 +1,123 @@
+use rand::seq::SliceRandom;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::{
+    ai,
+    game::{Board, Mark, Outcome, WinCondition},
+    State,
+};
+
+/// Positions in which the side to move has exactly one move that wins with best play,
+/// none of which wins on the spot. Written as [`Board::from_str`](std::str::FromStr)
+/// reads them; the side with fewer marks moves.
+const PUZZLES: &[&str] = &[
+    ".../..X/O..",
+    ".../X../..O",
+    "..O/X../...",
+    ".../X.X/O..",
+    "..O/.../XX.",
+    ".../X../.XO",
+    ".../X.O/.XO",
+    "OO./..X/X..",
+    "OO./.X./..X",
+    ".OX/X.X/..O",
+    ".X./X../OXO",
+    "..X/..X/OXO",
+];
+
+/// A `/puzzle` waiting for its answer.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Puzzle {
+    /// The state to go back to once the puzzle is answered.
+    previous: Box<State>,
+    pub board: Board,
+    pub to_move: Mark,
+    pub solution: usize,
+    /// The message with the puzzle's buttons, set once it's sent.
+    pub message_id: i32,
+}
+
+impl Puzzle {
+    /// A random puzzle of [`PUZZLES`].
+    pub fn random(previous: State) -> Self {
+        let position = PUZZLES.choose(&mut rand::thread_rng()).expect("there are puzzles");
+        let board: Board = position.parse().expect("puzzles are valid boards");
+        let count = |mark: Mark| board.cells().iter().filter(|&&cell| cell == Some(mark)).count();
+        let to_move = if count(Mark::X) > count(Mark::O) { Mark::O } else { Mark::X };
+        let solution = match winning_moves(&board, to_move).as_slice() {
+            &[solution] => solution,
+            _ => panic!("puzzle {} doesn't have exactly one winning move", position),
+        };
+        Self { previous: Box::new(previous), board, to_move, solution, message_id: 0 }
+    }
+
+    /// The state the chat was in before the puzzle.
+    pub fn into_previous(self) -> State {
+        *self.previous
+    }
+}
+
+/// The cells on which `mark` can win against any defence.
+pub fn winning_moves(board: &Board, mark: Mark) -> Vec<usize> {
+    board
+        .empty_cells()
+        .filter(|&cell| {
+            let mut board = board.clone();
+            board.place(cell, mark).ok();
+            ai::assess(&board, mark.other(), WinCondition::Normal) == Some(Outcome::Win(mark))
+        })
+        .collect()
+}
+
+pub fn puzzle_data(cell: usize) -> String {
+    format!("puzzle:{}", cell)
+}
+
+pub fn parse_puzzle_data(data: &str) -> Option<usize> {
+    data.strip_prefix("puzzle:")?.parse().ok()
+}
+
+pub fn puzzle_text(puzzle: &Puzzle) -> String {
+    format!("🧩 {} to move and win. Which cell wins?", puzzle.to_move.symbol())
+}
+
+pub fn puzzle_keyboard(puzzle: &Puzzle) -> InlineKeyboardMarkup {
+    let board = &puzzle.board;
+    let keyboard: Vec<Vec<_>> = board
+        .cells()
+        .chunks(board.size())
+        .enumerate()
+        .map(|(row, cells)| {
+            cells
+                .iter()
+                .enumerate()
+                .map(|(col, cell)| {
+                    let label = cell.map_or("⬜", |mark| mark.symbol());
+                    InlineKeyboardButton::callback(label.to_owned(), puzzle_data(row * board.size() + col))
+                })
+                .collect()
+        })
+        .collect();
+    InlineKeyboardMarkup::new(keyboard)
+}
+
+/// The puzzle's board with a star on the winning move.
+pub fn solution_text(puzzle: &Puzzle) -> String {
+    let board = &puzzle.board;
+    board
+        .cells()
+        .chunks(board.size())
+        .enumerate()
+        .map(|(row, cells)| {
+            cells
+                .iter()
+                .enumerate()
+                .map(|(col, cell)| match row * board.size() + col {
+                    cell_index if cell_index == puzzle.solution => "⭐",
+                    _ => cell.map_or("⬜", |mark| mark.symbol()),
+                })
+                .collect()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
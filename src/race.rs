@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use teloxide::prelude2::*;
+
+use crate::{MyDialogue, State};
+
+/// How long a `/race` lasts.
+pub const RACE_TIME_LIMIT: Duration = Duration::from_secs(60);
+/// The largest number `/add` and `/sub` take during a race, so that reaching the target
+/// takes more than a single step.
+pub const MAX_RACE_STEP: i32 = 9;
+/// How far the target of a race may be from the number it starts from.
+pub const MAX_RACE_DISTANCE: i64 = 500;
+
+/// A `/race`: the chat brings a copy of its number to `target` with small steps before
+/// the time runs out. The number itself is left as it was.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Race {
+    /// The state to go back to once the race is over.
+    previous: Box<State>,
+    /// Tells this race from the next one of the chat, for the timer of [`spawn_race_timer`].
+    pub id: u32,
+    pub number: i32,
+    pub target: i32,
+    pub steps: u32,
+    /// Unix time in seconds.
+    pub started_at: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RaceError {
+    NotNumeric,
+    SameNumber,
+    TooFar,
+}
+
+impl std::fmt::Display for RaceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RaceError::NotNumeric => write!(f, "Use /race <target>, e.g. /race 42"),
+            RaceError::SameNumber => write!(f, "You're there already, pick another target"),
+            RaceError::TooFar => write!(f, "The target can be at most {} away from your number", MAX_RACE_DISTANCE),
+        }
+    }
+}
+
+impl std::error::Error for RaceError {}
+
+/// How a step changed the race.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RaceStep {
+    Going,
+    Reached,
+    /// The step was zero or bigger than [`MAX_RACE_STEP`], and wasn't taken.
+    TooBig,
+}
+
+impl Race {
+    /// A race from `number` to the target given in `arg`, starting at `now`.
+    pub fn new(previous: State, number: i32, arg: &str, now: u64) -> Result<Self, RaceError> {
+        let target: i32 = arg.trim().parse().map_err(|_| RaceError::NotNumeric)?;
+        match (i64::from(target) - i64::from(number)).abs() {
+            0 => return Err(RaceError::SameNumber),
+            distance if distance > MAX_RACE_DISTANCE => return Err(RaceError::TooFar),
+            _ => {}
+        }
+        Ok(Self { previous: Box::new(previous), id: rand::random(), number, target, steps: 0, started_at: now })
+    }
+
+    /// The state the chat was in before the race.
+    pub fn into_previous(self) -> State {
+        *self.previous
+    }
+
+    /// Adds `delta` to the number, which is negative for `/sub`.
+    pub fn step(&mut self, delta: i32) -> RaceStep {
+        if !(1..=MAX_RACE_STEP).contains(&delta.saturating_abs()) {
+            return RaceStep::TooBig;
+        }
+        self.number = self.number.saturating_add(delta);
+        self.steps += 1;
+        if self.number == self.target {
+            RaceStep::Reached
+        } else {
+            RaceStep::Going
+        }
+    }
+
+    pub fn seconds_left(&self, now: u64) -> u64 {
+        (self.started_at + RACE_TIME_LIMIT.as_secs()).saturating_sub(now)
+    }
+
+    fn distance(&self) -> i64 {
+        (i64::from(self.target) - i64::from(self.number)).abs()
+    }
+}
+
+pub fn start_text(race: &Race) -> String {
+    format!(
+        "🏁 Race from {} to {}! Use /add and /sub with 1 to {}, you have {} seconds. /cancel gives up",
+        race.number,
+        race.target,
+        MAX_RACE_STEP,
+        RACE_TIME_LIMIT.as_secs()
+    )
+}
+
+pub fn progress_text(race: &Race, now: u64) -> String {
+    format!("Now {}, {} to go, {}s left", race.number, race.distance(), race.seconds_left(now))
+}
+
+pub fn finish_text(race: &Race, now: u64) -> String {
+    format!(
+        "🏆 You reached {} in {}s and {} steps!",
+        race.target,
+        now.saturating_sub(race.started_at),
+        race.steps
+    )
+}
+
+pub fn timeout_text(race: &Race) -> String {
+    format!("⏰ Time's up! You got to {}, {} away from {}", race.number, race.distance(), race.target)
+}
+
+/// Ends the race `id` of `chat_id` once its time is up, unless it was won or given up
+/// before, and tells the chat.
+pub fn spawn_race_timer(bot: AutoSend<Bot>, dialogue: MyDialogue, chat_id: i64, id: u32) {
+    tokio::spawn(async move {
+        tokio::time::sleep(RACE_TIME_LIMIT).await;
+        let race = match dialogue.get().await {
+            Ok(Some(State::Race(race))) if race.id == id => race,
+            Ok(_) => return,
+            Err(err) => {
+                log::warn!("Failed to check the race in chat {}: {}", chat_id, err);
+                return;
+            }
+        };
+        let text = timeout_text(&race);
+        if let Err(err) = dialogue.update(race.into_previous()).await {
+            log::warn!("Failed to end the race in chat {}: {}", chat_id, err);
+            return;
+        }
+        if let Err(err) = bot.send_message(chat_id, text).await {
+            log::warn!("Failed to report the end of the race in chat {}: {}", chat_id, err);
+        }
+    });
+}
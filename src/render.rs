@@ -0,0 +1,91 @@
+use std::{collections::HashMap, time::Duration};
+
+use teloxide::{prelude2::*, types::InlineKeyboardMarkup};
+
+use crate::MyActiveGames;
+
+/// Board edits closer together than this are merged, see [`edit_board`].
+pub const EDIT_WINDOW: Duration = Duration::from_millis(500);
+
+/// The boards edited less than [`EDIT_WINDOW`] ago, by chat id and message id, with the
+/// edit waiting for the end of the window, if any.
+#[derive(Debug, Default)]
+pub struct BoardEdits {
+    recent: HashMap<(i64, i32), Option<Render>>,
+}
+
+#[derive(Clone, Debug)]
+struct Render {
+    text: String,
+    markup: Option<InlineKeyboardMarkup>,
+}
+
+impl BoardEdits {
+    /// Returns the render to send now, or keeps it for later if the board was edited
+    /// within the window, replacing the one that was waiting.
+    fn start(&mut self, chat_id: i64, message_id: i32, render: Render) -> Option<Render> {
+        match self.recent.get_mut(&(chat_id, message_id)) {
+            Some(pending) => {
+                *pending = Some(render);
+                None
+            }
+            None => {
+                self.recent.insert((chat_id, message_id), None);
+                Some(render)
+            }
+        }
+    }
+
+    /// Takes the render waiting at the end of a window. Without one, the board is no
+    /// longer recent.
+    fn take(&mut self, chat_id: i64, message_id: i32) -> Option<Render> {
+        let render = self.recent.get_mut(&(chat_id, message_id))?.take();
+        if render.is_none() {
+            self.recent.remove(&(chat_id, message_id));
+        }
+        render
+    }
+}
+
+/// Edits a board to `text`, with `markup` as its buttons or without buttons. The first
+/// edit is sent right away; those that follow within [`EDIT_WINDOW`] are merged so that
+/// only the newest is sent when the window ends. Fast games thus stay under Telegram's
+/// edit rate limits, and the last state is always the one shown.
+pub async fn edit_board(
+    bot: &AutoSend<Bot>,
+    active_games: &MyActiveGames,
+    chat_id: i64,
+    message_id: i32,
+    text: String,
+    markup: Option<InlineKeyboardMarkup>,
+) -> crate::HandlerResult {
+    let render = active_games.lock().unwrap().edits.start(chat_id, message_id, Render { text, markup });
+    if let Some(render) = render {
+        tokio::spawn(flush(bot.clone(), active_games.clone(), chat_id, message_id));
+        send(bot, chat_id, message_id, render).await?;
+    }
+    Ok(())
+}
+
+/// Sends the render waiting at the end of each window, until a window passes without one.
+async fn flush(bot: AutoSend<Bot>, active_games: MyActiveGames, chat_id: i64, message_id: i32) {
+    loop {
+        tokio::time::sleep(EDIT_WINDOW).await;
+        let render = match active_games.lock().unwrap().edits.take(chat_id, message_id) {
+            Some(render) => render,
+            None => return,
+        };
+        if let Err(err) = send(&bot, chat_id, message_id, render).await {
+            log::warn!("Failed to edit the board in chat {}: {}", chat_id, err);
+        }
+    }
+}
+
+async fn send(bot: &AutoSend<Bot>, chat_id: i64, message_id: i32, render: Render) -> crate::HandlerResult {
+    let edit = bot.edit_message_text(chat_id, message_id, render.text);
+    match render.markup {
+        Some(markup) => edit.reply_markup(markup).await?,
+        None => edit.await?,
+    };
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use std::{collections::HashMap, time::Duration};
+
+use teloxide::{
+    prelude2::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+};
+
+use crate::{battle::board_text, game::Game, MyReplays};
+
+/// How long each position of a replay is shown.
+pub const REPLAY_DELAY: Duration = Duration::from_millis(1500);
+
+/// The replay running in each chat, by chat id. A new `/replay` stops the one before.
+#[derive(Debug, Default)]
+pub struct Replays {
+    running: HashMap<i64, Running>,
+}
+
+#[derive(Debug)]
+struct Running {
+    message_id: i32,
+    game: Game,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Replays {
+    /// Stops the replay of `chat_id` shown in `message_id`, returning its game. `None`
+    /// if that replay already ended.
+    pub fn stop(&mut self, chat_id: i64, message_id: i32) -> Option<Game> {
+        if self.running.get(&chat_id)?.message_id != message_id {
+            return None;
+        }
+        let running = self.running.remove(&chat_id)?;
+        running.task.abort();
+        Some(running.game)
+    }
+
+    fn start(&mut self, chat_id: i64, running: Running) {
+        if let Some(previous) = self.running.insert(chat_id, running) {
+            previous.task.abort();
+        }
+    }
+
+    fn finish(&mut self, chat_id: i64, message_id: i32) {
+        if self.running.get(&chat_id).is_some_and(|running| running.message_id == message_id) {
+            self.running.remove(&chat_id);
+        }
+    }
+}
+
+/// The replay of `game` after its first `step` moves, starting from the position it was
+/// started from.
+pub fn replay_text(game: &Game, step: usize) -> String {
+    let mut board = game.board.clone();
+    for &cell in &game.moves[step..] {
+        board.clear(cell);
+    }
+    format!("▶️ Replay, move {} of {}\n{}", step, game.moves.len(), board_text(&board))
+}
+
+pub fn skip_keyboard() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+        "Skip to end".to_owned(),
+        "replay:skip".to_owned(),
+    )]])
+}
+
+/// Sends the first position of `game` to `chat_id` and plays its moves one by one on
+/// that message. The replay has its own message, so it never edits a live board.
+pub async fn start_replay(
+    bot: &AutoSend<Bot>,
+    replays: &MyReplays,
+    chat_id: i64,
+    game: Game,
+) -> crate::HandlerResult {
+    let sent = bot.send_message(chat_id, replay_text(&game, 0)).reply_markup(skip_keyboard()).await?;
+    let task = tokio::spawn(play(bot.clone(), replays.clone(), chat_id, sent.id, game.clone()));
+    replays.lock().unwrap().start(chat_id, Running { message_id: sent.id, game, task });
+    Ok(())
+}
+
+async fn play(bot: AutoSend<Bot>, replays: MyReplays, chat_id: i64, message_id: i32, game: Game) {
+    let moves = game.moves.len();
+    for step in 1..=moves {
+        tokio::time::sleep(REPLAY_DELAY).await;
+        let edit = bot.edit_message_text(chat_id, message_id, replay_text(&game, step));
+        let edited = if step < moves { edit.reply_markup(skip_keyboard()).await } else { edit.await };
+        if let Err(err) = edited {
+            log::warn!("Stopping the replay in chat {}: {}", chat_id, err);
+            break;
+        }
+    }
+    replays.lock().unwrap().finish(chat_id, message_id);
+}
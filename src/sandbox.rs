@@ -0,0 +1,135 @@
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::{
+    ai,
+    game::{check_winner, Board, Mark, Outcome, WinCondition},
+    puzzle, State,
+};
+
+/// A `/sandbox` board, on which its owner places and removes marks freely.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Sandbox {
+    /// The state to go back to once the sandbox is closed.
+    previous: Box<State>,
+    /// The user who opened it, the only one who may change it.
+    pub owner: i64,
+    pub board: Board,
+    /// The message with the sandbox's buttons, set once it's sent.
+    pub message_id: i32,
+}
+
+impl Sandbox {
+    pub fn new(previous: State, size: usize, owner: i64) -> Self {
+        Self { previous: Box::new(previous), owner, board: Board::new(size), message_id: 0 }
+    }
+
+    /// The state the chat was in before the sandbox.
+    pub fn into_previous(self) -> State {
+        *self.previous
+    }
+
+    /// Turns `cell` from empty to X, from X to O and from O back to empty. Returns
+    /// `false` if there is no such cell.
+    pub fn cycle(&mut self, cell: usize) -> bool {
+        let next = match self.board.cells().get(cell) {
+            None => return false,
+            Some(None) => Some(Mark::X),
+            Some(Some(Mark::X)) => Some(Mark::O),
+            Some(Some(Mark::O)) => None,
+        };
+        self.board.clear(cell);
+        if let Some(mark) = next {
+            self.board.place(cell, mark).ok();
+        }
+        true
+    }
+}
+
+/// What a sandbox button asks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SandboxButton {
+    Cell(usize),
+    Solve,
+    Close,
+}
+
+pub fn parse_sandbox_data(data: &str) -> Option<SandboxButton> {
+    match data.strip_prefix("sandbox:")? {
+        "solve" => Some(SandboxButton::Solve),
+        "close" => Some(SandboxButton::Close),
+        cell => cell.parse().ok().map(SandboxButton::Cell),
+    }
+}
+
+/// The sandbox's title, followed by `analysis` after a Solve.
+pub fn sandbox_text(sandbox: &Sandbox, analysis: Option<&str>) -> String {
+    let text = format!(
+        "🧪 Sandbox {0}×{0}: tap a cell to cycle it through ⬜, {1} and {2}. Nothing ends here.",
+        sandbox.board.size(),
+        Mark::X.symbol(),
+        Mark::O.symbol(),
+    );
+    match analysis {
+        Some(analysis) => format!("{}\n\n🔍 {}", text, analysis),
+        None => text,
+    }
+}
+
+pub fn sandbox_keyboard(sandbox: &Sandbox) -> InlineKeyboardMarkup {
+    let board = &sandbox.board;
+    let mut keyboard: Vec<Vec<_>> = board
+        .cells()
+        .chunks(board.size())
+        .enumerate()
+        .map(|(row, cells)| {
+            cells
+                .iter()
+                .enumerate()
+                .map(|(col, cell)| {
+                    let label = cell.map_or("⬜", |mark| mark.symbol());
+                    InlineKeyboardButton::callback(label.to_owned(), format!("sandbox:{}", row * board.size() + col))
+                })
+                .collect()
+        })
+        .collect();
+    keyboard.push(vec![
+        InlineKeyboardButton::callback("🔍 Solve".to_owned(), "sandbox:solve".to_owned()),
+        InlineKeyboardButton::callback("Close".to_owned(), "sandbox:close".to_owned()),
+    ]);
+    InlineKeyboardMarkup::new(keyboard)
+}
+
+/// What the analyzer makes of the position on `board`, with the side that has fewer
+/// marks to move, X if they have as many: the result with best play and the moves that
+/// get it on boards small enough to solve, the hard bot's choice on bigger ones.
+pub fn analysis_text(board: &Board) -> String {
+    let count = |mark: Mark| board.cells().iter().filter(|&&cell| cell == Some(mark)).count();
+    let (x, o) = (count(Mark::X), count(Mark::O));
+    if x.abs_diff(o) > 1 {
+        return "No game gets here: one side has more than one mark more than the other".to_owned();
+    }
+    match check_winner(board, WinCondition::Normal) {
+        Some(Outcome::Win(mark)) => return format!("The game is over, {} won", mark.symbol()),
+        Some(Outcome::Draw) => return "The board is full, it's a draw".to_owned(),
+        None => {}
+    }
+    let to_move = if x > o { Mark::O } else { Mark::X };
+    let cells = |cells: &[usize]| cells.iter().map(|cell| (cell + 1).to_string()).collect::<Vec<_>>().join(", ");
+    match ai::assess(board, to_move, WinCondition::Normal) {
+        Some(Outcome::Win(mark)) if mark == to_move => format!(
+            "{} to move wins with best play, on {}",
+            to_move.symbol(),
+            cells(&puzzle::winning_moves(board, to_move))
+        ),
+        Some(Outcome::Win(mark)) => {
+            format!("{} to move loses against best play, {} wins", to_move.symbol(), mark.symbol())
+        }
+        Some(Outcome::Draw) => format!("{} to move: a draw with best play", to_move.symbol()),
+        None => match ai::best_move(board, to_move, WinCondition::Normal) {
+            Some(cell) => {
+                format!("{} to move. Too big to solve, the hard bot would play {}", to_move.symbol(), cell + 1)
+            }
+            None => "No move left".to_owned(),
+        },
+    }
+}
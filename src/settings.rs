@@ -0,0 +1,370 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::i18n;
+
+/// The move timers `/settings` cycles through, in seconds; `None` is no timer.
+pub const MOVE_TIMERS: &[Option<u64>] = &[None, Some(30), Some(60), Some(120)];
+/// The nudge delays `/settings` cycles through, in seconds; `None` is no nudge.
+pub const NUDGE_TIMERS: &[Option<u64>] = &[None, Some(15), Some(30), Some(60)];
+/// The delays before confirmations are deleted that `/settings` cycles through, in
+/// seconds; `None` keeps them.
+pub const AUTO_DELETE_DELAYS: &[Option<u64>] = &[None, Some(10), Some(30), Some(60)];
+/// The board sizes `/settings` cycles through.
+pub const BOARD_SIZES: &[usize] = &[3, 4, 5];
+/// The longest name `/rename` accepts, in characters.
+pub const MAX_NAME_LEN: usize = 32;
+/// What scoreboards call the players who turned `/anon` on.
+pub const ANONYMOUS: &str = "Anonymous";
+
+/// Per-user and per-chat preferences.
+#[derive(Debug, Default)]
+pub struct Settings {
+    /// By user id.
+    locales: HashMap<i64, &'static str>,
+    /// The names set with `/rename`, by user id.
+    names: HashMap<i64, String>,
+    /// Users who turned `/anon` on, shown as [`ANONYMOUS`] on scoreboards.
+    anonymous: HashSet<i64>,
+    /// Users who turned `/alerts` on, whose moves are confirmed with an alert.
+    alerts: HashSet<i64>,
+    /// By chat id.
+    prefs: HashMap<i64, GamePrefs>,
+    /// The message showing a chat's number, by chat id, see [`GamePrefs::edit_status`].
+    status_messages: HashMap<i64, i32>,
+    /// The scoreboard message of a chat, by chat id, see [`GamePrefs::pinned_scoreboard`].
+    scoreboard_messages: HashMap<i64, i32>,
+    /// Chats that got the welcome message.
+    welcomed: HashSet<i64>,
+    /// The commands turned off with `/disable`, without the slash, by chat id.
+    disabled_commands: HashMap<i64, BTreeSet<String>>,
+}
+
+/// What `/backup` saves of [`Settings`], which is all of it. Maps are lists of entries,
+/// since JSON objects only take string keys.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct SettingsBackup {
+    locales: Vec<(i64, String)>,
+    names: Vec<(i64, String)>,
+    anonymous: Vec<i64>,
+    alerts: Vec<i64>,
+    prefs: Vec<(i64, GamePrefs)>,
+    status_messages: Vec<(i64, i32)>,
+    scoreboard_messages: Vec<(i64, i32)>,
+    welcomed: Vec<i64>,
+    disabled_commands: Vec<(i64, Vec<String>)>,
+}
+
+impl SettingsBackup {
+    /// Checks that the languages are supported and that `/rename` would have taken the
+    /// names.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if !self.locales.iter().all(|(_, lang)| i18n::supported(lang) == Some(lang.as_str())) {
+            return Err("it has a language this bot doesn't speak");
+        }
+        if !self.names.iter().all(|(_, name)| clean_name(name).ok().flatten().as_ref() == Some(name)) {
+            return Err("it has a name /rename wouldn't take");
+        }
+        Ok(())
+    }
+}
+
+impl Settings {
+    pub fn backup(&self) -> SettingsBackup {
+        SettingsBackup {
+            locales: self.locales.iter().map(|(&user_id, &lang)| (user_id, lang.to_owned())).collect(),
+            names: self.names.iter().map(|(&user_id, name)| (user_id, name.clone())).collect(),
+            anonymous: self.anonymous.iter().copied().collect(),
+            alerts: self.alerts.iter().copied().collect(),
+            prefs: self.prefs.iter().map(|(&chat_id, &prefs)| (chat_id, prefs)).collect(),
+            status_messages: self.status_messages.iter().map(|(&chat_id, &id)| (chat_id, id)).collect(),
+            scoreboard_messages: self.scoreboard_messages.iter().map(|(&chat_id, &id)| (chat_id, id)).collect(),
+            welcomed: self.welcomed.iter().copied().collect(),
+            disabled_commands: self
+                .disabled_commands
+                .iter()
+                .map(|(&chat_id, commands)| (chat_id, commands.iter().cloned().collect()))
+                .collect(),
+        }
+    }
+
+    /// Replaces every setting with those of `backup`, which passed
+    /// [`SettingsBackup::validate`]. Languages this bot doesn't speak are dropped.
+    pub fn restore(&mut self, backup: SettingsBackup) {
+        *self = Settings {
+            locales: backup.locales.iter().filter_map(|(id, lang)| Some((*id, i18n::supported(lang)?))).collect(),
+            names: backup.names.into_iter().collect(),
+            anonymous: backup.anonymous.into_iter().collect(),
+            alerts: backup.alerts.into_iter().collect(),
+            prefs: backup.prefs.into_iter().collect(),
+            status_messages: backup.status_messages.into_iter().collect(),
+            scoreboard_messages: backup.scoreboard_messages.into_iter().collect(),
+            welcomed: backup.welcomed.into_iter().collect(),
+            disabled_commands: backup
+                .disabled_commands
+                .into_iter()
+                .filter(|(_, commands)| !commands.is_empty())
+                .map(|(chat_id, commands)| (chat_id, commands.into_iter().collect()))
+                .collect(),
+        };
+    }
+
+    /// The language the user picked with `/lang`, if any.
+    pub fn locale(&self, user_id: i64) -> Option<&'static str> {
+        self.locales.get(&user_id).copied()
+    }
+
+    pub fn set_locale(&mut self, user_id: i64, lang: &'static str) {
+        self.locales.insert(user_id, lang);
+    }
+
+    pub fn name(&self, user_id: i64) -> Option<&str> {
+        self.names.get(&user_id).map(String::as_str)
+    }
+
+    /// Sets the name the user plays under; `None` goes back to their Telegram name.
+    pub fn set_name(&mut self, user_id: i64, name: Option<String>) {
+        match name {
+            Some(name) => self.names.insert(user_id, name),
+            None => self.names.remove(&user_id),
+        };
+    }
+
+    pub fn is_anonymous(&self, user_id: i64) -> bool {
+        self.anonymous.contains(&user_id)
+    }
+
+    pub fn set_anonymous(&mut self, user_id: i64, anonymous: bool) {
+        if anonymous {
+            self.anonymous.insert(user_id);
+        } else {
+            self.anonymous.remove(&user_id);
+        }
+    }
+
+    pub fn wants_alerts(&self, user_id: i64) -> bool {
+        self.alerts.contains(&user_id)
+    }
+
+    pub fn set_alerts(&mut self, user_id: i64, alerts: bool) {
+        if alerts {
+            self.alerts.insert(user_id);
+        } else {
+            self.alerts.remove(&user_id);
+        }
+    }
+
+    pub fn prefs(&self, chat_id: i64) -> GamePrefs {
+        self.prefs.get(&chat_id).copied().unwrap_or_default()
+    }
+
+    pub fn prefs_mut(&mut self, chat_id: i64) -> &mut GamePrefs {
+        self.prefs.entry(chat_id).or_default()
+    }
+
+    pub fn status_message(&self, chat_id: i64) -> Option<i32> {
+        self.status_messages.get(&chat_id).copied()
+    }
+
+    pub fn set_status_message(&mut self, chat_id: i64, message_id: i32) {
+        self.status_messages.insert(chat_id, message_id);
+    }
+
+    /// Whether this is the first message of the chat since the bot started, remembering
+    /// that it isn't anymore.
+    pub fn first_contact(&mut self, chat_id: i64) -> bool {
+        self.welcomed.insert(chat_id)
+    }
+
+    pub fn forget_status_message(&mut self, chat_id: i64) {
+        self.status_messages.remove(&chat_id);
+    }
+
+    pub fn is_disabled(&self, chat_id: i64, command: &str) -> bool {
+        self.disabled_commands.get(&chat_id).is_some_and(|commands| commands.contains(command))
+    }
+
+    /// Turns `command` off or on again in `chat_id`. Returns whether that changed anything.
+    pub fn set_disabled(&mut self, chat_id: i64, command: &str, disabled: bool) -> bool {
+        if disabled {
+            return self.disabled_commands.entry(chat_id).or_default().insert(command.to_owned());
+        }
+        let commands = match self.disabled_commands.get_mut(&chat_id) {
+            Some(commands) => commands,
+            None => return false,
+        };
+        let removed = commands.remove(command);
+        if commands.is_empty() {
+            self.disabled_commands.remove(&chat_id);
+        }
+        removed
+    }
+
+    /// The commands turned off in `chat_id`, in alphabetical order.
+    pub fn disabled_commands(&self, chat_id: i64) -> Vec<&str> {
+        self.disabled_commands.get(&chat_id).into_iter().flatten().map(String::as_str).collect()
+    }
+
+    pub fn scoreboard_message(&self, chat_id: i64) -> Option<i32> {
+        self.scoreboard_messages.get(&chat_id).copied()
+    }
+
+    pub fn set_scoreboard_message(&mut self, chat_id: i64, message_id: i32) {
+        self.scoreboard_messages.insert(chat_id, message_id);
+    }
+}
+
+/// How a chat likes its games and replies, changed with the `/settings` panel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct GamePrefs {
+    /// Ask before a move is made.
+    pub confirm_moves: bool,
+    /// Point out winning and blocking cells.
+    pub hints: bool,
+    /// Seconds a player has for a move before forfeiting the game.
+    pub move_timer: Option<u64>,
+    /// Seconds after which the player to move is reminded that it's their turn.
+    pub nudge_timer: Option<u64>,
+    /// The board size of new battles.
+    pub board_size: usize,
+    /// Edit a single message showing the number instead of replying to every operation.
+    pub edit_status: bool,
+    /// Delete the boards of expired and stopped games instead of editing them.
+    pub delete_boards: bool,
+    /// Seconds after which confirmations of number changes are deleted.
+    pub auto_delete: Option<u64>,
+    /// Accept a cell number sent as text as a move, counting from 1 row by row.
+    pub text_moves: bool,
+    /// Send boards and confirmations as replies to the command that asked for them.
+    pub reply_to_commands: bool,
+    /// List the moves of finished games, marking the best ones and the blunders.
+    pub review_moves: bool,
+    /// Keep a pinned message with the chat's standings, edited after every game.
+    pub pinned_scoreboard: bool,
+    /// Pin the board of every game while it runs.
+    pub pin_boards: bool,
+    /// Remember a number for the chat, turned off with `/numbers off` by chats that only
+    /// play.
+    pub numbers_enabled: bool,
+}
+
+impl Default for GamePrefs {
+    fn default() -> Self {
+        Self {
+            confirm_moves: false,
+            hints: false,
+            move_timer: MOVE_TIMERS[0],
+            nudge_timer: NUDGE_TIMERS[0],
+            board_size: BOARD_SIZES[0],
+            edit_status: false,
+            delete_boards: false,
+            auto_delete: AUTO_DELETE_DELAYS[0],
+            text_moves: false,
+            reply_to_commands: false,
+            review_moves: false,
+            pinned_scoreboard: false,
+            pin_boards: false,
+            numbers_enabled: true,
+        }
+    }
+}
+
+impl GamePrefs {
+    /// Flips or cycles the setting behind a panel button. Returns `false` for an unknown
+    /// `key`.
+    pub fn toggle(&mut self, key: &str) -> bool {
+        match key {
+            "confirm" => self.confirm_moves = !self.confirm_moves,
+            "hints" => self.hints = !self.hints,
+            "timer" => self.move_timer = next(MOVE_TIMERS, self.move_timer),
+            "nudge" => self.nudge_timer = next(NUDGE_TIMERS, self.nudge_timer),
+            "size" => self.board_size = next(BOARD_SIZES, self.board_size),
+            "status" => self.edit_status = !self.edit_status,
+            "delete" => self.delete_boards = !self.delete_boards,
+            "autodelete" => self.auto_delete = next(AUTO_DELETE_DELAYS, self.auto_delete),
+            "textmoves" => self.text_moves = !self.text_moves,
+            "replies" => self.reply_to_commands = !self.reply_to_commands,
+            "review" => self.review_moves = !self.review_moves,
+            "scoreboard" => self.pinned_scoreboard = !self.pinned_scoreboard,
+            "pin" => self.pin_boards = !self.pin_boards,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// The value after `current` in `values`, wrapping around.
+fn next<T: Copy + PartialEq>(values: &[T], current: T) -> T {
+    let i = values.iter().position(|&v| v == current).map_or(0, |i| i + 1);
+    values[i % values.len()]
+}
+
+pub fn on_off(value: bool) -> &'static str {
+    if value {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+/// Strips control and text direction characters from a `/rename` name, which could
+/// otherwise break the layout of boards and brackets, and trims it. `None` if nothing is
+/// left.
+pub fn clean_name(input: &str) -> Result<Option<String>, NameTooLong> {
+    let name: String = input
+        .chars()
+        .filter(|&c| !c.is_control() && !is_direction_mark(c))
+        .collect();
+    let name = name.trim();
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(NameTooLong);
+    }
+    Ok((!name.is_empty()).then(|| name.to_owned()))
+}
+
+/// The marks, embeddings and isolates that change the direction of the text after them.
+fn is_direction_mark(c: char) -> bool {
+    matches!(c, '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NameTooLong;
+
+impl std::fmt::Display for NameTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Names can be at most {} characters long", MAX_NAME_LEN)
+    }
+}
+
+impl std::error::Error for NameTooLong {}
+
+fn seconds(value: Option<u64>) -> String {
+    value.map_or_else(|| "off".to_owned(), |secs| format!("{}s", secs))
+}
+
+/// The `/settings` panel: one button per setting, showing its current value.
+pub fn settings_keyboard(prefs: &GamePrefs) -> InlineKeyboardMarkup {
+    let buttons = [
+        (format!("Confirm moves: {}", on_off(prefs.confirm_moves)), "set:confirm"),
+        (format!("Hints: {}", on_off(prefs.hints)), "set:hints"),
+        (format!("Move timer: {}", seconds(prefs.move_timer)), "set:timer"),
+        (format!("Nudge after: {}", seconds(prefs.nudge_timer)), "set:nudge"),
+        (format!("Board size: {0}×{0}", prefs.board_size), "set:size"),
+        (format!("Edit one number message: {}", on_off(prefs.edit_status)), "set:status"),
+        (format!("Delete abandoned boards: {}", on_off(prefs.delete_boards)), "set:delete"),
+        (format!("Delete confirmations after: {}", seconds(prefs.auto_delete)), "set:autodelete"),
+        (format!("Moves as cell numbers: {}", on_off(prefs.text_moves)), "set:textmoves"),
+        (format!("Reply to commands: {}", on_off(prefs.reply_to_commands)), "set:replies"),
+        (format!("Review finished games: {}", on_off(prefs.review_moves)), "set:review"),
+        (format!("Pinned scoreboard: {}", on_off(prefs.pinned_scoreboard)), "set:scoreboard"),
+        (format!("Pin running boards: {}", on_off(prefs.pin_boards)), "set:pin"),
+    ];
+    let keyboard: Vec<Vec<_>> = buttons
+        .into_iter()
+        .map(|(label, data)| vec![InlineKeyboardButton::callback(label, data.to_owned())])
+        .collect();
+
+    InlineKeyboardMarkup::new(keyboard)
+}
@@ -0,0 +1,183 @@
+use std::collections::{HashMap, VecDeque};
+
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::game::Board;
+
+/// How many positions each user can keep.
+pub const SNAPSHOT_LIMIT: usize = 10;
+/// The longest name `/save` accepts, in characters.
+pub const MAX_SNAPSHOT_NAME_LEN: usize = 32;
+
+/// Positions saved with `/save`, by user id, oldest first.
+#[derive(Debug, Default)]
+pub struct Snapshots {
+    by_user: HashMap<i64, VecDeque<Snapshot>>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Snapshot {
+    pub name: String,
+    pub board: Board,
+    /// Unix time in seconds.
+    pub saved_at: u64,
+}
+
+/// What `/backup` saves of [`Snapshots`]: every user's positions, oldest first.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct SnapshotsBackup {
+    by_user: Vec<(i64, Vec<Snapshot>)>,
+}
+
+impl SnapshotsBackup {
+    /// Checks that `/save` could have saved every position.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        for (_, saved) in &self.by_user {
+            if saved.len() > SNAPSHOT_LIMIT {
+                return Err("a user has more positions than they can save");
+            }
+            if !saved.iter().all(|s| snapshot_name(&s.name).as_ref() == Ok(&s.name) && s.board.is_consistent()) {
+                return Err("a position has a broken name or board");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotNameError {
+    Empty,
+    TooLong,
+    Whitespace,
+}
+
+impl std::fmt::Display for SnapshotNameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotNameError::Empty => write!(f, "Give the position a name, e.g. /save fork"),
+            SnapshotNameError::TooLong => {
+                write!(f, "Names can be at most {} characters long", MAX_SNAPSHOT_NAME_LEN)
+            }
+            SnapshotNameError::Whitespace => write!(f, "Names can't contain spaces"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotNameError {}
+
+/// Checks a name given to `/save` or `/load`. Names are matched in any case.
+pub fn snapshot_name(name: &str) -> Result<String, SnapshotNameError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(SnapshotNameError::Empty);
+    }
+    if name.chars().count() > MAX_SNAPSHOT_NAME_LEN {
+        return Err(SnapshotNameError::TooLong);
+    }
+    if name.contains(char::is_whitespace) {
+        return Err(SnapshotNameError::Whitespace);
+    }
+    Ok(name.to_lowercase())
+}
+
+impl Snapshots {
+    /// Saves `board` under `name`, replacing the position saved under that name before.
+    /// Once a user has [`SNAPSHOT_LIMIT`] positions, their oldest one makes room.
+    pub fn save(&mut self, user_id: i64, name: String, board: Board, now: u64) {
+        let saved = self.by_user.entry(user_id).or_default();
+        saved.retain(|saved| saved.name != name);
+        if saved.len() == SNAPSHOT_LIMIT {
+            saved.pop_front();
+        }
+        saved.push_back(Snapshot { name, board, saved_at: now });
+    }
+
+    pub fn get(&self, user_id: i64, name: &str) -> Option<&Board> {
+        self.by_user.get(&user_id)?.iter().find(|saved| saved.name == name).map(|saved| &saved.board)
+    }
+
+    /// The user's positions, oldest first.
+    pub fn list(&self, user_id: i64) -> impl Iterator<Item = &Snapshot> {
+        self.by_user.get(&user_id).into_iter().flatten()
+    }
+
+    /// Removes the position saved under `name`. Returns whether there was one.
+    pub fn remove(&mut self, user_id: i64, name: &str) -> bool {
+        let saved = match self.by_user.get_mut(&user_id) {
+            Some(saved) => saved,
+            None => return false,
+        };
+        let before = saved.len();
+        saved.retain(|saved| saved.name != name);
+        let removed = saved.len() < before;
+        if saved.is_empty() {
+            self.by_user.remove(&user_id);
+        }
+        removed
+    }
+
+    pub fn backup(&self) -> SnapshotsBackup {
+        let by_user = self.by_user.iter().map(|(&user_id, saved)| (user_id, saved.iter().cloned().collect())).collect();
+        SnapshotsBackup { by_user }
+    }
+
+    /// Replaces every saved position with those of `backup`.
+    pub fn restore(&mut self, backup: SnapshotsBackup) {
+        self.by_user = backup
+            .by_user
+            .into_iter()
+            .filter(|(_, saved)| !saved.is_empty())
+            .map(|(user_id, saved)| (user_id, saved.into()))
+            .collect();
+    }
+
+    /// Removes every position of the user. Returns how many there were.
+    pub fn clear(&mut self, user_id: i64) -> usize {
+        self.by_user.remove(&user_id).map_or(0, |saved| saved.len())
+    }
+}
+
+/// The `/snapshots` list, with a button per position that deletes it.
+pub fn snapshots_list(snapshots: &Snapshots, user_id: i64) -> (String, InlineKeyboardMarkup) {
+    if snapshots.list(user_id).next().is_none() {
+        return ("You have no saved positions".to_owned(), InlineKeyboardMarkup::default());
+    }
+    let mut text = String::from("Your positions:");
+    let mut keyboard = Vec::new();
+    for saved in snapshots.list(user_id) {
+        text.push_str(&format!("\n{}: {} (saved {})", saved.name, saved.board, date_text(saved.saved_at)));
+        let data = delete_data(&saved.name);
+        // Telegram rejects callback data longer than 64 bytes, which a name of 32
+        // characters outside ASCII can exceed; /clearsnapshots still removes those.
+        if data.len() <= 64 {
+            keyboard.push(vec![InlineKeyboardButton::callback(format!("Delete {}", saved.name), data)]);
+        }
+    }
+    (text, InlineKeyboardMarkup::new(keyboard))
+}
+
+pub fn delete_data(name: &str) -> String {
+    format!("snapdel:{}", name)
+}
+
+pub fn parse_delete_data(data: &str) -> Option<&str> {
+    data.strip_prefix("snapdel:")
+}
+
+/// A Unix time as a UTC date and time, e.g. `2024-03-01 14:05 UTC`.
+pub fn date_text(secs: u64) -> String {
+    // Converts days since 1970-01-01 to a date of the proleptic Gregorian calendar, by
+    // counting in eras of 400 years that start on March 1st.
+    let days = secs / 86_400;
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    let minutes = secs % 86_400 / 60;
+    format!("{}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, minutes / 60, minutes % 60)
+}
@@ -0,0 +1,362 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::game::{Game, Line, Outcome, Player, WinCondition};
+
+/// The most games waiting for the replay channel; older ones are dropped first.
+const FEED_BACKLOG: usize = 50;
+
+/// Game results of a single user.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Record {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl Record {
+    fn total(records: impl Iterator<Item = Record>) -> Record {
+        records.fold(Record::default(), |total, record| Record {
+            wins: total.wins + record.wins,
+            losses: total.losses + record.losses,
+            draws: total.draws + record.draws,
+        })
+    }
+}
+
+/// The kinds of games results are kept apart for, see `/stats`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum GameType {
+    TicTacToe,
+    Misere,
+    Party,
+}
+
+impl GameType {
+    pub const ALL: [GameType; 3] = [GameType::TicTacToe, GameType::Misere, GameType::Party];
+
+    /// A battle's type, by what completing a line means in it.
+    pub fn of_battle(condition: WinCondition) -> Self {
+        match condition {
+            WinCondition::Normal => GameType::TicTacToe,
+            WinCondition::Misere => GameType::Misere,
+        }
+    }
+
+    /// The name `/stats` takes.
+    pub fn name(self) -> &'static str {
+        match self {
+            GameType::TicTacToe => "tictactoe",
+            GameType::Misere => "misere",
+            GameType::Party => "party",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|game_type| game_type.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            GameType::TicTacToe => "Tic-tac-toe",
+            GameType::Misere => "Misère",
+            GameType::Party => "Party",
+        }
+    }
+}
+
+/// Per-user game results, kept in memory for the lifetime of the bot.
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// By user id and game type.
+    records: HashMap<(i64, GameType), Record>,
+    past_seasons: Vec<HashMap<(i64, GameType), Record>>,
+    /// How often each line ended a game, by board size. Kept across seasons.
+    winning_lines: BTreeMap<usize, BTreeMap<Line, u32>>,
+    /// The games that ended, of every type. Kept across seasons.
+    totals: HashMap<GameType, Totals>,
+    /// How many `/puzzle`s each user solved. Kept across seasons.
+    puzzle_scores: HashMap<i64, u32>,
+    /// The results of the games played in each chat, by chat id and then user id, for
+    /// the chat's scoreboard.
+    chat_standings: HashMap<i64, HashMap<i64, Standing>>,
+    /// The last game that ended in each chat, for `/replay`.
+    last_games: HashMap<i64, Game>,
+    /// Finished games waiting to be posted to the replay channel, `None` without one.
+    /// See `feed.rs`.
+    feed: Option<VecDeque<FeedPost>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FeedPost {
+    pub game: Game,
+    pub outcome: Outcome,
+}
+
+/// How many games of a type ended, for `/globalstats`, whoever played them.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Totals {
+    pub games: u32,
+    pub moves: u64,
+}
+
+/// A player's results in one chat, with the name they last played under.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Standing {
+    pub name: String,
+    pub record: Record,
+}
+
+/// What `/backup` saves of [`Stats`]: everything but the games waiting for the replay
+/// channel. Maps are lists of entries, since JSON objects only take string keys.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct StatsBackup {
+    records: Vec<(i64, GameType, Record)>,
+    past_seasons: Vec<Vec<(i64, GameType, Record)>>,
+    winning_lines: Vec<(usize, Line, u32)>,
+    totals: Vec<(GameType, Totals)>,
+    puzzle_scores: Vec<(i64, u32)>,
+    /// By chat id, then user id.
+    chat_standings: Vec<(i64, i64, Standing)>,
+    last_games: Vec<(i64, Game)>,
+}
+
+impl StatsBackup {
+    /// Checks that the games can be replayed.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let playable = |game: &Game| {
+            game.board.is_consistent() && game.moves.iter().all(|&cell| cell < game.board.cells().len())
+        };
+        if !self.last_games.iter().all(|(_, game)| playable(game)) {
+            return Err("one of the last games has a broken board");
+        }
+        Ok(())
+    }
+}
+
+fn record_entries(records: &HashMap<(i64, GameType), Record>) -> Vec<(i64, GameType, Record)> {
+    records.iter().map(|(&(user_id, game_type), &record)| (user_id, game_type, record)).collect()
+}
+
+fn records_of(entries: Vec<(i64, GameType, Record)>) -> HashMap<(i64, GameType), Record> {
+    entries.into_iter().map(|(user_id, game_type, record)| ((user_id, game_type), record)).collect()
+}
+
+impl Stats {
+    /// Stats that also queue finished games for the replay channel.
+    pub fn with_feed() -> Self {
+        Self { feed: Some(VecDeque::new()), ..Self::default() }
+    }
+
+    pub fn record_win(&mut self, game_type: GameType, winner: i64, loser: i64) {
+        self.records.entry((winner, game_type)).or_default().wins += 1;
+        self.records.entry((loser, game_type)).or_default().losses += 1;
+    }
+
+    pub fn record_draw(&mut self, game_type: GameType, a: i64, b: i64) {
+        self.records.entry((a, game_type)).or_default().draws += 1;
+        self.records.entry((b, game_type)).or_default().draws += 1;
+    }
+
+    /// Counts a party game of `moves` moves between `players`: a win for `winner` and a
+    /// loss for everybody else, or a draw for all without a winner.
+    pub fn record_party(&mut self, winner: Option<i64>, players: &[i64], moves: usize) {
+        self.count_game(GameType::Party, moves);
+        for &player in players {
+            let record = self.records.entry((player, GameType::Party)).or_default();
+            match winner {
+                Some(winner) if winner == player => record.wins += 1,
+                Some(_) => record.losses += 1,
+                None => record.draws += 1,
+            }
+        }
+    }
+
+    /// The results of `user_id` this season in games of `game_type`.
+    pub fn typed_record(&self, user_id: i64, game_type: GameType) -> Record {
+        self.records.get(&(user_id, game_type)).copied().unwrap_or_default()
+    }
+
+    /// The results of `user_id` this season, all game types together.
+    pub fn record(&self, user_id: i64) -> Record {
+        Record::total(GameType::ALL.into_iter().map(|game_type| self.typed_record(user_id, game_type)))
+    }
+
+    /// Counts a game between `winner` and `loser` in `chat_id`, or a draw if `draw`.
+    pub fn record_chat_result(&mut self, chat_id: i64, winner: &Player, loser: &Player, draw: bool) {
+        let standings = self.chat_standings.entry(chat_id).or_default();
+        for (player, won) in [(winner, true), (loser, false)] {
+            let standing = standings.entry(player.id).or_default();
+            standing.name = player.name.clone();
+            match (draw, won) {
+                (true, _) => standing.record.draws += 1,
+                (false, true) => standing.record.wins += 1,
+                (false, false) => standing.record.losses += 1,
+            }
+        }
+    }
+
+    /// The players of `chat_id` by user id, most wins first, fewer losses first among
+    /// equal wins.
+    pub fn chat_standings(&self, chat_id: i64) -> Vec<(i64, &Standing)> {
+        let mut standings: Vec<_> =
+            self.chat_standings.get(&chat_id).into_iter().flatten().map(|(&id, standing)| (id, standing)).collect();
+        standings.sort_by(|(_, a), (_, b)| {
+            b.record.wins.cmp(&a.record.wins).then(a.record.losses.cmp(&b.record.losses)).then(a.name.cmp(&b.name))
+        });
+        standings
+    }
+
+    /// Counts `game`, which just ended in `chat_id` with `outcome`, in the totals and
+    /// keeps it as the one `/replay` shows and queues it for the replay channel. Games
+    /// that ended before their first move aren't worth replaying.
+    pub fn record_last_game(&mut self, chat_id: i64, game: &Game, outcome: Outcome) {
+        self.count_game(GameType::of_battle(game.condition), game.moves.len());
+        if game.moves.is_empty() {
+            return;
+        }
+        self.last_games.insert(chat_id, game.clone());
+        if let Some(feed) = &mut self.feed {
+            if feed.len() >= FEED_BACKLOG {
+                feed.pop_front();
+            }
+            feed.push_back(FeedPost { game: game.clone(), outcome });
+        }
+    }
+
+    fn count_game(&mut self, game_type: GameType, moves: usize) {
+        let totals = self.totals.entry(game_type).or_default();
+        totals.games += 1;
+        totals.moves += moves as u64;
+    }
+
+    pub fn totals(&self, game_type: GameType) -> Totals {
+        self.totals.get(&game_type).copied().unwrap_or_default()
+    }
+
+    pub fn next_feed_post(&mut self) -> Option<FeedPost> {
+        self.feed.as_mut()?.pop_front()
+    }
+
+    pub fn last_game(&self, chat_id: i64) -> Option<&Game> {
+        self.last_games.get(&chat_id)
+    }
+
+    pub fn record_line(&mut self, board_size: usize, line: Line) {
+        *self.winning_lines.entry(board_size).or_default().entry(line).or_default() += 1;
+    }
+
+    pub fn winning_lines(&self) -> &BTreeMap<usize, BTreeMap<Line, u32>> {
+        &self.winning_lines
+    }
+
+    /// Counts a solved puzzle for `user_id` and returns how many they solved so far.
+    pub fn record_puzzle(&mut self, user_id: i64) -> u32 {
+        let score = self.puzzle_scores.entry(user_id).or_default();
+        *score += 1;
+        *score
+    }
+
+    /// Clears the records of `user_id` in the current season, leaving everybody else's
+    /// and the archived seasons alone. Returns how many games the records counted.
+    pub fn reset_user(&mut self, user_id: i64) -> u32 {
+        let mut cleared = 0;
+        self.records.retain(|&(id, _), record| {
+            if id == user_id {
+                cleared += record.wins + record.losses + record.draws;
+            }
+            id != user_id
+        });
+        cleared
+    }
+
+    pub fn backup(&self) -> StatsBackup {
+        StatsBackup {
+            records: record_entries(&self.records),
+            past_seasons: self.past_seasons.iter().map(record_entries).collect(),
+            winning_lines: self
+                .winning_lines
+                .iter()
+                .flat_map(|(&size, lines)| lines.iter().map(move |(&line, &count)| (size, line, count)))
+                .collect(),
+            totals: self.totals.iter().map(|(&game_type, &totals)| (game_type, totals)).collect(),
+            puzzle_scores: self.puzzle_scores.iter().map(|(&user_id, &score)| (user_id, score)).collect(),
+            chat_standings: self
+                .chat_standings
+                .iter()
+                .flat_map(|(&chat_id, standings)| {
+                    standings.iter().map(move |(&user_id, standing)| (chat_id, user_id, standing.clone()))
+                })
+                .collect(),
+            last_games: self.last_games.iter().map(|(&chat_id, game)| (chat_id, game.clone())).collect(),
+        }
+    }
+
+    /// Replaces everything [`Stats::backup`] saves with `backup`. The games waiting for
+    /// the replay channel stay.
+    pub fn restore(&mut self, backup: StatsBackup) {
+        self.records = records_of(backup.records);
+        self.past_seasons = backup.past_seasons.into_iter().map(records_of).collect();
+        self.winning_lines = BTreeMap::new();
+        for (size, line, count) in backup.winning_lines {
+            self.winning_lines.entry(size).or_default().insert(line, count);
+        }
+        self.totals = backup.totals.into_iter().collect();
+        self.puzzle_scores = backup.puzzle_scores.into_iter().collect();
+        self.chat_standings = HashMap::new();
+        for (chat_id, user_id, standing) in backup.chat_standings {
+            self.chat_standings.entry(chat_id).or_default().insert(user_id, standing);
+        }
+        self.last_games = backup.last_games.into_iter().collect();
+    }
+
+    /// Moves the current records into a past season and starts a fresh one.
+    /// Returns how many players' records were archived.
+    pub fn archive_season(&mut self) -> usize {
+        let records = std::mem::take(&mut self.records);
+        let count = records.keys().map(|&(user_id, _)| user_id).collect::<std::collections::HashSet<_>>().len();
+        if count > 0 {
+            self.past_seasons.push(records);
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(record: Record) -> (u32, u32, u32) {
+        (record.wins, record.losses, record.draws)
+    }
+
+    #[test]
+    fn game_types_are_counted_apart() {
+        let mut stats = Stats::default();
+        stats.record_win(GameType::TicTacToe, 1, 2);
+        stats.record_win(GameType::TicTacToe, 1, 2);
+        stats.record_win(GameType::Misere, 2, 1);
+        stats.record_draw(GameType::Misere, 1, 2);
+
+        assert_eq!(counts(stats.typed_record(1, GameType::TicTacToe)), (2, 0, 0));
+        assert_eq!(counts(stats.typed_record(1, GameType::Misere)), (0, 1, 1));
+        assert_eq!(counts(stats.typed_record(2, GameType::TicTacToe)), (0, 2, 0));
+        assert_eq!(counts(stats.typed_record(2, GameType::Misere)), (1, 0, 1));
+        assert_eq!(counts(stats.typed_record(1, GameType::Party)), (0, 0, 0));
+        assert_eq!(counts(stats.record(1)), (2, 1, 1));
+    }
+
+    #[test]
+    fn resetting_a_user_leaves_the_others_alone() {
+        let mut stats = Stats::default();
+        stats.record_win(GameType::TicTacToe, 1, 2);
+        stats.record_draw(GameType::Misere, 1, 2);
+        stats.record_win(GameType::TicTacToe, 2, 3);
+
+        assert_eq!(stats.reset_user(1), 2);
+        assert_eq!(counts(stats.record(1)), (0, 0, 0));
+        assert_eq!(counts(stats.typed_record(2, GameType::TicTacToe)), (1, 1, 0));
+        assert_eq!(counts(stats.typed_record(2, GameType::Misere)), (0, 0, 1));
+        assert_eq!(counts(stats.record(3)), (0, 1, 0));
+        assert_eq!(stats.reset_user(1), 0);
+    }
+}
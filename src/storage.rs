@@ -0,0 +1,259 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::future::BoxFuture;
+use teloxide::{
+    dispatching2::dialogue::{
+        serializer::{Bincode, Json},
+        InMemStorage, RedisStorage, RedisStorageError, Serializer, SqliteStorage, SqliteStorageError, Storage,
+    },
+    prelude2::*,
+};
+
+use crate::{config::Backend, MyHealth, MyStorage, State};
+
+type StorageResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// The chat the health check writes to. Telegram's chat ids never get this low.
+const HEALTH_CHECK_CHAT: i64 = i64::MIN;
+/// How many checks in a row have to fail before the storage is reported as down.
+const HEALTH_FAILURE_ALERT: u32 = 3;
+
+/// Opens the dialogue storage of `backend`. Redis operations fail after `timeout`.
+pub async fn open(backend: Backend, timeout: Duration) -> StorageResult<MyStorage> {
+    let storage = match backend {
+        Backend::Redis => {
+            TimeoutStorage::new(RedisStorage::open("redis://127.0.0.1:6379", Bincode).await?.erase(), timeout)
+        }
+        Backend::Sqlite => SqliteStorage::open("db.sqlite", Json).await?.erase(),
+        Backend::Memory => InMemStorage::new().erase(),
+    };
+    Ok(storage)
+}
+
+/// `state` as `backend` writes it. The memory backend keeps states as they are, so they
+/// come out as JSON.
+pub fn serialize(backend: Backend, state: &State) -> StorageResult<Vec<u8>> {
+    Ok(match backend {
+        Backend::Redis => Bincode.serialize(state)?,
+        Backend::Sqlite | Backend::Memory => Json.serialize(state)?,
+    })
+}
+
+/// Copies the state of `chat_id` from `from` to the storage of `to`, replacing what `to`
+/// had for the chat. Returns `false` if the chat has no state to copy.
+pub async fn copy_chat(from: &MyStorage, to: Backend, timeout: Duration, chat_id: i64) -> StorageResult<bool> {
+    let state = match from.clone().get_dialogue(chat_id).await? {
+        Some(state) => state,
+        None => return Ok(false),
+    };
+    open(to, timeout).await?.update_dialogue(chat_id, state).await?;
+    Ok(true)
+}
+
+/// The outcome of the last health checks, see [`spawn_health_check`].
+#[derive(Clone, Copy, Debug)]
+pub struct Health {
+    /// The last check succeeded. The storage is assumed healthy until it's first checked,
+    /// since it was opened at startup.
+    pub healthy: bool,
+    /// How long the last successful check took.
+    pub latency: Option<Duration>,
+    /// Checks failed in a row.
+    pub failures: u32,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self { healthy: true, latency: None, failures: 0 }
+    }
+}
+
+/// Every `interval`, writes, reads and removes a state of a chat that doesn't exist,
+/// and records in `health` whether that worked within `timeout` and how long it took.
+/// The task is aborted through the returned handle when the bot stops.
+pub fn spawn_health_check(
+    storage: MyStorage,
+    health: MyHealth,
+    interval: Duration,
+    timeout: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let started = std::time::Instant::now();
+            let result = match tokio::time::timeout(timeout, check(&storage)).await {
+                Ok(result) => result,
+                Err(_) => Err(Box::new(StorageTimeout { chat_id: HEALTH_CHECK_CHAT }) as _),
+            };
+            let mut health = health.lock().unwrap();
+            match result {
+                Ok(()) => {
+                    let latency = started.elapsed();
+                    log::debug!("Storage health check took {:?}", latency);
+                    if health.failures >= HEALTH_FAILURE_ALERT {
+                        log::info!("The storage responds again after {} failed checks", health.failures);
+                    }
+                    *health = Health { healthy: true, latency: Some(latency), failures: 0 };
+                }
+                Err(err) => {
+                    health.healthy = false;
+                    health.failures += 1;
+                    if health.failures == HEALTH_FAILURE_ALERT {
+                        log::error!("The storage failed {} health checks in a row: {}", health.failures, err);
+                    } else {
+                        log::warn!("Storage health check failed: {}", err);
+                    }
+                }
+            }
+        }
+    })
+}
+
+async fn check(storage: &MyStorage) -> StorageResult<()> {
+    storage.clone().update_dialogue(HEALTH_CHECK_CHAT, State::Start).await?;
+    storage.clone().get_dialogue(HEALTH_CHECK_CHAT).await?;
+    storage.clone().remove_dialogue(HEALTH_CHECK_CHAT).await
+}
+
+/// Fails storage operations that take longer than `timeout` with [`StorageTimeout`],
+/// so that a hung connection can't block a handler forever.
+pub struct TimeoutStorage {
+    inner: MyStorage,
+    timeout: Duration,
+}
+
+impl TimeoutStorage {
+    pub fn new(inner: MyStorage, timeout: Duration) -> Arc<Self> {
+        Arc::new(Self { inner, timeout })
+    }
+
+    async fn run<T>(
+        timeout: Duration,
+        chat_id: i64,
+        op: BoxFuture<'static, StorageResult<T>>,
+    ) -> StorageResult<T> {
+        match tokio::time::timeout(timeout, op).await {
+            Ok(result) => result,
+            Err(_) => Err(Box::new(StorageTimeout { chat_id })),
+        }
+    }
+}
+
+impl Storage<State> for TimeoutStorage {
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn remove_dialogue(self: Arc<Self>, chat_id: i64) -> BoxFuture<'static, StorageResult<()>> {
+        let op = self.inner.clone().remove_dialogue(chat_id);
+        Box::pin(Self::run(self.timeout, chat_id, op))
+    }
+
+    fn update_dialogue(
+        self: Arc<Self>,
+        chat_id: i64,
+        dialogue: State,
+    ) -> BoxFuture<'static, StorageResult<()>> {
+        let op = self.inner.clone().update_dialogue(chat_id, dialogue);
+        Box::pin(Self::run(self.timeout, chat_id, op))
+    }
+
+    fn get_dialogue(self: Arc<Self>, chat_id: i64) -> BoxFuture<'static, StorageResult<Option<State>>> {
+        let op = self.inner.clone().get_dialogue(chat_id);
+        Box::pin(Self::run(self.timeout, chat_id, op))
+    }
+}
+
+/// A storage operation for `chat_id` didn't finish in time.
+#[derive(Clone, Copy, Debug)]
+pub struct StorageTimeout {
+    pub chat_id: i64,
+}
+
+impl std::fmt::Display for StorageTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Storage operation for chat {} timed out", self.chat_id)
+    }
+}
+
+impl std::error::Error for StorageTimeout {}
+
+/// Resets the state of a chat whose stored state can't be deserialized, e.g. after a
+/// change of `State` the stored data doesn't fit anymore, instead of failing every
+/// update of that chat. The chat is told its state was reset.
+pub struct RecoveringStorage {
+    inner: MyStorage,
+    bot: AutoSend<Bot>,
+}
+
+impl RecoveringStorage {
+    pub fn new(inner: MyStorage, bot: AutoSend<Bot>) -> Arc<Self> {
+        Arc::new(Self { inner, bot })
+    }
+}
+
+/// Whether `err` is a stored state that doesn't deserialize, rather than a failure to
+/// reach the storage.
+fn is_corrupt_state(err: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    matches!(
+        err.downcast_ref::<SqliteStorageError<<Json as Serializer<State>>::Error>>(),
+        Some(SqliteStorageError::SerdeError(_))
+    ) || matches!(
+        err.downcast_ref::<RedisStorageError<<Bincode as Serializer<State>>::Error>>(),
+        Some(RedisStorageError::SerdeError(_))
+    )
+}
+
+impl Storage<State> for RecoveringStorage {
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn remove_dialogue(self: Arc<Self>, chat_id: i64) -> BoxFuture<'static, StorageResult<()>> {
+        self.inner.clone().remove_dialogue(chat_id)
+    }
+
+    fn update_dialogue(
+        self: Arc<Self>,
+        chat_id: i64,
+        dialogue: State,
+    ) -> BoxFuture<'static, StorageResult<()>> {
+        self.inner.clone().update_dialogue(chat_id, dialogue)
+    }
+
+    fn get_dialogue(self: Arc<Self>, chat_id: i64) -> BoxFuture<'static, StorageResult<Option<State>>> {
+        Box::pin(async move {
+            match self.inner.clone().get_dialogue(chat_id).await {
+                Err(err) if is_corrupt_state(err.as_ref()) => {
+                    log::error!("Resetting the corrupt state of chat {}: {}", chat_id, err);
+                    self.inner.clone().remove_dialogue(chat_id).await?;
+                    let text = "Your session was reset due to a data issue";
+                    if let Err(err) = self.bot.send_message(chat_id, text).await {
+                        log::warn!("Failed to tell chat {} about its reset: {}", chat_id, err);
+                    }
+                    Ok(None)
+                }
+                result => result,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn corrupt_state_is_reset() {
+        let path = std::env::temp_dir().join(format!("tg-board-game-corrupt-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_str().unwrap();
+        // A bincode blob is no JSON, so the JSON storage can't read it back.
+        SqliteStorage::open(path, Bincode).await.unwrap().update_dialogue(1, State::GotNumber(5, None)).await.unwrap();
+        let inner = SqliteStorage::open(path, Json).await.unwrap().erase();
+        // Telling the chat fails at once, nothing listens there.
+        let bot = Bot::new("0:test").set_api_url("http://127.0.0.1:9/".parse().unwrap()).auto_send();
+        let storage = RecoveringStorage::new(inner.clone(), bot);
+
+        assert_eq!(storage.get_dialogue(1).await.unwrap(), None);
+        assert_eq!(inner.get_dialogue(1).await.unwrap(), None);
+        let _ = std::fs::remove_file(path);
+    }
+}
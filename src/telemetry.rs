@@ -0,0 +1,66 @@
+//! Opt-in logging of finished games for offline AI analysis, enabled by setting
+//! `TELEMETRY_FILE`.
+//!
+//! Only the shape of a game is written: the board size, the rules, the mode, the cells
+//! played in order and the outcome. User ids, names, chat ids and timestamps are never
+//! written, so a line can't be traced back to the people who played it.
+
+use std::path::Path;
+
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    ai::{self, Difficulty},
+    game::{Game, Mark, Mode, Outcome, WinCondition},
+};
+
+/// One line of the telemetry file.
+#[derive(Debug, serde::Serialize)]
+pub struct CompletedGame {
+    pub board_size: usize,
+    pub misere: bool,
+    /// `two_player`, `pass_and_play` or `ai_easy` / `ai_medium` / `ai_hard`.
+    pub mode: &'static str,
+    pub moves: Vec<usize>,
+    /// `!` or `?` for each move, see [`ai::annotate`]. Left out for boards too big to solve.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Vec<&'static str>>,
+    /// `x`, `o` or `draw`.
+    pub outcome: &'static str,
+}
+
+impl CompletedGame {
+    pub fn new(game: &Game, outcome: Outcome) -> Self {
+        let mode = match game.mode {
+            Mode::TwoPlayer => "two_player",
+            Mode::PassAndPlay => "pass_and_play",
+            Mode::VsAi(Difficulty::Easy) => "ai_easy",
+            Mode::VsAi(Difficulty::Medium) => "ai_medium",
+            Mode::VsAi(Difficulty::Hard) => "ai_hard",
+        };
+        let outcome = match outcome {
+            Outcome::Win(Mark::X) => "x",
+            Outcome::Win(Mark::O) => "o",
+            Outcome::Draw => "draw",
+        };
+        Self {
+            board_size: game.board.size(),
+            misere: game.condition == WinCondition::Misere,
+            mode,
+            moves: game.moves.clone(),
+            annotations: ai::annotate(&game.board, &game.moves, game.condition)
+                .map(|annotations| annotations.into_iter().map(ai::Annotation::symbol).collect()),
+            outcome,
+        }
+    }
+}
+
+/// Appends `game` to the JSONL file at `path`. The line is written with a single
+/// append, so lines of games finishing at the same time don't interleave.
+pub async fn log_game_telemetry(path: &Path, game: &CompletedGame) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(game)?;
+    line.push(b'\n');
+    let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+    file.write_all(&line).await?;
+    file.flush().await
+}
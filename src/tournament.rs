@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+
+use rand::seq::SliceRandom;
+use teloxide::prelude2::*;
+
+use crate::{
+    battle::{battle_text, board_keyboard, Battle, BattleGame},
+    expiry::ActiveGame,
+    game::{Game, Mode, Player, WinCondition},
+    MyActiveGames, MyDialogue, MyTournaments, State,
+};
+
+/// Tournaments by chat id. A chat runs at most one tournament at a time.
+#[derive(Debug, Default)]
+pub struct Tournaments {
+    by_chat: HashMap<i64, Tournament>,
+}
+
+impl Tournaments {
+    pub fn get(&self, chat_id: i64) -> Option<&Tournament> {
+        self.by_chat.get(&chat_id)
+    }
+
+    pub fn get_mut(&mut self, chat_id: i64) -> Option<&mut Tournament> {
+        self.by_chat.get_mut(&chat_id)
+    }
+
+    /// Opens registration, unless a tournament is already running in the chat.
+    pub fn open(&mut self, chat_id: i64) -> bool {
+        match self.by_chat.get(&chat_id) {
+            Some(t) if t.champion().is_none() && !t.cancelled => false,
+            _ => {
+                self.by_chat.insert(chat_id, Tournament::default());
+                true
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Tournament {
+    players: Vec<Player>,
+    /// Every round's matches; empty while players are still joining.
+    rounds: Vec<Vec<Match>>,
+    cancelled: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Match {
+    pub a: Player,
+    /// `None` is a bye: `a` goes to the next round without playing.
+    pub b: Option<Player>,
+    pub winner: Option<Player>,
+    /// The battle game the match is played in.
+    pub game_id: Option<u32>,
+}
+
+/// A match to start: its position in the bracket and its players.
+#[derive(Clone, Debug)]
+pub struct Pairing {
+    pub round: usize,
+    pub index: usize,
+    pub a: Player,
+    pub b: Player,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JoinError {
+    Closed,
+    AlreadyJoined,
+}
+
+impl std::fmt::Display for JoinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinError::Closed => write!(f, "Registration is closed"),
+            JoinError::AlreadyJoined => write!(f, "You have already joined"),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+impl Tournament {
+    pub fn is_joining(&self) -> bool {
+        self.rounds.is_empty() && !self.cancelled
+    }
+
+    pub fn players(&self) -> &[Player] {
+        &self.players
+    }
+
+    pub fn join(&mut self, player: Player) -> Result<(), JoinError> {
+        if !self.is_joining() {
+            return Err(JoinError::Closed);
+        }
+        if self.players.iter().any(|p| p.id == player.id) {
+            return Err(JoinError::AlreadyJoined);
+        }
+        self.players.push(player);
+        Ok(())
+    }
+
+    pub fn cancel(&mut self) {
+        self.cancelled = true;
+    }
+
+    /// Closes registration, shuffles the players into the first round and returns the
+    /// matches to play.
+    pub fn seed(&mut self) -> Vec<Pairing> {
+        let mut players = self.players.clone();
+        players.shuffle(&mut rand::thread_rng());
+        self.push_round(players)
+    }
+
+    /// Pairs `players` into a new round. The last player gets a bye if their number is odd.
+    fn push_round(&mut self, players: Vec<Player>) -> Vec<Pairing> {
+        let round: Vec<_> = players
+            .chunks(2)
+            .map(|pair| Match {
+                a: pair[0].clone(),
+                b: pair.get(1).cloned(),
+                winner: if pair.len() == 1 { Some(pair[0].clone()) } else { None },
+                game_id: None,
+            })
+            .collect();
+        self.rounds.push(round);
+        self.pending()
+    }
+
+    /// The matches of the current round that aren't decided yet and have no game.
+    fn pending(&self) -> Vec<Pairing> {
+        let round = self.rounds.len() - 1;
+        self.rounds[round]
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.winner.is_none() && m.game_id.is_none())
+            .filter_map(|(index, m)| {
+                Some(Pairing { round, index, a: m.a.clone(), b: m.b.clone()? })
+            })
+            .collect()
+    }
+
+    pub fn set_game(&mut self, pairing: &Pairing, game_id: u32) {
+        self.rounds[pairing.round][pairing.index].game_id = Some(game_id);
+    }
+
+    /// Whether `game_id` is the game of one of the matches being played.
+    pub fn plays(&self, game_id: u32) -> bool {
+        self.rounds.iter().flatten().any(|m| m.winner.is_none() && m.game_id == Some(game_id))
+    }
+
+    /// Records the result of the match played in `game_id`: `winner` is `None` on a draw,
+    /// which is replayed. Returns the matches to start next; empty if `game_id` isn't a
+    /// match of the current round.
+    pub fn report(&mut self, game_id: u32, winner: Option<i64>) -> Vec<Pairing> {
+        let found = self.rounds.last_mut().and_then(|round| {
+            round.iter_mut().find(|m| m.winner.is_none() && m.game_id == Some(game_id))
+        });
+        let m = match found {
+            Some(m) => m,
+            None => return Vec::new(),
+        };
+        m.game_id = None;
+        m.winner = match winner {
+            Some(id) if id == m.a.id => Some(m.a.clone()),
+            Some(_) => m.b.clone(),
+            None => None,
+        };
+
+        let round = self.rounds.last().expect("found a match above");
+        if round.iter().any(|m| m.winner.is_none()) {
+            return self.pending();
+        }
+        let winners: Vec<_> = round.iter().filter_map(|m| m.winner.clone()).collect();
+        if winners.len() == 1 {
+            return Vec::new();
+        }
+        self.push_round(winners)
+    }
+
+    pub fn champion(&self) -> Option<&Player> {
+        match self.rounds.last()?.as_slice() {
+            [only] if only.b.is_some() => only.winner.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn status_text(&self) -> String {
+        if self.cancelled {
+            return "The tournament was cancelled".to_owned();
+        }
+        if self.is_joining() {
+            let names: Vec<_> = self.players.iter().map(|p| p.name.as_str()).collect();
+            return format!("Registration is open. Players: {}", names.join(", "));
+        }
+
+        let mut text = String::new();
+        for (i, round) in self.rounds.iter().enumerate() {
+            text.push_str(&format!("Round {}\n", i + 1));
+            for m in round {
+                let result = match &m.winner {
+                    Some(winner) => format!(" → {}", winner.name),
+                    None => " (playing)".to_owned(),
+                };
+                match &m.b {
+                    Some(b) => text.push_str(&format!("  {} vs {}{}\n", m.a.name, b.name, result)),
+                    None => text.push_str(&format!("  {} has a bye\n", m.a.name)),
+                }
+            }
+        }
+        if let Some(champion) = self.champion() {
+            text.push_str(&format!("\n🏆 Champion: {}", champion.name));
+        }
+        text
+    }
+}
+
+/// Reports the end of `game_id` to the chat's tournament, if it's one of its matches:
+/// announces the champion or starts the matches that can be played next.
+pub async fn report_result(
+    bot: &AutoSend<Bot>,
+    dialogue: &MyDialogue,
+    active_games: &MyActiveGames,
+    tournaments: &MyTournaments,
+    chat_id: i64,
+    game_id: u32,
+    winner: Option<i64>,
+) -> crate::HandlerResult {
+    let next = tournaments.lock().unwrap().get_mut(chat_id).map(|tournament| {
+        let pairings = tournament.report(game_id, winner);
+        (pairings, tournament.champion().map(|p| p.name.clone()))
+    });
+    if let Some((pairings, champion)) = next {
+        if let Some(champion) = champion {
+            bot.send_message(chat_id, format!("🏆 {} wins the tournament!", champion)).await?;
+        }
+        start_matches(bot, dialogue, active_games, tournaments, chat_id, pairings).await?;
+    }
+    Ok(())
+}
+
+/// Sends the boards of `pairings` to the chat and adds their games to its battle.
+pub async fn start_matches(
+    bot: &AutoSend<Bot>,
+    dialogue: &MyDialogue,
+    active_games: &MyActiveGames,
+    tournaments: &MyTournaments,
+    chat_id: i64,
+    pairings: Vec<Pairing>,
+) -> crate::HandlerResult {
+    if pairings.is_empty() {
+        return Ok(());
+    }
+
+    let mut battle = match dialogue.get().await? {
+        Some(State::Battle(battle)) => battle,
+        previous => Battle::new(previous.unwrap_or_default()),
+    };
+
+    for pairing in pairings {
+        let mut game = Game::new(3, Mode::TwoPlayer, WinCondition::Normal, crate::now())
+            .seated(pairing.a.clone(), pairing.b.clone());
+        game.repetition_limit = active_games.lock().unwrap().repetition_limit();
+        let game_id = battle.next_game_id();
+        let text = format!("Tournament, round {}\n{}", pairing.round + 1, battle_text(&game));
+        let sent = bot.send_message(chat_id, text).reply_markup(board_keyboard(game_id, &game)).await?;
+        active_games.lock().unwrap().insert(
+            chat_id,
+            game_id,
+            ActiveGame::new(sent.id, &game),
+        );
+        battle.games.insert(game_id, BattleGame { message_id: sent.id, game, pinned: false });
+        if let Some(tournament) = tournaments.lock().unwrap().get_mut(chat_id) {
+            tournament.set_game(&pairing, game_id);
+        }
+    }
+
+    dialogue.update(State::Battle(battle)).await?;
+    Ok(())
+}